@@ -1,6 +1,3 @@
-extern crate extprim;
-#[macro_use]
-extern crate extprim_literals;
 extern crate hyper;
 extern crate telegram;
 extern crate futures;
@@ -17,8 +14,8 @@ fn main() {
     // [DEBUG] Step
     println!(" * Request for (p,q) Authorization");
 
-    let req = telegram::Request::new(telegram::schema::mtproto::req_pq {
-        nonce: i128!(0x3E0549828CCA27E966B301A48FECE2FC),
+    let req = telegram::Request::new(telegram::schema::mtproto::ReqPq {
+        nonce: 0x3E0549828CCA27E966B301A48FECE2FCu128.into(),
     });
 
     // [DEBUG] Step
@@ -33,7 +30,7 @@ fn main() {
     pprint(&buffer);
 
     // [DEBUG] Step
-    println!(" - Send {}\n", "http://149.154.167.50:443/api");
+    println!(" - Send http://149.154.167.50:443/api\n");
 
     let mut core = Core::new().unwrap();
     let client = telegram::Client::new(&core.handle());
@@ -0,0 +1,103 @@
+//! Allocations and time spent deserializing a `messages.messages` reply with long messages,
+//! comparing the owned types of `schema` with the borrowing types of `schema::borrowed`.
+//!
+//! Run with `cargo bench -p telegram --bench deserialize`.
+
+extern crate telegram;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use telegram::de;
+use telegram::ser::Serialize;
+use telegram::schema;
+
+/// Counts the allocations and allocated bytes of the process.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const MESSAGES: usize = 100;
+const MESSAGE_SIZE: usize = 4096;
+const ITERATIONS: u32 = 20;
+
+fn measure<T, F: FnMut() -> T>(name: &str, mut f: F) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    let elapsed = start.elapsed() / ITERATIONS;
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / ITERATIONS as usize;
+    let allocated = (ALLOCATED.load(Ordering::Relaxed) - allocated) / ITERATIONS as usize;
+
+    println!(
+        "{:<10} {:>6} allocations {:>10} bytes allocated {:>12?} per reply",
+        name,
+        allocations,
+        allocated,
+        elapsed
+    );
+}
+
+fn main() {
+    let message = schema::Message_ {
+        flags: 0,
+        id: 1,
+        from_id: 2,
+        to_id: schema::PeerUser { user_id: 3 }.into(),
+        date: 0,
+        message: "x".repeat(MESSAGE_SIZE),
+        media: schema::MessageMedia::MessageMediaEmpty,
+    };
+
+    let reply = schema::messages::Messages::from(schema::messages::Messages_ {
+        messages: vec![message.into(); MESSAGES],
+        chats: Vec::new(),
+        users: Vec::new(),
+    });
+
+    let buffer = reply.to_vec().unwrap();
+
+    println!(
+        "messages.messages with {} messages of {} bytes",
+        MESSAGES,
+        MESSAGE_SIZE
+    );
+
+    measure("owned", || {
+        de::from_slice::<schema::messages::Messages>(&buffer).unwrap()
+    });
+
+    measure("borrowed", || {
+        de::from_slice::<schema::borrowed::messages::Messages>(&buffer).unwrap()
+    });
+}
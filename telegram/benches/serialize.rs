@@ -0,0 +1,100 @@
+//! Allocations and time spent serializing large `upload.saveFilePart` requests, comparing
+//! the streaming serializer with serializing the message into an intermediate buffer first.
+//!
+//! Run with `cargo bench -p telegram --bench serialize`.
+
+extern crate telegram;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use telegram::ser::Serialize;
+use telegram::schema::upload::SaveFilePart;
+use telegram::Request;
+
+/// Counts the allocations and allocated bytes of the process.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const PART_SIZE: usize = 512 * 1024;
+const ITERATIONS: u32 = 20;
+
+/// The former way of serializing a request: the message is serialized into a growing
+/// buffer to learn its length, then copied after the header.
+fn to_vec_buffered<T: Serialize>(message_id: u64, body: &T) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    0u64.serialize_to(&mut result).unwrap();
+    message_id.serialize_to(&mut result).unwrap();
+
+    let mut message = Vec::new();
+    body.serialize_to(&mut message).unwrap();
+
+    (message.len() as u32).serialize_to(&mut result).unwrap();
+    result.extend(message);
+
+    result
+}
+
+fn measure<F: FnMut() -> Vec<u8>>(name: &str, mut f: F) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let allocated = ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        let buffer = f();
+        assert!(buffer.len() > PART_SIZE);
+    }
+
+    let elapsed = start.elapsed() / ITERATIONS;
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / ITERATIONS as usize;
+    let allocated = (ALLOCATED.load(Ordering::Relaxed) - allocated) / ITERATIONS as usize;
+
+    println!(
+        "{:<10} {:>6} allocations {:>10} bytes allocated {:>12?} per request",
+        name,
+        allocations,
+        allocated,
+        elapsed
+    );
+}
+
+fn main() {
+    let body = SaveFilePart {
+        file_id: 0x1234_5678,
+        file_part: 0,
+        bytes: vec![0xAB; PART_SIZE].into(),
+    };
+    let request = Request::new(body.clone());
+
+    println!("upload.saveFilePart with {} bytes", PART_SIZE);
+
+    measure("buffered", || to_vec_buffered(0, &body));
+    measure("streaming", || request.to_vec().unwrap());
+}
@@ -11,4 +11,8 @@ fn main() {
 
     let dest_path = Path::new(&out_dir).join("mtproto_schema.rs");
     telegram_codegen::translate_from_json_file("schema/mtproto-schema.json", &dest_path).unwrap();
+
+    // A schema with conditional parameters, which the one above lacks, for the tests
+    let dest_path = Path::new(&out_dir).join("flags_schema.rs");
+    telegram_codegen::translate_from_json_file("tests/fixtures/flags.json", &dest_path).unwrap();
 }
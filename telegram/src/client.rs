@@ -1,40 +1,124 @@
+use std::fmt;
+use std::rc::Rc;
+use std::time::Duration;
+
 use tokio::reactor::Handle;
-use futures::{future, Stream, Future};
+use futures::{future, Future};
+use de::DeserializeOwned;
 use ser::Serialize;
-use hyper::{self, Body};
-use hyper::client::HttpConnector;
 use request::Request;
-use error;
+use rpc::RemoteCall;
+use dc::Pool;
+use messages::{self, Outbox};
+use peers::PeerCache;
+use retry::{Retry, RetryPolicy};
+use schema::DcOption;
+use session::RsaKey;
+use transport::{self, HttpTransport, Transport};
+use error::{self, ErrorKind};
 
+/// A Telegram client.
+///
+/// The client keeps a connection to each data center it talks to: the home data center
+/// of the account, and those files are stored on, each with its own authorization key
+/// and session. Clones share the same connections, so a client can be moved into the
+/// futures of the calls made with it.
+#[derive(Clone)]
 pub struct Client {
-    http_client: hyper::Client<HttpConnector, Body>,
+    pub(crate) pool: Rc<Pool>,
+    pub(crate) outbox: Rc<Outbox>,
+    pub(crate) peers: Rc<PeerCache>,
+    pub(crate) retry: Rc<Retry>,
+    pub(crate) server_keys: Rc<Vec<RsaKey>>,
+    pub(crate) update_timeout: Duration,
 }
 
 impl Client {
     /// Create a new Telegram client.
     #[inline]
     pub fn new(handle: &Handle) -> Client {
+        let handle = handle.clone();
+
+        Client::with_connector(transport::default_dc(), move |dc| {
+            Ok(HttpTransport::with_uri(&handle, transport::dc_uri(dc)?))
+        })
+    }
+
+    /// Create a client sending its requests through `transport`, to the default data
+    /// center only.
+    pub fn with_transport<T: Transport + 'static>(transport: T) -> Client {
+        let home = transport::default_dc();
+        let home_id = home.id;
+        let transport = Rc::new(transport);
+
+        Client::with_connector(home, move |dc| {
+            if dc.id != home_id {
+                bail!(ErrorKind::UnknownDc(dc.id));
+            }
+
+            Ok(transport.clone())
+        })
+    }
+
+    /// Create a client whose home data center is `home`, opening the transport to each
+    /// data center it uses with `connect`.
+    ///
+    /// The addresses of the other data centers are taken from `help.getConfig`.
+    pub fn with_connector<F, T>(home: DcOption, connect: F) -> Client
+    where
+        F: Fn(&DcOption) -> error::Result<T> + 'static,
+        T: Transport + 'static,
+    {
+        let connect = move |dc: &DcOption| -> error::Result<Rc<dyn Transport>> {
+            Ok(Rc::new(connect(dc)?))
+        };
+
         Client {
-            http_client: hyper::Client::new(handle),
+            pool: Rc::new(Pool::new(home, Box::new(connect))),
+            outbox: Rc::default(),
+            peers: Rc::default(),
+            retry: Rc::new(Retry::new(RetryPolicy::default())),
+            server_keys: Rc::new(vec![RsaKey::telegram()]),
+            update_timeout: messages::UPDATE_TIMEOUT,
         }
     }
 
-    // Send a constructed request using this Client.
+    /// Send a plaintext request to the home data center, resolving to the raw response,
+    /// e.g. to generate an authorization key by hand.
     pub fn request<T: Serialize>(
         &self,
         req: Request<T>,
-    ) -> Box<Future<Item = Vec<u8>, Error = error::Error>> {
-        let http_request = match req.to_http_request() {
-            Ok(req) => req,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = error::Error>> {
+        let message = match req.to_vec() {
+            Ok(message) => message,
             Err(error) => return Box::new(future::err(error)),
         };
 
         Box::new(
-            self.http_client
-                .request(http_request)
-                .and_then(|res| res.body().concat2())
-                .map(|data| data.to_vec())
-                .map_err(|err| err.into())
+            self.connection(self.home_dc())
+                .and_then(move |connection| connection.transport().send(message)),
         )
     }
+
+    /// Invoke a method on the home data center, resolving to its result, or to
+    /// `ErrorKind::Rpc` if the server replied with an error.
+    ///
+    /// The method is invoked again where the server redirects to with a 303 error, e.g.
+    /// `PHONE_MIGRATE_4` makes data center 4 the home one, and as the retry policy of the
+    /// client says after flood waits and failures.
+    pub fn invoke<R>(&self, query: R) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+    where
+        R: RemoteCall + Clone + 'static,
+        R::Reply: DeserializeOwned + 'static,
+    {
+        self.invoke_on(self.home_dc(), query)
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("home_dc", &self.home_dc())
+            .finish()
+    }
 }
@@ -0,0 +1,246 @@
+//! The primitives of MTProto and of end-to-end encryption: SHA-1, AES-256 in IGE mode,
+//! the keys of messages and Diffie-Hellman.
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes256;
+use num_bigint::BigUint;
+use rand::{self, RngCore};
+use sha1::{Digest, Sha1};
+
+use error;
+
+/// Size of the DH prime and keys, in bytes.
+pub(crate) const KEY_SIZE: usize = 256;
+
+/// The prime Telegram sends for Diffie-Hellman, in the generation of authorization keys
+/// and in the configurations of secret chats, known to be a safe prime.
+const KNOWN_PRIME: &[u8] = b"\
+    C71CAEB9C6B1C9048E6C522F70F13F73980D40238E3E21C14934D037563D930F48198A0AA7C14058229493D2\
+    2530F4DBFA336F6E0AC925139543AED44CCE7C3720FD51F69458705AC68CD4FE6B6B13ABDC9746512969328\
+    454F18FAF8C595F642477FE96BB2A941D5BCD1D4AC8CC49880708FA9B378E3C4F3A9060BEE67CF9A4A4A695\
+    811051907E162753B56B0F6B410DBA74D8A84B2A14B3144E0EF1284754FD17ED950D5965B4B9DD46582DB11\
+    78D169C6BC465B0D6FF9CA3928FEF5B9AE4E418FC15E83EBEA0F87FA9FF5EED70050DED2849F47BF959D956\
+    850CE929851F0D8115F635B105EE2E4E15D04B2454BF6F4FADF034B10403119CD8E3B92FCC5B";
+
+/// Number of Miller-Rabin rounds a prime which isn't the known one goes through.
+const PRIMALITY_ROUNDS: usize = 32;
+
+/// `sha1` of the concatenation of `parts`.
+pub(crate) fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+
+    hasher.finalize().into()
+}
+
+/// The AES key and IV of a message, from the shared key and the message key (MTProto 1.0):
+/// `x` is 0 for the messages of the client, 8 for those of the server.
+pub(crate) fn aes_key_iv(key: &[u8], msg_key: &[u8], x: usize) -> ([u8; 32], [u8; 32]) {
+    let a = sha1(&[msg_key, &key[x..x + 32]]);
+    let b = sha1(&[&key[x + 32..x + 48], msg_key, &key[x + 48..x + 64]]);
+    let c = sha1(&[&key[x + 64..x + 96], msg_key]);
+    let d = sha1(&[msg_key, &key[x + 96..x + 128]]);
+
+    let mut aes_key = [0; 32];
+    aes_key[..8].copy_from_slice(&a[..8]);
+    aes_key[8..20].copy_from_slice(&b[8..20]);
+    aes_key[20..].copy_from_slice(&c[4..16]);
+
+    let mut aes_iv = [0; 32];
+    aes_iv[..12].copy_from_slice(&a[8..20]);
+    aes_iv[12..20].copy_from_slice(&b[..8]);
+    aes_iv[20..24].copy_from_slice(&c[16..20]);
+    aes_iv[24..].copy_from_slice(&d[..8]);
+
+    (aes_key, aes_iv)
+}
+
+/// AES-256 in Infinite Garble Extension mode, which chains each block with both the
+/// previous plaintext and ciphertext blocks.
+///
+/// The chaining carries over from one call to the next, so that data can go through it
+/// in chunks (of whole blocks).
+pub(crate) struct Ige {
+    cipher: Aes256,
+
+    /// The previous ciphertext and plaintext blocks, the two halves of the IV at first.
+    previous_cipher: [u8; 16],
+    previous_plain: [u8; 16],
+}
+
+impl Ige {
+    pub(crate) fn new(key: &[u8; 32], iv: &[u8; 32]) -> Ige {
+        let mut previous_cipher = [0; 16];
+        let mut previous_plain = [0; 16];
+        previous_cipher.copy_from_slice(&iv[..16]);
+        previous_plain.copy_from_slice(&iv[16..]);
+
+        Ige {
+            cipher: Aes256::new(GenericArray::from_slice(key)),
+            previous_cipher,
+            previous_plain,
+        }
+    }
+
+    /// Encrypt `data` in place; its length must be a multiple of 16.
+    pub(crate) fn encrypt(&mut self, data: &mut [u8]) {
+        assert_eq!(data.len() % 16, 0, "IGE works on whole blocks");
+
+        for chunk in data.chunks_mut(16) {
+            let mut plain = [0; 16];
+            plain.copy_from_slice(chunk);
+
+            let block = GenericArray::from_mut_slice(chunk);
+            xor(block, &self.previous_cipher);
+            self.cipher.encrypt_block(block);
+            xor(block, &self.previous_plain);
+
+            self.previous_cipher.copy_from_slice(block);
+            self.previous_plain = plain;
+        }
+    }
+
+    /// Decrypt `data` in place; its length must be a multiple of 16.
+    pub(crate) fn decrypt(&mut self, data: &mut [u8]) {
+        assert_eq!(data.len() % 16, 0, "IGE works on whole blocks");
+
+        for chunk in data.chunks_mut(16) {
+            let mut encrypted = [0; 16];
+            encrypted.copy_from_slice(chunk);
+
+            let block = GenericArray::from_mut_slice(chunk);
+            xor(block, &self.previous_plain);
+            self.cipher.decrypt_block(block);
+            xor(block, &self.previous_cipher);
+
+            self.previous_plain.copy_from_slice(block);
+            self.previous_cipher = encrypted;
+        }
+    }
+}
+
+fn xor(block: &mut [u8], with: &[u8; 16]) {
+    for (byte, with) in block.iter_mut().zip(with) {
+        *byte ^= with;
+    }
+}
+
+/// A Diffie-Hellman group whose prime and generator were checked.
+pub(crate) struct Dh {
+    g: BigUint,
+    p: BigUint,
+}
+
+impl Dh {
+    pub(crate) fn new(g: i32, p: &[u8]) -> error::Result<Dh> {
+        let p = BigUint::from_bytes_be(p);
+
+        if p.bits() != 8 * KEY_SIZE as u64 {
+            bail!("the DH prime isn't of 2048 bits");
+        }
+
+        // `g` generates the subgroup of order `(p - 1) / 2`
+        let generates = match g {
+            2 => &p % 8u32 == BigUint::from(7u32),
+            3 => &p % 3u32 == BigUint::from(2u32),
+            4 => true,
+            5 => [1u32, 4].contains(&residue(&p, 5)),
+            6 => [19u32, 23].contains(&residue(&p, 24)),
+            7 => [3u32, 5, 6].contains(&residue(&p, 7)),
+            _ => false,
+        };
+
+        if !generates {
+            bail!("the DH generator {} doesn't suit the prime", g);
+        }
+
+        let known = BigUint::parse_bytes(KNOWN_PRIME, 16).unwrap();
+        if p != known && !(is_probable_prime(&p) && is_probable_prime(&(&p >> 1))) {
+            bail!("the DH prime isn't a safe prime");
+        }
+
+        Ok(Dh {
+            g: BigUint::from(g as u32),
+            p,
+        })
+    }
+
+    /// `g ^ exponent mod p`, as bytes.
+    pub(crate) fn power(&self, exponent: &[u8]) -> Vec<u8> {
+        let power = self.g.modpow(&BigUint::from_bytes_be(exponent), &self.p);
+        padded(&power)
+    }
+
+    /// The key shared with the side which sent `g_x`, with this side's `exponent`.
+    pub(crate) fn shared_key(&self, g_x: &[u8], exponent: &[u8]) -> error::Result<Vec<u8>> {
+        let g_x = BigUint::from_bytes_be(g_x);
+
+        // Both `g_x` and `p - g_x` are at least 2^1984, so that neither is small
+        let bound = BigUint::from(1u32) << (8 * KEY_SIZE - 64);
+        if g_x < bound || g_x > &self.p - &bound {
+            bail!("the DH value of the other side is out of range");
+        }
+
+        let key = g_x.modpow(&BigUint::from_bytes_be(exponent), &self.p);
+        Ok(padded(&key))
+    }
+}
+
+/// A big-endian number, left-padded to the size of the keys.
+fn padded(number: &BigUint) -> Vec<u8> {
+    let bytes = number.to_bytes_be();
+    let mut padded = vec![0; KEY_SIZE.saturating_sub(bytes.len())];
+    padded.extend(bytes);
+    padded
+}
+
+fn residue(number: &BigUint, modulus: u32) -> u32 {
+    (number % modulus).to_u32_digits().first().cloned().unwrap_or(0)
+}
+
+/// Miller-Rabin test of an odd number.
+fn is_probable_prime(n: &BigUint) -> bool {
+    let one = BigUint::from(1u32);
+    let two = BigUint::from(2u32);
+
+    if n < &BigUint::from(5u32) || !n.bit(0) {
+        return n == &two || n == &BigUint::from(3u32);
+    }
+
+    let n_1 = n - &one;
+    let s = n_1.trailing_zeros().unwrap_or(0);
+    let d = &n_1 >> s;
+
+    let mut rng = rand::thread_rng();
+
+    'rounds: for _ in 0..PRIMALITY_ROUNDS {
+        let mut bytes = vec![0; (n.bits() as usize).div_ceil(8)];
+        rng.fill_bytes(&mut bytes);
+        let a = BigUint::from_bytes_be(&bytes) % (n - 3u32) + &two;
+
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n_1 {
+            continue;
+        }
+
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_1 {
+                continue 'rounds;
+            }
+        }
+
+        return false;
+    }
+
+    true
+}
+
+pub(crate) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
@@ -0,0 +1,440 @@
+//! The connections to the data centers, and the authorization of the account on each of
+//! them.
+//!
+//! An account lives on a home data center, where it logged in. The other data centers,
+//! which hold files or which the account is migrated to, only accept it once the home
+//! data center exported its authorization (`auth.exportAuthorization`) and they imported
+//! it (`auth.importAuthorization`). The client does that the first time it uses them.
+//!
+//! Each connection generates its own authorization key with its data center, the first
+//! time a method is invoked on it, and invokes methods in a session under that key.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::rc::{Rc, Weak};
+
+use futures::future::{self, Shared};
+use futures::Future;
+use hyper;
+
+use de::{Deserialize, DeserializeOwned, Deserializer};
+use error::{self, ErrorKind};
+use handshake;
+use rpc::{self, RemoteCall};
+use schema::{self, auth, help, upload, DcOption};
+use session::Session;
+use transport::Transport;
+use Client;
+
+type Connect = Box<dyn Fn(&DcOption) -> error::Result<Rc<dyn Transport>>>;
+
+type Authorization = Shared<Box<dyn Future<Item = (), Error = error::Error>>>;
+
+type Handshake = Shared<Box<dyn Future<Item = Rc<Session>, Error = error::Error>>>;
+
+/// The connections of a client, by data center.
+pub(crate) struct Pool {
+    connect: Connect,
+    home_dc: Cell<i32>,
+    dc_options: RefCell<HashMap<i32, DcOption>>,
+    connections: RefCell<HashMap<i32, Rc<Connection>>>,
+}
+
+/// A connection to a data center: the transport its requests are sent through, the
+/// session they are encrypted in, and the import of the authorization of the home data
+/// center.
+pub(crate) struct Connection {
+    transport: Rc<dyn Transport>,
+
+    /// The generation of the authorization key of the session, once started.
+    session: RefCell<Option<Handshake>>,
+
+    /// The import of the authorization of the home data center, once started.
+    authorization: RefCell<Option<Authorization>>,
+}
+
+impl Pool {
+    pub(crate) fn new(home: DcOption, connect: Connect) -> Pool {
+        let home_dc = home.id;
+        let mut dc_options = HashMap::new();
+        dc_options.insert(home_dc, home);
+
+        Pool {
+            connect,
+            home_dc: Cell::new(home_dc),
+            dc_options: RefCell::new(dc_options),
+            connections: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Open a connection to a data center whose address is known.
+    fn connect(&self, dc_id: i32) -> error::Result<Rc<Connection>> {
+        if let Some(connection) = self.connections.borrow().get(&dc_id) {
+            return Ok(connection.clone());
+        }
+
+        let transport = match self.dc_options.borrow().get(&dc_id) {
+            Some(dc) => (self.connect)(dc)?,
+            None => bail!(ErrorKind::UnknownDc(dc_id)),
+        };
+
+        let connection = Rc::new(Connection {
+            transport,
+            session: RefCell::new(None),
+            authorization: RefCell::new(None),
+        });
+
+        self.connections
+            .borrow_mut()
+            .insert(dc_id, connection.clone());
+
+        Ok(connection)
+    }
+}
+
+impl Connection {
+    pub(crate) fn transport(&self) -> &Rc<dyn Transport> {
+        &self.transport
+    }
+
+    /// The session with the data center, generating its authorization key once.
+    fn session(
+        self: &Rc<Self>,
+        client: &Client,
+    ) -> Box<dyn Future<Item = Rc<Session>, Error = error::Error>> {
+        let session = self.session.borrow().clone();
+        let session = match session {
+            Some(session) => session,
+            None => {
+                let failed = Rc::downgrade(self);
+
+                let generate: Box<dyn Future<Item = Rc<Session>, Error = error::Error>> =
+                    Box::new(
+                        handshake::generate(self.transport.clone(), client.server_keys.clone())
+                            .map(Rc::new)
+                            .map_err(move |error| {
+                                // Generate another key the next time the data center is used
+                                if let Some(connection) = Weak::upgrade(&failed) {
+                                    connection.session.borrow_mut().take();
+                                }
+
+                                error
+                            }),
+                    );
+
+                let session = generate.shared();
+                *self.session.borrow_mut() = Some(session.clone());
+
+                session
+            }
+        };
+
+        Box::new(
+            session
+                .map(|session| (*session).clone())
+                .map_err(|error| shared_error(&error)),
+        )
+    }
+
+    /// Invoke a method in the session, adding the peers of its result to the cache of
+    /// `client`.
+    fn send<R>(
+        self: &Rc<Self>,
+        query: R,
+        client: &Client,
+    ) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+    where
+        R: RemoteCall,
+        R::Reply: DeserializeOwned + 'static,
+    {
+        let body = match query.to_vec() {
+            Ok(body) => body,
+            Err(error) => return Box::new(future::err(error)),
+        };
+
+        let transport = self.transport.clone();
+        let peers = client.peers.clone();
+
+        Box::new(
+            self.session(client)
+                .and_then(move |session| session.invoke(transport, body))
+                .and_then(move |result| {
+                    let body = rpc::reply_body(&result)?;
+
+                    R::Reply::deserialize_from(&mut Deserializer::new(body).observing(&peers))
+                }),
+        )
+    }
+}
+
+impl Client {
+    /// The data center of the account, where methods are invoked by default.
+    pub fn home_dc(&self) -> i32 {
+        self.pool.home_dc.get()
+    }
+
+    /// Invoke a method on the data center `dc_id`, importing the authorization of the
+    /// home data center there first if it wasn't yet.
+    pub fn invoke_on<R>(
+        &self,
+        dc_id: i32,
+        query: R,
+    ) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+    where
+        R: RemoteCall + Clone + 'static,
+        R::Reply: DeserializeOwned + 'static,
+    {
+        let client = self.clone();
+
+        self.invoke_with_retries(query, move |query| client.invoke_redirected(dc_id, query))
+    }
+
+    /// Invoke a method on a data center, following the redirections to others.
+    fn invoke_redirected<R>(
+        &self,
+        dc_id: i32,
+        query: R,
+    ) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+    where
+        R: RemoteCall + Clone + 'static,
+        R::Reply: DeserializeOwned + 'static,
+    {
+        let client = self.clone();
+
+        Box::new(self.invoke_once(dc_id, query.clone()).or_else(
+            move |error| -> Box<dyn Future<Item = R::Reply, Error = error::Error>> {
+                match migration(&error) {
+                    Some(Migration::Home(dc_id)) => {
+                        // Connect while the previous home data center can still tell the
+                        // address of the new one
+                        Box::new(client.connection(dc_id).and_then(move |_| {
+                            client.pool.home_dc.set(dc_id);
+                            client.invoke_once(dc_id, query)
+                        }))
+                    }
+
+                    Some(Migration::File(dc_id)) => client.invoke_once(dc_id, query),
+                    None => Box::new(future::err(error)),
+                }
+            },
+        ))
+    }
+
+    /// Download `limit` bytes of a file from `offset`, from the data center it is stored
+    /// on.
+    pub fn get_file(
+        &self,
+        location: &schema::FileLocation,
+        offset: i32,
+        limit: i32,
+    ) -> Box<dyn Future<Item = upload::File, Error = error::Error>> {
+        let location = match *location {
+            schema::FileLocation::FileLocation(ref location) => location,
+            schema::FileLocation::FileLocationUnavailable(_) => {
+                return Box::new(future::err(ErrorKind::FileUnavailable.into()))
+            }
+        };
+
+        let query = upload::GetFile {
+            location: schema::InputFileLocation_ {
+                volume_id: location.volume_id,
+                local_id: location.local_id,
+                secret: location.secret,
+            }.into(),
+            offset,
+            limit,
+        };
+
+        self.invoke_on(location.dc_id, query)
+    }
+
+    /// Invoke a method on a data center, without following redirections.
+    fn invoke_once<R>(
+        &self,
+        dc_id: i32,
+        query: R,
+    ) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+    where
+        R: RemoteCall + 'static,
+        R::Reply: DeserializeOwned + 'static,
+    {
+        let client = self.clone();
+        let sender = self.clone();
+
+        Box::new(
+            self.connection(dc_id)
+                .and_then(move |connection| {
+                    client
+                        .authorize(dc_id, &connection)
+                        .map(move |()| connection)
+                })
+                .and_then(move |connection| connection.send(query, &sender)),
+        )
+    }
+
+    /// The connection to a data center, asking the home one for its address if it isn't
+    /// known yet.
+    pub(crate) fn connection(
+        &self,
+        dc_id: i32,
+    ) -> Box<dyn Future<Item = Rc<Connection>, Error = error::Error>> {
+        let home_dc = self.home_dc();
+
+        if dc_id == home_dc || self.pool.dc_options.borrow().contains_key(&dc_id) {
+            return Box::new(future::result(self.pool.connect(dc_id)));
+        }
+
+        let pool = self.pool.clone();
+
+        Box::new(
+            self.invoke_once(home_dc, help::GetConfig)
+                .and_then(move |config| {
+                    {
+                        let mut dc_options = pool.dc_options.borrow_mut();
+
+                        for dc in config.dc_options {
+                            dc_options.entry(dc.id).or_insert(dc);
+                        }
+                    }
+
+                    pool.connect(dc_id)
+                }),
+        )
+    }
+
+    /// Import the authorization of the home data center on the connection to another one,
+    /// once.
+    fn authorize(
+        &self,
+        dc_id: i32,
+        connection: &Rc<Connection>,
+    ) -> Box<dyn Future<Item = (), Error = error::Error>> {
+        if dc_id == self.home_dc() {
+            return Box::new(future::ok(()));
+        }
+
+        let authorization = connection.authorization.borrow().clone();
+        let authorization = match authorization {
+            Some(authorization) => authorization,
+            None => {
+                let importing = Rc::downgrade(connection);
+                let client = self.clone();
+                let failed = Rc::downgrade(connection);
+
+                let import: Box<dyn Future<Item = (), Error = error::Error>> = Box::new(
+                    self.invoke_once(self.home_dc(), auth::ExportAuthorization { dc_id })
+                        .and_then(move |exported| {
+                            let query = auth::ImportAuthorization {
+                                id: exported.id,
+                                bytes: exported.bytes,
+                            };
+
+                            match importing.upgrade() {
+                                Some(connection) => connection.send(query, &client),
+                                None => Box::new(future::err(ErrorKind::UnknownDc(dc_id).into())),
+                            }
+                        })
+                        .map(|_| ())
+                        .map_err(move |error| {
+                            // Try again the next time the data center is used
+                            if let Some(connection) = Weak::upgrade(&failed) {
+                                connection.authorization.borrow_mut().take();
+                            }
+
+                            error
+                        }),
+                );
+
+                let authorization = import.shared();
+                *connection.authorization.borrow_mut() = Some(authorization.clone());
+
+                authorization
+            }
+        };
+
+        Box::new(
+            authorization
+                .map(|_| ())
+                .map_err(|error| shared_error(&error)),
+        )
+    }
+}
+
+/// Where a 303 error redirects to.
+enum Migration {
+    /// The account lives on another data center (`PHONE_MIGRATE_X`, `USER_MIGRATE_X`,
+    /// `NETWORK_MIGRATE_X`).
+    Home(i32),
+
+    /// The file is stored on another data center (`FILE_MIGRATE_X`).
+    File(i32),
+}
+
+fn migration(error: &error::Error) -> Option<Migration> {
+    let message = match *error.kind() {
+        ErrorKind::Rpc(303, ref message) => message,
+        _ => return None,
+    };
+
+    let separator = message.rfind('_')?;
+    let dc_id = message[separator + 1..].parse().ok()?;
+
+    match &message[..separator] {
+        "PHONE_MIGRATE" | "USER_MIGRATE" | "NETWORK_MIGRATE" => Some(Migration::Home(dc_id)),
+        "FILE_MIGRATE" => Some(Migration::File(dc_id)),
+        _ => None,
+    }
+}
+
+/// A copy of the error of a shared future, for each of the futures waiting on it.
+///
+/// The kind is kept, so that e.g. the retry policy still tells transport failures apart;
+/// the errors of the transports, which can't be cloned, are copied as well as they can be.
+/// There is no catch-all arm, so that a new kind has to be copied here too.
+fn shared_error(error: &error::Error) -> error::Error {
+    let kind = match *error.kind() {
+        ErrorKind::Msg(ref message) => ErrorKind::Msg(message.clone()),
+        ErrorKind::Io(ref error) => ErrorKind::Io(io_error(error)),
+        ErrorKind::Hyper(ref error) => ErrorKind::Hyper(hyper_error(error)),
+        ErrorKind::Utf8(error) => ErrorKind::Utf8(error),
+        ErrorKind::UnexpectedEof => ErrorKind::UnexpectedEof,
+        ErrorKind::UnexpectedConstructor(expected, id) => {
+            ErrorKind::UnexpectedConstructor(expected, id)
+        }
+        ErrorKind::UnknownConstructor(id) => ErrorKind::UnknownConstructor(id),
+        ErrorKind::Rpc(code, ref message) => ErrorKind::Rpc(code, message.clone()),
+        ErrorKind::UnknownDc(dc_id) => ErrorKind::UnknownDc(dc_id),
+        ErrorKind::UnknownPeer(ref peer) => ErrorKind::UnknownPeer(peer.clone()),
+        ErrorKind::UpdateTimeout(random_id) => ErrorKind::UpdateTimeout(random_id),
+        ErrorKind::FileUnavailable => ErrorKind::FileUnavailable,
+        ErrorKind::ValueTooLong(len) => ErrorKind::ValueTooLong(len),
+        ErrorKind::InvalidTlValue(ref reason) => ErrorKind::InvalidTlValue(reason.clone()),
+        ErrorKind::InvalidLengthPrefix(prefix) => ErrorKind::InvalidLengthPrefix(prefix),
+    };
+
+    kind.into()
+}
+
+fn io_error(error: &io::Error) -> io::Error {
+    io::Error::new(error.kind(), error.to_string())
+}
+
+fn hyper_error(error: &hyper::Error) -> hyper::Error {
+    match *error {
+        hyper::Error::Method => hyper::Error::Method,
+        hyper::Error::Version => hyper::Error::Version,
+        hyper::Error::Header => hyper::Error::Header,
+        hyper::Error::TooLarge => hyper::Error::TooLarge,
+        hyper::Error::Incomplete => hyper::Error::Incomplete,
+        hyper::Error::Status => hyper::Error::Status,
+        hyper::Error::Timeout => hyper::Error::Timeout,
+        hyper::Error::Upgrade => hyper::Error::Upgrade,
+        hyper::Error::Closed => hyper::Error::Closed,
+        hyper::Error::Io(ref error) => hyper::Error::Io(io_error(error)),
+        hyper::Error::Utf8(error) => hyper::Error::Utf8(error),
+
+        // An invalid URI or a canceled request, whose errors have no public constructor
+        ref error => hyper::Error::Io(io::Error::other(error.to_string())),
+    }
+}
@@ -0,0 +1,338 @@
+use std::str;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use error::{self, ErrorKind};
+use peers::PeerCache;
+use schema;
+use {Bytes, Int128, Int256};
+
+macro_rules! impl_deserialize {
+    ($type:path, $read:path, $len:expr) => {
+        impl<'de> Deserialize<'de> for $type {
+            #[inline]
+            fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+                Ok($read(reader.read_slice($len)?))
+            }
+        }
+    };
+}
+
+/// A cursor over serialized TL data.
+///
+/// Strings and bytes can be borrowed from the input (as `&'de str` and `&'de [u8]`)
+/// instead of being copied, e.g. by the types of `schema::borrowed`.
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+    lossy: bool,
+    peers: Option<&'de PeerCache>,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(input: &'de [u8]) -> Self {
+        Deserializer {
+            input,
+            lossy: false,
+            peers: None,
+        }
+    }
+
+    /// Replace invalid UTF-8 in owned strings with U+FFFD instead of failing, as Telegram
+    /// is known to send such strings (e.g. in names).
+    ///
+    /// Borrowed `&str` can't hold the replacement and are always checked strictly.
+    pub fn lossy(mut self) -> Self {
+        self.lossy = true;
+        self
+    }
+
+    /// Add the users, chats and contacts to `peers` as they are deserialized, wherever
+    /// they are in the value.
+    pub fn observing(mut self, peers: &'de PeerCache) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// The peer cache given to `observing`, if any.
+    pub(crate) fn peers(&self) -> Option<&'de PeerCache> {
+        self.peers
+    }
+
+    /// The input which is yet to be deserialized.
+    pub fn remaining(&self) -> &'de [u8] {
+        self.input
+    }
+
+    /// Consume the next `len` bytes of the input.
+    pub fn read_slice(&mut self, len: usize) -> error::Result<&'de [u8]> {
+        if self.input.len() < len {
+            bail!(ErrorKind::UnexpectedEof);
+        }
+
+        let (slice, input) = self.input.split_at(len);
+        self.input = input;
+
+        Ok(slice)
+    }
+
+    /// Consume a `string` or `bytes` value, returning its contents without the length
+    /// and the padding.
+    pub fn read_bytes(&mut self) -> error::Result<&'de [u8]> {
+        let (header, len) = match self.read_slice(1)?[0] {
+            254 => (4, LittleEndian::read_uint(self.read_slice(3)?, 3) as usize),
+            255 => bail!(ErrorKind::InvalidLengthPrefix(255)),
+            len => (1, len as usize),
+        };
+
+        let bytes = self.read_slice(len)?;
+
+        // The overall length of the value is padded to be divisible by 4
+        self.read_slice((4 - (header + len) % 4) % 4)?;
+
+        Ok(bytes)
+    }
+}
+
+pub trait Deserialize<'de>: Sized {
+    /// Deserialize from the passed reader, borrowing from its input where possible.
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self>;
+}
+
+/// A type which doesn't borrow from the input it is deserialized from.
+pub trait DeserializeOwned: for<'de> Deserialize<'de> {}
+
+impl<T> DeserializeOwned for T where T: for<'de> Deserialize<'de> {}
+
+/// A constructor whose fields can be deserialized on their own, once its identifier
+/// was read (e.g. by the enum of its type).
+pub trait DeserializeBare<'de>: Sized {
+    /// Deserialize the fields following the constructor identifier.
+    fn deserialize_bare_from(reader: &mut Deserializer<'de>) -> error::Result<Self>;
+}
+
+/// A type whose deserialized values are passed on, being `#[tl(observed)]`: the users,
+/// chats and contacts of the schema, for the peer cache of the deserializer.
+pub trait Observed {
+    fn observed(&self, reader: &Deserializer);
+}
+
+/// A conditional field, present only when a bit of a `#` parameter is set.
+pub trait DeserializeFlag<'de>: Sized {
+    /// Deserialize from the passed reader if the bit of this field is `set`.
+    fn deserialize_flagged_from(set: bool, reader: &mut Deserializer<'de>) -> error::Result<Self>;
+}
+
+/// `flags.N?true` fields are only represented by their bit.
+impl<'de> DeserializeFlag<'de> for bool {
+    #[inline]
+    fn deserialize_flagged_from(set: bool, _reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        Ok(set)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeFlag<'de> for Option<T> {
+    #[inline]
+    fn deserialize_flagged_from(set: bool, reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        if set {
+            Ok(Some(T::deserialize_from(reader)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Deserialize a value from the start of `input`.
+pub fn from_slice<'de, T: Deserialize<'de>>(input: &'de [u8]) -> error::Result<T> {
+    T::deserialize_from(&mut Deserializer::new(input))
+}
+
+/// Deserialize a value from the start of `input`, replacing invalid UTF-8 in owned strings.
+pub fn from_slice_lossy<'de, T: Deserialize<'de>>(input: &'de [u8]) -> error::Result<T> {
+    T::deserialize_from(&mut Deserializer::new(input).lossy())
+}
+
+/// The error for the constructor `id` read where a `expected` is expected:
+/// `UnknownConstructor` if `id` isn't in the schema at all, `UnexpectedConstructor`
+/// if it belongs to another type.
+pub fn unexpected_constructor(expected: &'static str, id: u32) -> error::Error {
+    if schema::registry().get(id).is_some() {
+        ErrorKind::UnexpectedConstructor(expected, id).into()
+    } else {
+        ErrorKind::UnknownConstructor(id).into()
+    }
+}
+
+/// Conversion of a value borrowing from its input into one owning its data.
+pub trait IntoOwned {
+    type Owned;
+
+    fn into_owned(self) -> Self::Owned;
+}
+
+impl IntoOwned for &str {
+    type Owned = String;
+
+    fn into_owned(self) -> String {
+        self.to_string()
+    }
+}
+
+impl IntoOwned for &[u8] {
+    type Owned = Bytes;
+
+    fn into_owned(self) -> Bytes {
+        self.into()
+    }
+}
+
+impl<T: IntoOwned> IntoOwned for Option<T> {
+    type Owned = Option<T::Owned>;
+
+    fn into_owned(self) -> Self::Owned {
+        self.map(IntoOwned::into_owned)
+    }
+}
+
+impl<T: IntoOwned> IntoOwned for Vec<T> {
+    type Owned = Vec<T::Owned>;
+
+    fn into_owned(self) -> Self::Owned {
+        self.into_iter().map(IntoOwned::into_owned).collect()
+    }
+}
+
+impl<'de> Deserialize<'de> for bool {
+    #[inline]
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        match u32::deserialize_from(reader)? {
+            0x997275b5 => Ok(true),
+            0xbc799737 => Ok(false),
+            id => bail!(ErrorKind::UnexpectedConstructor("Bool", id)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for i8 {
+    #[inline]
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        Ok(reader.read_slice(1)?[0] as i8)
+    }
+}
+
+impl<'de> Deserialize<'de> for u8 {
+    #[inline]
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        Ok(reader.read_slice(1)?[0])
+    }
+}
+
+impl_deserialize!(i16, LittleEndian::read_i16, 2);
+impl_deserialize!(i32, LittleEndian::read_i32, 4);
+impl_deserialize!(i64, LittleEndian::read_i64, 8);
+
+impl_deserialize!(u16, LittleEndian::read_u16, 2);
+impl_deserialize!(u32, LittleEndian::read_u32, 4);
+impl_deserialize!(u64, LittleEndian::read_u64, 8);
+
+impl_deserialize!(f32, LittleEndian::read_f32, 4);
+impl_deserialize!(f64, LittleEndian::read_f64, 8);
+
+impl<'de> Deserialize<'de> for Int128 {
+    #[inline]
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(reader.read_slice(16)?);
+
+        Ok(Int128(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Int256 {
+    #[inline]
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(reader.read_slice(32)?);
+
+        Ok(Int256(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for &'de str {
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        Ok(str::from_utf8(reader.read_bytes()?)?)
+    }
+}
+
+impl<'de> Deserialize<'de> for String {
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        let bytes = reader.read_bytes()?;
+
+        if reader.lossy {
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        } else {
+            Ok(str::from_utf8(bytes)?.to_string())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for &'de [u8] {
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        reader.read_bytes()
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        reader.read_bytes().map(Bytes::from)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Vec<T> {
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        match u32::deserialize_from(reader)? {
+            0x1cb5c415 => {}
+            id => bail!(ErrorKind::UnexpectedConstructor("Vector", id)),
+        }
+
+        let len = u32::deserialize_from(reader)? as usize;
+
+        // Every element takes at least one byte, so a corrupted length can't make
+        // this allocate more than the size of the input
+        let mut elements = Vec::with_capacity(len.min(reader.remaining().len()));
+        for _ in 0..len {
+            elements.push(T::deserialize_from(reader)?);
+        }
+
+        Ok(elements)
+    }
+}
+
+/// Values without a constructor identifier are the same bare and boxed.
+macro_rules! impl_deserialize_bare {
+    ($($type:ty),*) => {
+        $(
+            impl<'de> DeserializeBare<'de> for $type {
+                #[inline]
+                fn deserialize_bare_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+                    Self::deserialize_from(reader)
+                }
+            }
+        )*
+    };
+}
+
+impl_deserialize_bare!(i32, i64, u32, f64, Int128, Int256, &'de str, String, &'de [u8], Bytes);
+
+/// A bare `vector<T>`: the length and the bare elements, without the identifier.
+impl<'de, T: DeserializeBare<'de>> DeserializeBare<'de> for Vec<T> {
+    fn deserialize_bare_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        let len = u32::deserialize_from(reader)? as usize;
+
+        let mut elements = Vec::with_capacity(len.min(reader.remaining().len()));
+        for _ in 0..len {
+            elements.push(T::deserialize_bare_from(reader)?);
+        }
+
+        Ok(elements)
+    }
+}
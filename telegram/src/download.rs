@@ -0,0 +1,347 @@
+//! Downloading files, as a stream of chunks.
+//!
+//! The chunks are requested with `upload.getFile` from the data center the file is
+//! stored on, several at a time, and come out of the stream in order.
+//!
+//! Files of secret chats are decrypted as they are downloaded, with `Download::decrypt`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use futures::stream::FuturesOrdered;
+use futures::{future, Async, Future, Poll, Stream};
+
+use crypto::Ige;
+use error;
+use schema::{self, storage, upload};
+use secret::FileKey;
+use Client;
+
+/// Size of the chunks requested, which divides 1 MB so that no chunk crosses a 1 MB
+/// boundary, as `upload.getFile` requires.
+pub const CHUNK_SIZE: usize = 128 * 1024;
+
+/// Number of chunks requested at the same time by default.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Where a file can be downloaded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadLocation {
+    /// The data center the file is stored on.
+    pub dc_id: i32,
+    pub location: schema::InputFileLocation,
+
+    /// Size of the file, if known, so that no chunk is requested past its end.
+    pub size: Option<u64>,
+}
+
+impl DownloadLocation {
+    pub fn new(dc_id: i32, location: schema::InputFileLocation) -> Self {
+        DownloadLocation {
+            dc_id,
+            location,
+            size: None,
+        }
+    }
+}
+
+impl From<schema::FileLocation_> for DownloadLocation {
+    fn from(location: schema::FileLocation_) -> Self {
+        DownloadLocation::new(
+            location.dc_id,
+            schema::InputFileLocation_ {
+                volume_id: location.volume_id,
+                local_id: location.local_id,
+                secret: location.secret,
+            }.into(),
+        )
+    }
+}
+
+impl From<schema::Document_> for DownloadLocation {
+    fn from(document: schema::Document_) -> Self {
+        DownloadLocation {
+            size: Some(document.size as u64),
+            ..DownloadLocation::new(
+                document.dc_id,
+                schema::InputDocumentFileLocation {
+                    id: document.id,
+                    access_hash: document.access_hash,
+                }.into(),
+            )
+        }
+    }
+}
+
+impl From<schema::Video_> for DownloadLocation {
+    fn from(video: schema::Video_) -> Self {
+        DownloadLocation {
+            size: Some(video.size as u64),
+            ..DownloadLocation::new(
+                video.dc_id,
+                schema::InputVideoFileLocation {
+                    id: video.id,
+                    access_hash: video.access_hash,
+                }.into(),
+            )
+        }
+    }
+}
+
+impl From<schema::Audio_> for DownloadLocation {
+    fn from(audio: schema::Audio_) -> Self {
+        DownloadLocation {
+            size: Some(audio.size as u64),
+            ..DownloadLocation::new(
+                audio.dc_id,
+                schema::InputAudioFileLocation {
+                    id: audio.id,
+                    access_hash: audio.access_hash,
+                }.into(),
+            )
+        }
+    }
+}
+
+impl From<schema::EncryptedFile_> for DownloadLocation {
+    fn from(file: schema::EncryptedFile_) -> Self {
+        DownloadLocation {
+            size: Some(file.size as u64),
+            ..DownloadLocation::new(
+                file.dc_id,
+                schema::InputEncryptedFileLocation {
+                    id: file.id,
+                    access_hash: file.access_hash,
+                }.into(),
+            )
+        }
+    }
+}
+
+/// The size and type of a downloaded file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Downloaded {
+    pub size: u64,
+    pub file_type: storage::FileType,
+}
+
+type Chunk = Box<dyn Future<Item = upload::File, Error = error::Error>>;
+
+/// A download, as a stream of the chunks of the file.
+///
+/// Nothing is requested until the stream is first polled; until then, it can be
+/// configured.
+pub struct Download {
+    client: Client,
+    location: DownloadLocation,
+    concurrency: usize,
+
+    /// Offset of the next chunk to request.
+    next_offset: u64,
+
+    /// Number of bytes to drop from the start of the first chunk, to resume from an
+    /// offset which isn't aligned.
+    skip: usize,
+
+    /// The chunks requested, all polled at once, which come out in order.
+    chunks: FuturesOrdered<Chunk>,
+    file_type: Option<storage::FileType>,
+    done: bool,
+
+    /// The cipher the chunks are decrypted with, and the number of bytes left before the
+    /// padding, for a file of a secret chat.
+    decryption: Option<(Ige, u64)>,
+
+    /// Set when the download was configured with both an offset and decryption, which
+    /// the first poll fails with.
+    conflict: bool,
+}
+
+impl Client {
+    /// Download a file, e.g. a `FileLocation_` or a `Document_`.
+    pub fn download<L: Into<DownloadLocation>>(&self, location: L) -> Download {
+        Download {
+            client: self.clone(),
+            location: location.into(),
+            concurrency: DEFAULT_CONCURRENCY,
+            next_offset: 0,
+            skip: 0,
+            chunks: FuturesOrdered::new(),
+            file_type: None,
+            done: false,
+            decryption: None,
+            conflict: false,
+        }
+    }
+}
+
+impl Download {
+    /// Start from `offset` bytes into the file, e.g. to resume a download.
+    ///
+    /// Files which are decrypted can only be downloaded from their start: the download
+    /// fails if both are asked for.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.conflict |= self.decryption.is_some() && offset != 0;
+
+        self.next_offset = offset - offset % CHUNK_SIZE as u64;
+        self.skip = (offset % CHUNK_SIZE as u64) as usize;
+        self
+    }
+
+    /// Decrypt the file with AES-256-IGE under `key` as it is downloaded, dropping the
+    /// padding past its `size` (the one in the media of the message, not the one of the
+    /// encrypted file).
+    ///
+    /// The download fails if it was also given an offset.
+    pub fn decrypt(mut self, key: &FileKey, size: u64) -> Self {
+        // Each block is chained to the previous ones, so none can be skipped
+        self.conflict |= self.next_offset != 0 || self.skip != 0;
+
+        self.decryption = Some((key.cipher(), size));
+        self
+    }
+
+    /// Number of chunks requested at the same time, 4 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Type of the file, known once its first chunk was received.
+    pub fn file_type(&self) -> Option<&storage::FileType> {
+        self.file_type.as_ref()
+    }
+
+    /// Write the rest of the file to `writer`.
+    pub fn write_to<W>(self, writer: W) -> Box<dyn Future<Item = Downloaded, Error = error::Error>>
+    where
+        W: Write + 'static,
+    {
+        Box::new(WriteTo {
+            download: self,
+            writer,
+            size: 0,
+        })
+    }
+
+    /// Write the rest of the file to the file at `path`, which is created or truncated.
+    pub fn save<P>(self, path: P) -> Box<dyn Future<Item = Downloaded, Error = error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        match File::create(path) {
+            Ok(file) => self.write_to(file),
+            Err(error) => Box::new(future::err(error.into())),
+        }
+    }
+
+    fn request(&mut self) {
+        let end = self.location.size.unwrap_or(u64::MAX);
+
+        while !self.done && self.chunks.len() < self.concurrency && self.next_offset < end {
+            let query = upload::GetFile {
+                location: self.location.location.clone(),
+                offset: self.next_offset as i32,
+                limit: CHUNK_SIZE as i32,
+            };
+
+            self.chunks
+                .push(self.client.invoke_on(self.location.dc_id, query));
+            self.next_offset += CHUNK_SIZE as u64;
+        }
+    }
+}
+
+impl Stream for Download {
+    type Item = Vec<u8>;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.conflict {
+            self.conflict = false;
+            self.done = true;
+            bail!("encrypted files are downloaded from their start, not from an offset");
+        }
+
+        loop {
+            self.request();
+
+            let file = match try_ready!(self.chunks.poll()) {
+                Some(file) => file,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            self.file_type = Some(file.type_);
+
+            let mut bytes = file.bytes.into_vec();
+
+            // A short chunk is the last one; those requested past it are dropped
+            if bytes.len() < CHUNK_SIZE {
+                self.done = true;
+                self.chunks = FuturesOrdered::new();
+            }
+
+            if let Some((ref mut cipher, ref mut left)) = self.decryption {
+                if bytes.len() % 16 != 0 {
+                    bail!("an encrypted file which isn't made of whole blocks");
+                }
+
+                cipher.decrypt(&mut bytes);
+                bytes.truncate((*left).min(bytes.len() as u64) as usize);
+                *left -= bytes.len() as u64;
+            }
+
+            if self.skip > 0 {
+                if self.skip > bytes.len() {
+                    bail!("the offset is past the end of the file");
+                }
+
+                bytes.drain(..self.skip);
+                self.skip = 0;
+            }
+
+            if !bytes.is_empty() {
+                return Ok(Async::Ready(Some(bytes)));
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Download {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Download")
+            .field("location", &self.location)
+            .field("concurrency", &self.concurrency)
+            .field("next_offset", &self.next_offset)
+            .field("file_type", &self.file_type)
+            .finish()
+    }
+}
+
+/// The future of `Download::write_to`.
+struct WriteTo<W> {
+    download: Download,
+    writer: W,
+    size: u64,
+}
+
+impl<W: Write> Future for WriteTo<W> {
+    type Item = Downloaded;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while let Some(chunk) = try_ready!(self.download.poll()) {
+            self.writer.write_all(&chunk)?;
+            self.size += chunk.len() as u64;
+        }
+
+        self.writer.flush()?;
+
+        Ok(Async::Ready(Downloaded {
+            size: self.size,
+            file_type: self.download.file_type.take().unwrap_or_default(),
+        }))
+    }
+}
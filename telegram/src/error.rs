@@ -1,6 +1,76 @@
+// error-chain 0.10 implements the deprecated `description` and `cause` methods
+#![allow(deprecated)]
+
 error_chain!{
     foreign_links {
         Io(::std::io::Error);
         Hyper(::hyper::Error);
+        Utf8(::std::str::Utf8Error);
+    }
+
+    errors {
+        UnexpectedEof {
+            description("unexpected end of input")
+        }
+
+        UnexpectedConstructor(expected: &'static str, id: u32) {
+            description("unexpected constructor")
+            display("unexpected constructor 0x{:08x} for {}", id, expected)
+        }
+
+        /// An identifier which is neither a constructor nor a method of the schema.
+        UnknownConstructor(id: u32) {
+            description("unknown constructor")
+            display("unknown constructor 0x{:08x}", id)
+        }
+
+        /// An error the server replied to a method with, e.g. 400 `PHONE_CODE_INVALID`.
+        Rpc(code: i32, message: String) {
+            description("RPC error")
+            display("RPC error {}: {}", code, message)
+        }
+
+        /// A data center whose address isn't in the configuration, or which the transports
+        /// of the client can't reach.
+        UnknownDc(id: i32) {
+            description("unknown data center")
+            display("unknown data center {}", id)
+        }
+
+        /// A user or chat whose access hash the client hasn't seen, e.g. `user 7`.
+        UnknownPeer(peer: String) {
+            description("unknown peer")
+            display("unknown peer: {}", peer)
+        }
+
+        /// A message was sent, but its update didn't arrive in time, e.g. as updates aren't
+        /// handed to `Client::handle_updates`.
+        UpdateTimeout(random_id: i64) {
+            description("no update for a sent message")
+            display("no update arrived for the message sent with random identifier {}", random_id)
+        }
+
+        /// A `fileLocationUnavailable`, e.g. the photo of a user without one.
+        FileUnavailable {
+            description("file location unavailable")
+        }
+
+        /// A string or bytes value is longer than its 3-byte length prefix can represent.
+        ValueTooLong(len: usize) {
+            description("string or bytes value too long")
+            display("string or bytes value of {} bytes exceeds the maximum of 16777215", len)
+        }
+
+        /// A `TlValue` doesn't match the schema.
+        InvalidTlValue(reason: String) {
+            description("invalid TL value")
+            display("invalid TL value: {}", reason)
+        }
+
+        /// The first byte of a string or bytes value is neither a length nor 254.
+        InvalidLengthPrefix(prefix: u8) {
+            description("invalid string or bytes length prefix")
+            display("invalid string or bytes length prefix 0x{:02x}", prefix)
+        }
     }
 }
@@ -0,0 +1,323 @@
+//! The generation of an authorization key with a data center, as described at
+//! https://core.telegram.org/mtproto/auth_key.
+//!
+//! The client asks for a number `pq` to factorize (`req_pq`), proving it did the work by
+//! sending the factors along with a secret `new_nonce`, encrypted with an RSA key of the
+//! server (`req_DH_params`). Both sides then derive a temporary AES key from the nonces,
+//! which encrypts a Diffie-Hellman exchange (`set_client_DH_params`) whose result is the
+//! authorization key.
+
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::Future;
+use rand;
+
+use crypto::{self, Dh, Ige, KEY_SIZE};
+use de::{Deserialize, DeserializeOwned, Deserializer};
+use error;
+use request::Request;
+use rpc::RemoteCall;
+use schema::mtproto::{self, PQInnerData_, ResPq, ServerDhParams, SetClientDhParamsAnswer};
+use ser::Serialize;
+use session::{AuthKey, RsaKey, Session};
+use transport::Transport;
+use {Int128, Int256};
+
+/// Number of pseudo-random sequences Pollard's rho tries before `pq` is deemed prime.
+const FACTORIZE_ATTEMPTS: u64 = 32;
+
+/// Generate an authorization key with the data center behind `transport`, starting a
+/// session with it.
+pub(crate) fn generate(
+    transport: Rc<dyn Transport>,
+    keys: Rc<Vec<RsaKey>>,
+) -> Box<dyn Future<Item = Session, Error = error::Error>> {
+    let nonce = Int128(rand::random());
+    let dh_params = transport.clone();
+    let client_dh_params = transport.clone();
+
+    Box::new(
+        invoke(&transport, mtproto::ReqPq { nonce })
+            .and_then(move |res_pq| Handshake::new(nonce, res_pq, &keys))
+            .and_then(move |(handshake, query)| {
+                invoke(&dh_params, query).map(move |params| (handshake, params))
+            })
+            .and_then(|(handshake, params)| {
+                let (query, auth_key, time_offset) = handshake.client_dh_params(params)?;
+                Ok((handshake, query, auth_key, time_offset))
+            })
+            .and_then(move |(handshake, query, auth_key, time_offset)| {
+                invoke(&client_dh_params, query).and_then(move |answer| {
+                    handshake.session(answer, auth_key, time_offset)
+                })
+            }),
+    )
+}
+
+/// Invoke a method of the handshake, in plaintext.
+fn invoke<R>(
+    transport: &Rc<dyn Transport>,
+    query: R,
+) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+where
+    R: RemoteCall,
+    R::Reply: DeserializeOwned + 'static,
+{
+    let message = match Request::new(query).to_vec() {
+        Ok(message) => message,
+        Err(error) => return Box::new(::futures::future::err(error)),
+    };
+
+    Box::new(transport.send(message).and_then(|response| {
+        let mut reader = Deserializer::new(&response);
+
+        // auth_key_id, message_id, message_length
+        if u64::deserialize_from(&mut reader)? != 0 {
+            bail!("an encrypted reply to a method generating an authorization key");
+        }
+
+        reader.read_slice(8 + 4)?;
+        R::Reply::deserialize_from(&mut reader)
+    }))
+}
+
+/// The nonces of a handshake, known once the server replied to `req_pq`.
+struct Handshake {
+    nonce: Int128,
+    server_nonce: Int128,
+
+    /// The secret of the client, sent encrypted with the RSA key of the server.
+    new_nonce: Int256,
+}
+
+impl Handshake {
+    /// Check `resPQ` and answer it with `req_DH_params`.
+    fn new(
+        nonce: Int128,
+        res_pq: ResPq,
+        keys: &[RsaKey],
+    ) -> error::Result<(Handshake, mtproto::ReqDhParams)> {
+        if res_pq.nonce != nonce {
+            bail!("the nonce of the reply to req_pq doesn't match");
+        }
+
+        let key = keys
+            .iter()
+            .find(|key| res_pq.server_public_key_fingerprints.contains(&key.fingerprint()))
+            .ok_or("the data center has none of the RSA keys of the client")?;
+
+        if res_pq.pq.len() > 8 {
+            bail!("pq is longer than 64 bits");
+        }
+
+        let (p, q) = factorize(res_pq.pq.iter().fold(0, |pq, &byte| pq << 8 | u64::from(byte)))?;
+
+        let handshake = Handshake {
+            nonce,
+            server_nonce: res_pq.server_nonce,
+            new_nonce: Int256(rand::random()),
+        };
+
+        let inner: mtproto::PQInnerData = PQInnerData_ {
+            pq: res_pq.pq,
+            p: big_endian(p).into(),
+            q: big_endian(q).into(),
+            nonce,
+            server_nonce: handshake.server_nonce,
+            new_nonce: handshake.new_nonce,
+        }.into();
+        let inner = inner.to_vec()?;
+
+        // sha1(data) + data + random padding, to 255 bytes
+        let mut data_with_hash = crypto::sha1(&[&inner]).to_vec();
+        data_with_hash.extend(&inner);
+        let padding = 255usize.saturating_sub(data_with_hash.len());
+        data_with_hash.extend(crypto::random_bytes(padding));
+
+        let query = mtproto::ReqDhParams {
+            nonce,
+            server_nonce: handshake.server_nonce,
+            p: big_endian(p).into(),
+            q: big_endian(q).into(),
+            public_key_fingerprint: key.fingerprint(),
+            encrypted_data: key.encrypt(&data_with_hash).into(),
+        };
+
+        Ok((handshake, query))
+    }
+
+    /// Read the DH parameters of the server and answer them with `set_client_DH_params`,
+    /// along with the authorization key and the offset of the time of the server.
+    fn client_dh_params(
+        &self,
+        params: ServerDhParams,
+    ) -> error::Result<(mtproto::SetClientDhParams, Vec<u8>, i64)> {
+        let params = match params {
+            ServerDhParams::ServerDhParamsOk(params) => params,
+            ServerDhParams::ServerDhParamsFail(_) => {
+                bail!("the data center failed to read the encrypted p_q_inner_data")
+            }
+        };
+
+        if params.nonce != self.nonce || params.server_nonce != self.server_nonce {
+            bail!("the nonces of the reply to req_DH_params don't match");
+        }
+
+        let mut answer = params.encrypted_answer.into_vec();
+        if answer.len() < 20 || !answer.len().is_multiple_of(16) {
+            bail!("the encrypted answer of the data center is of a wrong size");
+        }
+
+        let (aes_key, aes_iv) = self.tmp_aes_key_iv();
+        Ige::new(&aes_key, &aes_iv).decrypt(&mut answer);
+
+        // sha1(answer) + answer + padding
+        let (hash, rest) = answer.split_at(20);
+        let mut reader = Deserializer::new(rest);
+        let inner = mtproto::ServerDhInnerData::deserialize_from(&mut reader)?;
+
+        let len = rest.len() - reader.remaining().len();
+        if crypto::sha1(&[&rest[..len]]) != hash {
+            bail!("the answer of the data center failed to decrypt");
+        }
+
+        if inner.nonce != self.nonce || inner.server_nonce != self.server_nonce {
+            bail!("the nonces of server_DH_inner_data don't match");
+        }
+
+        let dh = Dh::new(inner.g, &inner.dh_prime)?;
+        let b = crypto::random_bytes(KEY_SIZE);
+        let auth_key = dh.shared_key(&inner.g_a, &b)?;
+
+        let client_inner = mtproto::ClientDhInnerData {
+            nonce: self.nonce,
+            server_nonce: self.server_nonce,
+            retry_id: 0,
+            g_b: dh.power(&b).into(),
+        }.to_vec()?;
+
+        // sha1(data) + data + random padding, to a multiple of 16 bytes
+        let mut data_with_hash = crypto::sha1(&[&client_inner]).to_vec();
+        data_with_hash.extend(&client_inner);
+        let padding = (16 - data_with_hash.len() % 16) % 16;
+        data_with_hash.extend(crypto::random_bytes(padding));
+        Ige::new(&aes_key, &aes_iv).encrypt(&mut data_with_hash);
+
+        let query = mtproto::SetClientDhParams {
+            nonce: self.nonce,
+            server_nonce: self.server_nonce,
+            encrypted_data: data_with_hash.into(),
+        };
+
+        Ok((query, auth_key, i64::from(inner.server_time) - now()))
+    }
+
+    /// Check that the server computed the same authorization key, starting a session
+    /// with it.
+    fn session(
+        &self,
+        answer: SetClientDhParamsAnswer,
+        auth_key: Vec<u8>,
+        time_offset: i64,
+    ) -> error::Result<Session> {
+        let ok = match answer {
+            SetClientDhParamsAnswer::DhGenOk(ok) => ok,
+            SetClientDhParamsAnswer::DhGenRetry(_) | SetClientDhParamsAnswer::DhGenFail(_) => {
+                bail!("the data center failed to generate the authorization key")
+            }
+        };
+
+        if ok.nonce != self.nonce || ok.server_nonce != self.server_nonce {
+            bail!("the nonces of dh_gen_ok don't match");
+        }
+
+        // The lower 128 bits of sha1(new_nonce + 1 + the higher 64 bits of sha1(auth_key))
+        let aux_hash = crypto::sha1(&[&auth_key]);
+        let new_nonce_hash = crypto::sha1(&[self.new_nonce.as_bytes(), &[1], &aux_hash[..8]]);
+        if new_nonce_hash[4..] != ok.new_nonce_hash1.as_bytes()[..] {
+            bail!("the data center generated another authorization key");
+        }
+
+        let salt = LittleEndian::read_i64(&self.new_nonce.as_bytes()[..8])
+            ^ LittleEndian::read_i64(self.server_nonce.as_bytes());
+
+        Ok(Session::new(AuthKey::new(auth_key), salt, time_offset))
+    }
+
+    /// The AES key and IV of the DH exchange, from the nonces.
+    fn tmp_aes_key_iv(&self) -> ([u8; 32], [u8; 32]) {
+        let new_nonce = self.new_nonce.as_bytes();
+        let server_nonce = self.server_nonce.as_bytes();
+
+        let new_server = crypto::sha1(&[new_nonce, server_nonce]);
+        let server_new = crypto::sha1(&[server_nonce, new_nonce]);
+        let new_new = crypto::sha1(&[new_nonce, new_nonce]);
+
+        let mut aes_key = [0; 32];
+        aes_key[..20].copy_from_slice(&new_server);
+        aes_key[20..].copy_from_slice(&server_new[..12]);
+
+        let mut aes_iv = [0; 32];
+        aes_iv[..8].copy_from_slice(&server_new[12..]);
+        aes_iv[8..28].copy_from_slice(&new_new);
+        aes_iv[28..].copy_from_slice(&new_nonce[..4]);
+
+        (aes_key, aes_iv)
+    }
+}
+
+/// The factors `p < q` of `pq`, with Pollard's rho.
+fn factorize(pq: u64) -> error::Result<(u64, u64)> {
+    if pq.is_multiple_of(2) {
+        return Ok((2, pq / 2));
+    }
+
+    let step = |x: u64, c: u64| {
+        ((u128::from(x) * u128::from(x) + u128::from(c)) % u128::from(pq)) as u64
+    };
+
+    for c in 1..FACTORIZE_ATTEMPTS {
+        let (mut x, mut y, mut divisor) = (2, 2, 1);
+
+        while divisor == 1 {
+            x = step(x, c);
+            y = step(step(y, c), c);
+            divisor = gcd(x.abs_diff(y), pq);
+        }
+
+        if divisor != pq {
+            let (p, q) = (divisor, pq / divisor);
+            return Ok((p.min(q), p.max(q)));
+        }
+    }
+
+    bail!("pq {} couldn't be factorized", pq)
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let rest = a % b;
+        a = b;
+        b = rest;
+    }
+
+    a
+}
+
+/// A number as big-endian bytes, without leading zeros.
+fn big_endian(number: u64) -> Vec<u8> {
+    let bytes = number.to_be_bytes();
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+
+    bytes[zeros..].to_vec()
+}
+
+/// The local time, in seconds.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Compare in time independent of the contents, so that comparing nonces doesn't leak
+/// how many of their leading bytes match.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn fmt_hex(f: &mut fmt::Formatter, name: &str, bytes: &[u8]) -> fmt::Result {
+    write!(f, "{}(0x", name)?;
+
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+
+    write!(f, ")")
+}
+
+/// A TL `int128`, e.g. a nonce.
+///
+/// Held as its 16 bytes in wire order. Conversions from and to integers are big-endian,
+/// so that the nonce `0x3E0549828CCA27E966B301A48FECE2FC` of the samples at
+/// https://core.telegram.org/mtproto/samples-auth_key is serialized as `3E 05 .. FC`.
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[derive(Clone, Copy, Eq, Default)]
+pub struct Int128(pub [u8; 16]);
+
+impl Int128 {
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl PartialEq for Int128 {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for Int128 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Debug for Int128 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_hex(f, "Int128", &self.0)
+    }
+}
+
+impl From<[u8; 16]> for Int128 {
+    fn from(value: [u8; 16]) -> Self {
+        Int128(value)
+    }
+}
+
+impl From<u128> for Int128 {
+    fn from(value: u128) -> Self {
+        Int128(value.to_be_bytes())
+    }
+}
+
+impl From<i128> for Int128 {
+    fn from(value: i128) -> Self {
+        Int128(value.to_be_bytes())
+    }
+}
+
+impl From<Int128> for [u8; 16] {
+    fn from(value: Int128) -> Self {
+        value.0
+    }
+}
+
+impl From<Int128> for u128 {
+    fn from(value: Int128) -> Self {
+        u128::from_be_bytes(value.0)
+    }
+}
+
+impl From<Int128> for i128 {
+    fn from(value: Int128) -> Self {
+        i128::from_be_bytes(value.0)
+    }
+}
+
+/// A TL `int256`, e.g. the `new_nonce` of the authorization key exchange.
+///
+/// Held as its 32 bytes in wire order, like `Int128`.
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[derive(Clone, Copy, Eq, Default)]
+pub struct Int256(pub [u8; 32]);
+
+impl Int256 {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The first and last 16 bytes.
+    pub fn split(&self) -> (Int128, Int128) {
+        let mut high = [0; 16];
+        let mut low = [0; 16];
+        high.copy_from_slice(&self.0[..16]);
+        low.copy_from_slice(&self.0[16..]);
+
+        (Int128(high), Int128(low))
+    }
+}
+
+impl PartialEq for Int256 {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl Hash for Int256 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Debug for Int256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_hex(f, "Int256", &self.0)
+    }
+}
+
+impl From<[u8; 32]> for Int256 {
+    fn from(value: [u8; 32]) -> Self {
+        Int256(value)
+    }
+}
+
+/// From the first and last 16 bytes.
+impl From<(Int128, Int128)> for Int256 {
+    fn from((high, low): (Int128, Int128)) -> Self {
+        let mut bytes = [0; 32];
+        bytes[..16].copy_from_slice(&high.0);
+        bytes[16..].copy_from_slice(&low.0);
+
+        Int256(bytes)
+    }
+}
+
+impl From<Int256> for [u8; 32] {
+    fn from(value: Int256) -> Self {
+        value.0
+    }
+}
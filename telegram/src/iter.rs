@@ -0,0 +1,263 @@
+//! Iterating over dialogs and messages, as streams fetching their pages as they go.
+//!
+//! `messages.getDialogs`, `messages.getHistory` and `messages.search` return the newest
+//! entries first, a page at a time. The next page is asked for with `max_id`, below the
+//! last entry received, rather than with an offset: messages arriving meanwhile would
+//! shift an offset, and entries would come twice or not at all.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use futures::{Async, Future, Poll, Stream};
+
+use error;
+use messages::message_id;
+use schema::{self, messages};
+use Client;
+
+/// Number of entries asked for per page by default, the most the server returns.
+const DEFAULT_PAGE_SIZE: i32 = 100;
+
+/// A dialog, with its last message if the server sent it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialog {
+    pub dialog: schema::Dialog,
+    pub top_message: Option<schema::Message>,
+}
+
+/// Entries of a reply, newest first.
+struct Page<T> {
+    entries: Vec<T>,
+
+    /// Number of entries in total, if the reply is a slice of them.
+    count: Option<u32>,
+}
+
+type PageFuture<T> = Box<dyn Future<Item = Page<T>, Error = error::Error>>;
+
+/// Fetches the page below a `max_id` (0 for the newest one) of a given size.
+type Fetch<T> = Box<dyn Fn(i32, i32) -> PageFuture<T>>;
+
+/// A stream of dialogs or messages, newest first.
+///
+/// Nothing is requested until the stream is first polled. Flood waits between pages are
+/// waited out as the retry policy of the client says.
+#[must_use = "streams do nothing unless polled"]
+pub struct Iter<T> {
+    fetch: Fetch<T>,
+
+    /// Identifier of an entry, e.g. of the top message of a dialog, which orders them.
+    position: fn(&T) -> i32,
+
+    page_size: i32,
+    pending: Option<PageFuture<T>>,
+    buffer: VecDeque<T>,
+
+    /// Position of the oldest entry received, below which the next page is.
+    max_id: i32,
+
+    received: u32,
+    total: Option<u32>,
+    done: bool,
+}
+
+impl<T: 'static> Iter<T> {
+    fn new(fetch: Fetch<T>, position: fn(&T) -> i32) -> Iter<T> {
+        Iter {
+            fetch,
+            position,
+            page_size: DEFAULT_PAGE_SIZE,
+            pending: None,
+            buffer: VecDeque::new(),
+            max_id: 0,
+            received: 0,
+            total: None,
+            done: false,
+        }
+    }
+
+    /// Ask for `page_size` entries per request.
+    pub fn page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Number of entries in total, known once the first page came.
+    pub fn total(&self) -> Option<u32> {
+        self.total
+    }
+
+    fn receive(&mut self, page: Page<T>) {
+        let max_id = self.max_id;
+        let position = self.position;
+
+        // Entries newer than the last page came after it was fetched, and those at the
+        // boundary already came with it
+        let entries = page
+            .entries
+            .into_iter()
+            .filter(|entry| max_id == 0 || position(entry) < max_id);
+
+        let before = self.buffer.len();
+        self.buffer.extend(entries);
+        let new = (self.buffer.len() - before) as u32;
+
+        self.received += new;
+        self.total = Some(page.count.unwrap_or(self.received));
+
+        if let Some(oldest) = self.buffer.iter().map(position).min() {
+            self.max_id = oldest;
+        }
+
+        // A reply which isn't a slice holds every entry left
+        self.done = new == 0 || page.count.is_none_or(|count| self.received >= count);
+    }
+}
+
+impl<T: 'static> Stream for Iter<T> {
+    type Item = T;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<T>, error::Error> {
+        loop {
+            if let Some(entry) = self.buffer.pop_front() {
+                return Ok(Async::Ready(Some(entry)));
+            }
+
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            if self.pending.is_none() {
+                self.pending = Some((self.fetch)(self.max_id, self.page_size));
+            }
+
+            let page = try_ready!(self.pending.as_mut().unwrap().poll());
+            self.pending = None;
+            self.receive(page);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Iter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Iter")
+            .field("page_size", &self.page_size)
+            .field("max_id", &self.max_id)
+            .field("received", &self.received)
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+impl Client {
+    /// The dialogs of the account, the most recently active first.
+    pub fn iter_dialogs(&self) -> Iter<Dialog> {
+        let client = self.clone();
+
+        let fetch = move |max_id, limit| -> PageFuture<Dialog> {
+            let query = messages::GetDialogs {
+                offset: 0,
+                max_id,
+                limit,
+            };
+
+            Box::new(client.invoke(query).map(|dialogs| match dialogs {
+                messages::Dialogs::Dialogs(dialogs) => {
+                    dialog_page(dialogs.dialogs, dialogs.messages, None)
+                }
+
+                messages::Dialogs::DialogsSlice(dialogs) => {
+                    dialog_page(dialogs.dialogs, dialogs.messages, Some(dialogs.count))
+                }
+            }))
+        };
+
+        Iter::new(Box::new(fetch), |dialog| dialog.dialog.top_message)
+    }
+
+    /// The messages exchanged with `peer`, the newest first.
+    pub fn iter_history(&self, peer: schema::InputPeer) -> Iter<schema::Message> {
+        let client = self.clone();
+
+        let fetch = move |max_id, limit| -> PageFuture<schema::Message> {
+            let query = messages::GetHistory {
+                peer: peer.clone(),
+                offset: 0,
+                max_id,
+                limit,
+            };
+
+            Box::new(client.invoke(query).map(message_page))
+        };
+
+        Iter::new(Box::new(fetch), message_id)
+    }
+
+    /// The messages exchanged with `peer` which contain `query` and match `filter`, the
+    /// newest first.
+    pub fn iter_search(
+        &self,
+        peer: schema::InputPeer,
+        query: &str,
+        filter: schema::MessagesFilter,
+    ) -> Iter<schema::Message> {
+        let client = self.clone();
+        let q = query.to_string();
+
+        let fetch = move |max_id, limit| -> PageFuture<schema::Message> {
+            let query = messages::Search {
+                peer: peer.clone(),
+                q: q.clone(),
+                filter: filter.clone(),
+                min_date: 0,
+                max_date: 0,
+                offset: 0,
+                max_id,
+                limit,
+            };
+
+            Box::new(client.invoke(query).map(message_page))
+        };
+
+        Iter::new(Box::new(fetch), message_id)
+    }
+}
+
+fn message_page(messages: messages::Messages) -> Page<schema::Message> {
+    match messages {
+        messages::Messages::Messages(messages) => Page {
+            entries: messages.messages,
+            count: None,
+        },
+
+        messages::Messages::MessagesSlice(messages) => Page {
+            entries: messages.messages,
+            count: Some(messages.count.max(0) as u32),
+        },
+    }
+}
+
+fn dialog_page(
+    dialogs: Vec<schema::Dialog>,
+    messages: Vec<schema::Message>,
+    count: Option<i32>,
+) -> Page<Dialog> {
+    let mut messages: HashMap<i32, schema::Message> = messages
+        .into_iter()
+        .map(|message| (message_id(&message), message))
+        .collect();
+
+    let entries = dialogs
+        .into_iter()
+        .map(|dialog| Dialog {
+            top_message: messages.remove(&dialog.top_message),
+            dialog,
+        })
+        .collect();
+
+    Page {
+        entries,
+        count: count.map(|count| count.max(0) as u32),
+    }
+}
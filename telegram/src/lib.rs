@@ -1,27 +1,120 @@
+//! Unofficial Telegram API library.
+//!
+//! A `Client` invokes the methods of the schema as futures, run on a tokio event loop,
+//! e.g. to log in and list the last dialogs:
+//!
+//! ```no_run
+//! extern crate futures;
+//! extern crate telegram;
+//! extern crate tokio_core;
+//!
+//! use futures::Stream;
+//! use telegram::login::LoginState;
+//! use telegram::Client;
+//! use tokio_core::reactor::Core;
+//!
+//! fn prompt(_: &str) -> String {
+//!     unimplemented!()
+//! }
+//!
+//! fn main() {
+//!     let mut core = Core::new().unwrap();
+//!     let client = Client::new(&core.handle());
+//!
+//!     let mut state = core.run(client.login("+15550100", 12345, "0123456789abcdef")).unwrap();
+//!
+//!     let user = loop {
+//!         let next = match state {
+//!             LoginState::CodeSent(code_sent) => code_sent.sign_in(&prompt("Code")),
+//!             LoginState::NeedsSignUp(sign_up) => sign_up.sign_up(&prompt("First name"), ""),
+//!             LoginState::NeedsPassword(password) => password.check_password(&prompt("Password")),
+//!             LoginState::LoggedIn(user) => break user,
+//!         };
+//!
+//!         state = core.run(next).unwrap();
+//!     };
+//!
+//!     let dialogs = core.run(client.iter_dialogs().take(20).collect()).unwrap();
+//!     println!("logged in as {:?}, {} dialogs", user, dialogs.len());
+//! }
+//! ```
+
 extern crate byteorder;
 extern crate tokio_core as tokio;
+#[macro_use]
 extern crate futures;
 extern crate hyper;
+extern crate sha2;
+extern crate md5;
+extern crate sha1;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate flate2;
 #[macro_use]
 extern crate error_chain;
-extern crate extprim;
 #[macro_use]
 extern crate telegram_derive;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "json")]
+extern crate base64;
+
+// The derives refer to this crate as `::telegram`, as they do from other crates
+extern crate self as telegram;
 
 pub mod ser;
-// pub mod de;
+pub mod de;
 pub mod error;
+pub mod meta;
+pub mod value;
+pub mod login;
+pub mod download;
+pub mod iter;
+pub mod upload;
+pub mod peers;
+pub mod retry;
+pub mod secret;
+pub mod store;
+mod bytes;
 mod client;
+mod crypto;
+mod dc;
+mod handshake;
+mod int;
+mod messages;
+mod object;
 mod request;
+mod rpc;
+mod session;
+mod timer;
+mod transport;
 
+pub use bytes::Bytes;
 pub use client::Client;
+pub use int::{Int128, Int256};
+pub use object::{TlConstructor, TlObject};
 pub use request::Request;
+pub use rpc::RemoteCall;
+pub use session::RsaKey;
+pub use transport::{HttpTransport, Transport};
+pub use value::TlValue;
 
-#[allow(non_camel_case_types)]
+// Method builders take every required parameter of the method
+#[allow(clippy::too_many_arguments)]
 pub mod schema {
     include!(concat!(env!("OUT_DIR"), "/schema.rs"));
 
     pub mod mtproto {
         include!(concat!(env!("OUT_DIR"), "/mtproto_schema.rs"));
     }
+
+    /// The constructors and methods of the API and of MTProto.
+    pub fn registry() -> &'static ::meta::Registry {
+        static REGISTRY: ::std::sync::OnceLock<::meta::Registry> = ::std::sync::OnceLock::new();
+
+        REGISTRY.get_or_init(|| ::meta::Registry::new(&[CONSTRUCTORS, mtproto::CONSTRUCTORS]))
+    }
 }
@@ -0,0 +1,305 @@
+//! Logging in with a phone number: the code sent to it, signing up if the number isn't
+//! registered yet, and the password of two-step verification.
+//!
+//! `Client::login` resolves to a `LoginState`, which tells what the user has to provide
+//! next.
+
+use futures::{future, Future};
+use sha2::{Digest, Sha256};
+
+use error::{self, ErrorKind};
+use schema::{self, auth};
+use {Bytes, Client, RemoteCall, TlConstructor, TlObject};
+
+/// Language of the messages sent by Telegram, e.g. the one carrying the code.
+const LANG_CODE: &str = "en";
+
+/// Where logging in stands, and what it needs to go on.
+#[derive(Debug)]
+pub enum LoginState {
+    /// A code was sent to the phone (or to the other sessions of the account).
+    CodeSent(CodeSent),
+
+    /// The phone number isn't registered: an account has to be created.
+    NeedsSignUp(NeedsSignUp),
+
+    /// The account is protected by a password (two-step verification).
+    NeedsPassword(NeedsPassword),
+
+    /// Logged in as this user.
+    LoggedIn(schema::User),
+}
+
+type LoginFuture = Box<dyn Future<Item = LoginState, Error = error::Error>>;
+
+impl Client {
+    /// Start logging in with `phone_number`, sending it a code.
+    ///
+    /// `api_id` and `api_hash` identify the application, see https://my.telegram.org.
+    pub fn login(&self, phone_number: &str, api_id: i32, api_hash: &str) -> LoginFuture {
+        let client = self.clone();
+        let phone_number = phone_number.to_string();
+        let send_code = auth::SendCode {
+            phone_number: phone_number.clone(),
+            sms_type: 0,
+            api_id,
+            api_hash: api_hash.into(),
+            lang_code: LANG_CODE.into(),
+        };
+
+        Box::new(
+            self.invoke(auth::CheckPhone {
+                phone_number: phone_number.clone(),
+            }).and_then(move |checked| {
+                client.invoke(send_code).map(move |sent| {
+                    let sent = match sent {
+                        auth::SentCode::SentCode(sent) => sent,
+                        auth::SentCode::SentAppCode(sent) => auth::SentCode_ {
+                            phone_registered: sent.phone_registered,
+                            phone_code_hash: sent.phone_code_hash,
+                            send_call_timeout: sent.send_call_timeout,
+                            is_password: sent.is_password,
+                        },
+                    };
+
+                    LoginState::CodeSent(CodeSent {
+                        client,
+                        phone_number,
+                        phone_registered: checked.phone_registered || sent.phone_registered,
+                        phone_code_hash: sent.phone_code_hash,
+                        send_call_timeout: sent.send_call_timeout,
+                    })
+                })
+            }),
+        )
+    }
+}
+
+/// A code was sent to the phone, to sign in (or up) with.
+#[derive(Debug, Clone)]
+pub struct CodeSent {
+    client: Client,
+    phone_number: String,
+    phone_registered: bool,
+    phone_code_hash: String,
+    send_call_timeout: i32,
+}
+
+impl CodeSent {
+    /// Whether an account is registered with the phone number.
+    pub fn phone_registered(&self) -> bool {
+        self.phone_registered
+    }
+
+    /// Seconds after which the code can be requested by phone call, with `call`.
+    pub fn send_call_timeout(&self) -> i32 {
+        self.send_call_timeout
+    }
+
+    /// Sign in with the code. An invalid code fails with `ErrorKind::Rpc` (e.g.
+    /// `PHONE_CODE_INVALID`), after which this can be retried.
+    pub fn sign_in(&self, code: &str) -> LoginFuture {
+        let needs_sign_up = LoginState::NeedsSignUp(NeedsSignUp {
+            client: self.client.clone(),
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+            phone_code: code.into(),
+        });
+
+        if !self.phone_registered {
+            return Box::new(future::ok(needs_sign_up));
+        }
+
+        let client = self.client.clone();
+        let sign_in = auth::SignIn {
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+            phone_code: code.into(),
+        };
+
+        Box::new(self.client.invoke(sign_in).then(move |result| -> LoginFuture {
+            let error = match result {
+                Ok(authorization) => return Box::new(future::ok(LoginState::LoggedIn(authorization.user))),
+                Err(error) => error,
+            };
+
+            match *error.kind() {
+                ErrorKind::Rpc(_, ref message) if message == "SESSION_PASSWORD_NEEDED" => {
+                    Box::new(client.invoke(GetPassword).map(move |password| {
+                        LoginState::NeedsPassword(NeedsPassword { client, password })
+                    }))
+                }
+
+                ErrorKind::Rpc(_, ref message) if message == "PHONE_NUMBER_UNOCCUPIED" => {
+                    Box::new(future::ok(needs_sign_up))
+                }
+
+                _ => Box::new(future::err(error)),
+            }
+        }))
+    }
+
+    /// Send the code again by SMS.
+    pub fn resend_sms(&self) -> Box<dyn Future<Item = bool, Error = error::Error>> {
+        self.client.invoke(auth::SendSms {
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+        })
+    }
+
+    /// Have the code dictated by a phone call, once `send_call_timeout` has passed.
+    pub fn call(&self) -> Box<dyn Future<Item = bool, Error = error::Error>> {
+        self.client.invoke(auth::SendCall {
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+        })
+    }
+}
+
+/// The phone number isn't registered yet; signing up creates an account for it.
+#[derive(Debug, Clone)]
+pub struct NeedsSignUp {
+    client: Client,
+    phone_number: String,
+    phone_code_hash: String,
+    phone_code: String,
+}
+
+impl NeedsSignUp {
+    /// Create the account, with the name shown to other users.
+    pub fn sign_up(&self, first_name: &str, last_name: &str) -> LoginFuture {
+        Box::new(
+            self.client
+                .invoke(auth::SignUp {
+                    phone_number: self.phone_number.clone(),
+                    phone_code_hash: self.phone_code_hash.clone(),
+                    phone_code: self.phone_code.clone(),
+                    first_name: first_name.into(),
+                    last_name: last_name.into(),
+                })
+                .map(|authorization| LoginState::LoggedIn(authorization.user)),
+        )
+    }
+}
+
+/// The account is protected by a password.
+#[derive(Debug, Clone)]
+pub struct NeedsPassword {
+    client: Client,
+    password: Password,
+}
+
+impl NeedsPassword {
+    /// The hint the user chose for the password.
+    pub fn hint(&self) -> Option<&str> {
+        match self.password {
+            Password::Password { ref hint, .. } if !hint.is_empty() => Some(hint),
+            _ => None,
+        }
+    }
+
+    /// Log in with the password. A wrong password fails with `ErrorKind::Rpc`
+    /// (`PASSWORD_HASH_INVALID`), after which this can be retried.
+    pub fn check_password(&self, password: &str) -> LoginFuture {
+        let salt: &[u8] = match self.password {
+            Password::Password { ref current_salt, .. } => current_salt,
+            Password::NoPassword { .. } => &[],
+        };
+
+        Box::new(
+            self.client
+                .invoke(CheckPassword {
+                    password_hash: password_hash(salt, password),
+                })
+                .map(|authorization| LoginState::LoggedIn(authorization.user)),
+        )
+    }
+}
+
+/// `sha256(salt + password + salt)`, as checked by `auth.checkPassword`.
+pub fn password_hash(salt: &[u8], password: &str) -> Bytes {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    hasher.update(salt);
+
+    hasher.finalize().to_vec().into()
+}
+
+// Two-step verification came after the layer of the bundled schema, so its methods are
+// declared here
+
+/// `account.getPassword`: the salt and hint of the password of the account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[id = 0x548a30f5]
+#[tl(name = "account.getPassword", combinator = "account.getPassword = account.Password")]
+pub struct GetPassword;
+
+impl RemoteCall for GetPassword {
+    type Reply = Password;
+}
+
+impl TlObject for GetPassword {
+    fn constructor_id(&self) -> u32 {
+        Self::CONSTRUCTOR_ID
+    }
+
+    fn tl_name(&self) -> &'static str {
+        Self::TL_NAME
+    }
+}
+
+impl TlConstructor for GetPassword {
+    const CONSTRUCTOR_ID: u32 = 0x548a30f5;
+    const TL_NAME: &'static str = "account.getPassword";
+}
+
+/// `account.Password`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[tl(name = "account.Password")]
+pub enum Password {
+    #[id = 0x96dabc18]
+    #[tl(combinator = "account.noPassword new_salt:bytes email_unconfirmed_pattern:string = account.Password")]
+    NoPassword {
+        new_salt: Bytes,
+        email_unconfirmed_pattern: String,
+    },
+
+    #[id = 0x7c18141c]
+    #[tl(combinator = "account.password current_salt:bytes new_salt:bytes hint:string \
+                       has_recovery:Bool email_unconfirmed_pattern:string = account.Password")]
+    Password {
+        current_salt: Bytes,
+        new_salt: Bytes,
+        hint: String,
+        has_recovery: bool,
+        email_unconfirmed_pattern: String,
+    },
+}
+
+/// `auth.checkPassword`: log in with the hash of the password, see `password_hash`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[id = 0x0a63011e]
+#[tl(name = "auth.checkPassword", combinator = "auth.checkPassword password_hash:bytes = auth.Authorization")]
+pub struct CheckPassword {
+    pub password_hash: Bytes,
+}
+
+impl RemoteCall for CheckPassword {
+    type Reply = auth::Authorization;
+}
+
+impl TlObject for CheckPassword {
+    fn constructor_id(&self) -> u32 {
+        Self::CONSTRUCTOR_ID
+    }
+
+    fn tl_name(&self) -> &'static str {
+        Self::TL_NAME
+    }
+}
+
+impl TlConstructor for CheckPassword {
+    const CONSTRUCTOR_ID: u32 = 0x0a63011e;
+    const TL_NAME: &'static str = "auth.checkPassword";
+}
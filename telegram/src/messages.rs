@@ -0,0 +1,295 @@
+//! Sending messages.
+//!
+//! Each message is sent with a random identifier, which the server deduplicates on: a
+//! send which failed before its reply came back is retried with the same one, so that
+//! the message isn't sent twice.
+//!
+//! `messages.sendMessage` only replies with the identifier and date of the message; the
+//! message itself comes with the updates, as does the one of a send whose reply was
+//! lost. Updates have to be handed to the client with `Client::handle_updates` (e.g.
+//! those of `updates.getDifference`), which resolves the sends waiting on them; a send
+//! given no update within `Client::update_timeout` fails with `ErrorKind::UpdateTimeout`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::unsync::oneshot;
+use futures::{future, Future};
+use rand;
+
+use de::DeserializeOwned;
+use error::{self, ErrorKind};
+use rpc::RemoteCall;
+use schema::{self, messages};
+use timer;
+use Client;
+
+/// How long a send waits for the update of its message by default.
+pub(crate) const UPDATE_TIMEOUT: Duration = Duration::from_secs(60);
+
+type MessageFuture = Box<dyn Future<Item = schema::Message, Error = error::Error>>;
+
+/// The messages sent which are waiting for their update.
+#[derive(Default)]
+pub(crate) struct Outbox {
+    pending: RefCell<HashMap<i64, Pending>>,
+
+    /// Messages whose update arrived before the identifier of their send was known.
+    early: RefCell<HashMap<i32, schema::Message>>,
+}
+
+struct Pending {
+    id: Option<i32>,
+    sender: oneshot::Sender<schema::Message>,
+}
+
+/// A send waiting in the outbox, which stops waiting once dropped, e.g. along with the
+/// future of the send.
+struct Waiting {
+    outbox: Rc<Outbox>,
+    random_id: i64,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.outbox.pending.borrow_mut().remove(&self.random_id);
+        self.outbox.prune();
+    }
+}
+
+/// What the reply of a send tells about the message sent.
+enum Sent {
+    Id(i32),
+    Message(Box<schema::Message>),
+}
+
+impl Outbox {
+    /// Wait for the message sent with `random_id`.
+    fn wait(outbox: &Rc<Outbox>, random_id: i64) -> (Waiting, oneshot::Receiver<schema::Message>) {
+        let (sender, receiver) = oneshot::channel();
+        outbox
+            .pending
+            .borrow_mut()
+            .insert(random_id, Pending { id: None, sender });
+
+        let waiting = Waiting {
+            outbox: outbox.clone(),
+            random_id,
+        };
+
+        (waiting, receiver)
+    }
+
+    /// Hand `message` to the send waiting on it, or give it back if there is none; the
+    /// pending sends are few, so they are all looked at.
+    fn resolve(&self, message: schema::Message) -> Option<schema::Message> {
+        let id = message_id(&message);
+        let random_id = self.pending
+            .borrow()
+            .iter()
+            .find(|&(_, pending)| pending.id == Some(id))
+            .map(|(&random_id, _)| random_id);
+
+        match random_id {
+            Some(random_id) => {
+                let pending = self.pending.borrow_mut().remove(&random_id).unwrap();
+
+                // The send may have been dropped meanwhile
+                let _ = pending.sender.send(message);
+                None
+            }
+
+            None => Some(message),
+        }
+    }
+
+    /// The message `id` of the send `random_id` is known.
+    fn identify(&self, random_id: i64, id: i32) {
+        if let Some(pending) = self.pending.borrow_mut().get_mut(&random_id) {
+            pending.id = Some(id);
+        }
+
+        let early = self.early.borrow_mut().remove(&id);
+        if let Some(message) = early {
+            self.resolve(message);
+        }
+
+        self.prune();
+    }
+
+    /// Forget the messages which arrived early once no send could be waiting on them.
+    fn prune(&self) {
+        if self.pending.borrow().values().all(|pending| pending.id.is_some()) {
+            self.early.borrow_mut().clear();
+        }
+    }
+
+    fn handle_update(&self, update: &schema::Update) {
+        match *update {
+            schema::Update::UpdateMessageId(ref update) => {
+                self.identify(update.random_id, update.id)
+            }
+
+            schema::Update::UpdateNewMessage(ref update) => {
+                let waiting = self.pending
+                    .borrow()
+                    .values()
+                    .any(|pending| pending.id.is_none());
+
+                if let Some(message) = self.resolve(update.message.clone()) {
+                    // Until the reply of a send comes, any message may be the one it sent
+                    if waiting {
+                        self.early
+                            .borrow_mut()
+                            .insert(message_id(&message), message);
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+}
+
+impl Client {
+    /// Wait up to `timeout` for the update of each message sent through this client, and
+    /// its clones made from now on, 60 seconds by default.
+    pub fn update_timeout(mut self, timeout: Duration) -> Client {
+        self.update_timeout = timeout;
+        self
+    }
+
+    /// Send a text message to `peer`, resolving to the message once its update arrives.
+    pub fn send_message(&self, peer: schema::InputPeer, text: &str) -> MessageFuture {
+        let random_id = rand::random();
+        let query = messages::SendMessage {
+            peer,
+            message: text.into(),
+            random_id,
+        };
+
+        self.send(query, random_id, |sent| match sent {
+            messages::SentMessage::SentMessage(sent) => Sent::Id(sent.id),
+            messages::SentMessage::SentMessageLink(sent) => Sent::Id(sent.id),
+        })
+    }
+
+    /// Send media, e.g. an uploaded photo, to `peer`, resolving to the message the reply
+    /// states, or to the one of the update if an earlier attempt sent it.
+    pub fn send_media(
+        &self,
+        peer: schema::InputPeer,
+        media: schema::InputMedia,
+    ) -> MessageFuture {
+        let random_id = rand::random();
+        let query = messages::SendMedia {
+            peer,
+            media,
+            random_id,
+        };
+
+        self.send(query, random_id, |stated| {
+            let message = match stated {
+                messages::StatedMessage::StatedMessage(stated) => stated.message,
+                messages::StatedMessage::StatedMessageLink(stated) => stated.message,
+            };
+
+            Sent::Message(Box::new(message))
+        })
+    }
+
+    /// Invoke a send of `random_id`, resolving to the message its reply states, or else to
+    /// the one of its update.
+    fn send<R, F>(&self, query: R, random_id: i64, sent: F) -> MessageFuture
+    where
+        R: RemoteCall + Clone + 'static,
+        R::Reply: DeserializeOwned + 'static,
+        F: FnOnce(R::Reply) -> Sent + 'static,
+    {
+        let (waiting, receiver) = Outbox::wait(&self.outbox, random_id);
+        let timeout = self.update_timeout;
+
+        Box::new(send_with_retries(self, query).and_then(move |reply| -> MessageFuture {
+            match reply.map(sent) {
+                Some(Sent::Message(message)) => return Box::new(future::ok(*message)),
+
+                // Without a reply, the identifier comes with `updateMessageID`
+                Some(Sent::Id(id)) => waiting.outbox.identify(random_id, id),
+                None => {}
+            }
+
+            let timeout = timer::sleep(timeout)
+                .and_then(move |()| Err(ErrorKind::UpdateTimeout(random_id).into()));
+
+            Box::new(
+                receiver
+                    .map_err(|_| "the send was dropped before its message arrived".into())
+                    .select(timeout)
+                    .map(|(message, _)| message)
+                    .map_err(|(error, _)| error)
+                    .then(move |result| {
+                        drop(waiting);
+                        result
+                    }),
+            )
+        }))
+    }
+
+    /// Hand updates over to the client, e.g. those of `updates.getDifference`, adding
+    /// their users and chats to `Client::peers`.
+    pub fn handle_updates(&self, updates: &schema::Updates) {
+        match *updates {
+            schema::Updates::UpdateShort(ref short) => self.outbox.handle_update(&short.update),
+            schema::Updates::Updates(ref updates) => {
+                self.peers.add_all(&updates.users, &updates.chats);
+                self.handle_update_list(&updates.updates)
+            }
+
+            schema::Updates::UpdatesCombined(ref updates) => {
+                self.peers.add_all(&updates.users, &updates.chats);
+                self.handle_update_list(&updates.updates)
+            }
+
+            _ => {}
+        }
+    }
+
+    fn handle_update_list(&self, updates: &[schema::Update]) {
+        // `updateMessageID` comes before `updateNewMessage`, but the order isn't relied on
+        for update in updates {
+            self.outbox.handle_update(update);
+        }
+    }
+}
+
+/// Invoke a send, resolving to `None` if its random identifier was already sent.
+///
+/// The identifier is fresh, so only an earlier attempt of the send, retried by the client
+/// after a transport failure, can have sent it.
+fn send_with_retries<R>(
+    client: &Client,
+    query: R,
+) -> Box<dyn Future<Item = Option<R::Reply>, Error = error::Error>>
+where
+    R: RemoteCall + Clone + 'static,
+    R::Reply: DeserializeOwned + 'static,
+{
+    Box::new(client.invoke(query).then(|reply| match reply {
+        Ok(reply) => Ok(Some(reply)),
+        Err(error) => match *error.kind() {
+            ErrorKind::Rpc(_, ref message) if message == "RANDOM_ID_DUPLICATE" => Ok(None),
+            _ => Err(error),
+        },
+    }))
+}
+
+pub(crate) fn message_id(message: &schema::Message) -> i32 {
+    match *message {
+        schema::Message::MessageEmpty(ref message) => message.id,
+        schema::Message::Message(ref message) => message.id,
+        schema::Message::MessageForwarded(ref message) => message.id,
+        schema::Message::MessageService(ref message) => message.id,
+    }
+}
@@ -0,0 +1,154 @@
+//! Metadata of the schema, generated alongside the types, to handle objects
+//! without static types (e.g. `TlValue`), and to describe identifiers met at runtime.
+
+use std::collections::HashMap;
+
+use error::{self, ErrorKind};
+
+/// A constructor or a method of the schema.
+#[derive(Debug)]
+pub struct Constructor {
+    pub id: u32,
+
+    /// Full TL name, e.g. `auth.sentCode` or `auth.sendCode`.
+    pub name: &'static str,
+
+    /// Full TL type of a constructor (e.g. `auth.SentCode`), result type of a method.
+    pub kind: &'static str,
+
+    /// Whether this is a method rather than a constructor.
+    pub method: bool,
+
+    pub params: &'static [Param],
+}
+
+impl Constructor {
+    /// The result type of a method, `None` for a constructor.
+    pub fn returns(&self) -> Option<&'static str> {
+        if self.method {
+            Some(self.kind)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Param {
+    pub name: &'static str,
+
+    /// TL type as written in the schema, e.g. `flags.0?Vector<int>` or `!X`.
+    pub kind: &'static str,
+}
+
+/// Constructors and methods indexed by identifier, name and type.
+///
+/// The registry of the generated schema is `schema::registry()`. It includes the
+/// constructors of the types mapped onto Rust ones (`boolTrue`, `boolFalse`, `true` and
+/// `null`), but not `vector`, which is read as part of the type holding it rather than as
+/// an object.
+#[derive(Debug)]
+pub struct Registry {
+    constructors: Vec<&'static Constructor>,
+    by_id: HashMap<u32, &'static Constructor>,
+    by_name: HashMap<&'static str, &'static Constructor>,
+    by_kind: HashMap<&'static str, Vec<&'static Constructor>>,
+}
+
+impl Registry {
+    /// Index the given tables. Should an identifier or a name appear in more than one
+    /// table, the first one wins.
+    pub fn new(tables: &[&'static [Constructor]]) -> Registry {
+        let mut registry = Registry {
+            constructors: Vec::new(),
+            by_id: HashMap::new(),
+            by_name: HashMap::new(),
+            by_kind: HashMap::new(),
+        };
+
+        for constructor in tables.iter().flat_map(|table| table.iter()) {
+            if registry.by_id.contains_key(&constructor.id) {
+                continue;
+            }
+
+            registry.constructors.push(constructor);
+            registry.by_id.insert(constructor.id, constructor);
+            registry.by_name.entry(constructor.name).or_insert(constructor);
+
+            if !constructor.method {
+                registry.by_kind.entry(constructor.kind).or_default().push(constructor);
+            }
+        }
+
+        registry
+    }
+
+    /// Look up a constructor or a method by identifier.
+    pub fn get(&self, id: u32) -> Option<&'static Constructor> {
+        self.by_id.get(&id).cloned()
+    }
+
+    /// Look up a constructor or a method by identifier, failing with
+    /// `ErrorKind::UnknownConstructor` if there's none.
+    pub fn lookup(&self, id: u32) -> error::Result<&'static Constructor> {
+        self.get(id).ok_or_else(|| ErrorKind::UnknownConstructor(id).into())
+    }
+
+    /// Look up a constructor or a method by full TL name.
+    pub fn by_name(&self, name: &str) -> Option<&'static Constructor> {
+        self.by_name.get(name).cloned()
+    }
+
+    /// The constructors of a type, e.g. `peerUser`, `peerChat` and `peerChannel` for `Peer`.
+    pub fn constructors_of(&self, kind: &str) -> &[&'static Constructor] {
+        self.by_kind.get(kind).map_or(&[], |constructors| constructors)
+    }
+
+    /// The constructor of a bare type, referred to either by the name of the constructor
+    /// (e.g. `future_salt`) or by its type prefixed with `%` (e.g. `%Message`).
+    pub fn bare(&self, kind: &str) -> Option<&'static Constructor> {
+        if let Some(kind) = kind.strip_prefix('%') {
+            self.constructors_of(kind).first().cloned()
+        } else if kind.rsplit('.').next().is_some_and(|name| name.starts_with(char::is_lowercase)) {
+            self.by_name(kind)
+        } else {
+            None
+        }
+    }
+
+    /// All constructors and methods, in the order of the tables: in the generated ones,
+    /// the builtin constructors, then those of the root module and of the other modules
+    /// by name, each in schema order.
+    pub fn iter(&self) -> impl Iterator<Item = &'static Constructor> + '_ {
+        self.constructors.iter().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.constructors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constructors.is_empty()
+    }
+}
+
+/// Split a conditional parameter type into the `#` parameter it depends on,
+/// its bit and its type, e.g. `flags.0?string` into `flags`, 0 and `string`.
+pub fn condition(kind: &str) -> Option<(&str, u32, &str)> {
+    let index = kind.find('?')?;
+    let (flags, bit) = kind[..index].split_at(kind[..index].find('.')?);
+
+    Some((flags, bit[1..].parse().ok()?, &kind[index + 1..]))
+}
+
+/// The element type of a vector, and whether the vector is boxed (`Vector<T>`)
+/// rather than bare (`vector<T>`).
+pub fn vector_element(kind: &str) -> Option<(&str, bool)> {
+    if kind.starts_with("Vector<") && kind.ends_with('>') {
+        Some((&kind[7..kind.len() - 1], true))
+    } else if kind.starts_with("vector<") && kind.ends_with('>') {
+        Some((&kind[7..kind.len() - 1], false))
+    } else {
+        None
+    }
+}
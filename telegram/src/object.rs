@@ -0,0 +1,23 @@
+/// A value of a TL type, which knows the constructor it was built with.
+///
+/// Implemented by every generated type. For enums the answer depends on the variant, e.g.
+/// `schema::User::UserContact(..)` reports the id and name of `userContact`.
+pub trait TlObject {
+    /// Identifier of the TL constructor of this value.
+    fn constructor_id(&self) -> u32;
+
+    /// Name of the TL constructor of this value, e.g. `"auth.sentCode"`.
+    fn tl_name(&self) -> &'static str;
+}
+
+/// A type representing exactly one TL constructor.
+///
+/// Implemented by the generated structs: types with a single constructor, methods and the
+/// standalone structs wrapped by enum variants (e.g. `schema::UserContact`).
+pub trait TlConstructor: TlObject {
+    /// Identifier of the TL constructor.
+    const CONSTRUCTOR_ID: u32;
+
+    /// Name of the TL constructor.
+    const TL_NAME: &'static str;
+}
@@ -0,0 +1,400 @@
+//! The users and chats seen by a client, to refer to them again.
+//!
+//! Methods take users as an `InputUser` or `InputPeer`, which for most users need the
+//! `access_hash` the server sent along with the user. The client keeps the users, chats
+//! and contacts of every reply and update it sees in a `PeerCache`, from which
+//! `Client::input_peer` builds them.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use futures::{future, Future};
+
+use de::{self, Deserializer, Observed};
+use error::{self, ErrorKind};
+use schema::{self, contacts};
+use ser::Serialize;
+use store::SessionStore;
+use Client;
+
+/// Key of the peers in a `SessionStore`.
+const STORE_KEY: &str = "peers";
+
+/// A user seen by the client.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CachedUser {
+    pub id: i32,
+
+    /// Hash proving the client has seen the user, for users who are neither the user of
+    /// the client nor one of its contacts.
+    pub access_hash: Option<i64>,
+
+    /// Whether this is the user of the client.
+    pub is_self: bool,
+
+    /// Whether the user is a contact of the user of the client.
+    pub contact: bool,
+
+    /// Username, without `@`; empty if the user has none.
+    pub username: String,
+
+    /// Phone number, if known.
+    pub phone: String,
+}
+
+impl CachedUser {
+    pub fn input_peer(&self) -> Option<schema::InputPeer> {
+        Some(if self.is_self {
+            schema::InputPeer::InputPeerSelf
+        } else if self.contact {
+            schema::InputPeerContact { user_id: self.id }.into()
+        } else {
+            schema::InputPeerForeign {
+                user_id: self.id,
+                access_hash: self.access_hash?,
+            }.into()
+        })
+    }
+
+    pub fn input_user(&self) -> Option<schema::InputUser> {
+        Some(if self.is_self {
+            schema::InputUser::InputUserSelf
+        } else if self.contact {
+            schema::InputUserContact { user_id: self.id }.into()
+        } else {
+            schema::InputUserForeign {
+                user_id: self.id,
+                access_hash: self.access_hash?,
+            }.into()
+        })
+    }
+}
+
+/// A chat seen by the client.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CachedChat {
+    pub id: i32,
+    pub title: String,
+}
+
+/// The users and chats seen by a client, by identifier.
+#[derive(Debug, Default)]
+pub struct PeerCache {
+    users: RefCell<HashMap<i32, CachedUser>>,
+    chats: RefCell<HashMap<i32, CachedChat>>,
+}
+
+impl PeerCache {
+    pub fn new() -> PeerCache {
+        PeerCache::default()
+    }
+
+    pub fn user(&self, id: i32) -> Option<CachedUser> {
+        self.users.borrow().get(&id).cloned()
+    }
+
+    pub fn chat(&self, id: i32) -> Option<CachedChat> {
+        self.chats.borrow().get(&id).cloned()
+    }
+
+    /// The user with a username, compared regardless of case and of a leading `@`.
+    pub fn by_username(&self, username: &str) -> Option<CachedUser> {
+        let username = username.trim_start_matches('@');
+        if username.is_empty() {
+            return None;
+        }
+
+        self.users
+            .borrow()
+            .values()
+            .find(|user| user.username.eq_ignore_ascii_case(username))
+            .cloned()
+    }
+
+    /// The user with a phone number, compared regardless of a leading `+`.
+    pub fn by_phone(&self, phone: &str) -> Option<CachedUser> {
+        let phone = phone.trim_start_matches('+');
+        if phone.is_empty() {
+            return None;
+        }
+
+        self.users
+            .borrow()
+            .values()
+            .find(|user| user.phone.trim_start_matches('+') == phone)
+            .cloned()
+    }
+
+    /// The `InputPeer` of a user or chat seen before.
+    pub fn input_peer(&self, peer: &schema::Peer) -> Option<schema::InputPeer> {
+        match *peer {
+            schema::Peer::PeerUser(ref peer) => self.user(peer.user_id)?.input_peer(),
+            schema::Peer::PeerChat(ref peer) => {
+                self.chat(peer.chat_id)?;
+                Some(schema::InputPeerChat { chat_id: peer.chat_id }.into())
+            }
+        }
+    }
+
+    pub fn add_user(&self, user: &schema::User) {
+        let (id, access_hash, is_self, contact, username, phone) = match *user {
+            schema::User::UserEmpty(_) => return,
+            schema::User::UserSelf(ref user) => {
+                (user.id, None, true, false, &user.username, Some(&user.phone))
+            }
+
+            schema::User::UserContact(ref user) => (
+                user.id,
+                Some(user.access_hash),
+                false,
+                true,
+                &user.username,
+                Some(&user.phone),
+            ),
+
+            schema::User::UserRequest(ref user) => (
+                user.id,
+                Some(user.access_hash),
+                false,
+                false,
+                &user.username,
+                Some(&user.phone),
+            ),
+
+            schema::User::UserForeign(ref user) => (
+                user.id,
+                Some(user.access_hash),
+                false,
+                false,
+                &user.username,
+                None,
+            ),
+
+            schema::User::UserDeleted(ref user) => {
+                (user.id, None, false, false, &user.username, None)
+            }
+        };
+
+        let mut users = self.users.borrow_mut();
+        let cached = users.entry(id).or_insert_with(|| CachedUser {
+            id,
+            ..Default::default()
+        });
+
+        cached.access_hash = access_hash.or(cached.access_hash);
+        cached.is_self |= is_self;
+        cached.contact = contact;
+        cached.username = username.clone();
+
+        if let Some(phone) = phone.filter(|phone| !phone.is_empty()) {
+            cached.phone = phone.clone();
+        }
+    }
+
+    pub fn add_chat(&self, chat: &schema::Chat) {
+        let (id, title) = match *chat {
+            schema::Chat::ChatEmpty(_) => return,
+            schema::Chat::Chat(ref chat) => (chat.id, &chat.title),
+            schema::Chat::ChatForbidden(ref chat) => (chat.id, &chat.title),
+        };
+
+        self.chats.borrow_mut().insert(
+            id,
+            CachedChat {
+                id,
+                title: title.clone(),
+            },
+        );
+    }
+
+    pub(crate) fn add_all(&self, users: &[schema::User], chats: &[schema::Chat]) {
+        for user in users {
+            self.add_user(user);
+        }
+
+        for chat in chats {
+            self.add_chat(chat);
+        }
+    }
+
+    /// Mark a user as a contact, who can then be referred to without an access hash.
+    pub fn add_contact(&self, contact: &schema::Contact) {
+        self.users
+            .borrow_mut()
+            .entry(contact.user_id)
+            .or_insert_with(|| CachedUser {
+                id: contact.user_id,
+                ..Default::default()
+            })
+            .contact = true;
+    }
+
+    /// Save the peers under `peers` in `store`.
+    pub fn save(&self, store: &dyn SessionStore) -> error::Result<()> {
+        let stored = StoredPeers {
+            users: self.users
+                .borrow()
+                .values()
+                .map(|user| StoredUser {
+                    id: user.id,
+                    has_access_hash: user.access_hash.is_some(),
+                    access_hash: user.access_hash.unwrap_or(0),
+                    is_self: user.is_self,
+                    contact: user.contact,
+                    username: user.username.clone(),
+                    phone: user.phone.clone(),
+                })
+                .collect(),
+
+            chats: self.chats
+                .borrow()
+                .values()
+                .map(|chat| StoredChat {
+                    id: chat.id,
+                    title: chat.title.clone(),
+                })
+                .collect(),
+        };
+
+        store.save(STORE_KEY, &stored.to_vec()?)
+    }
+
+    /// Add the peers saved in `store`, if any.
+    pub fn load(&self, store: &dyn SessionStore) -> error::Result<()> {
+        let stored: StoredPeers = match store.load(STORE_KEY)? {
+            Some(value) => de::from_slice(&value)?,
+            None => return Ok(()),
+        };
+
+        let mut users = self.users.borrow_mut();
+        for user in stored.users {
+            users.entry(user.id).or_insert(CachedUser {
+                id: user.id,
+                access_hash: Some(user.access_hash).filter(|_| user.has_access_hash),
+                is_self: user.is_self,
+                contact: user.contact,
+                username: user.username,
+                phone: user.phone,
+            });
+        }
+
+        let mut chats = self.chats.borrow_mut();
+        for chat in stored.chats {
+            chats.entry(chat.id).or_insert(CachedChat {
+                id: chat.id,
+                title: chat.title,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Observed for schema::User {
+    fn observed(&self, reader: &Deserializer) {
+        if let Some(peers) = reader.peers() {
+            peers.add_user(self);
+        }
+    }
+}
+
+impl Observed for schema::Chat {
+    fn observed(&self, reader: &Deserializer) {
+        if let Some(peers) = reader.peers() {
+            peers.add_chat(self);
+        }
+    }
+}
+
+impl Observed for schema::Contact {
+    fn observed(&self, reader: &Deserializer) {
+        if let Some(peers) = reader.peers() {
+            peers.add_contact(self);
+        }
+    }
+}
+
+impl Client {
+    /// The users and chats seen by the client.
+    pub fn peers(&self) -> &PeerCache {
+        &self.peers
+    }
+
+    /// The `InputPeer` of a user or chat seen before, failing with `ErrorKind::UnknownPeer`
+    /// for one whose access hash isn't known.
+    pub fn input_peer(&self, peer: &schema::Peer) -> error::Result<schema::InputPeer> {
+        self.peers.input_peer(peer).ok_or_else(|| {
+            let peer = match *peer {
+                schema::Peer::PeerUser(ref peer) => format!("user {}", peer.user_id),
+                schema::Peer::PeerChat(ref peer) => format!("chat {}", peer.chat_id),
+            };
+
+            ErrorKind::UnknownPeer(peer).into()
+        })
+    }
+
+    /// The `InputUser` of a user seen before.
+    pub fn input_user(&self, user_id: i32) -> error::Result<schema::InputUser> {
+        self.peers
+            .user(user_id)
+            .and_then(|user| user.input_user())
+            .ok_or_else(|| ErrorKind::UnknownPeer(format!("user {}", user_id)).into())
+    }
+
+    /// The `InputPeer` of the user with a username, e.g. `@durov`, asking the server with
+    /// `contacts.resolveUsername` if it wasn't seen yet.
+    pub fn resolve(
+        &self,
+        username: &str,
+    ) -> Box<dyn Future<Item = schema::InputPeer, Error = error::Error>> {
+        let cached = self.peers.by_username(username);
+
+        if let Some(input_peer) = cached.and_then(|user| user.input_peer()) {
+            return Box::new(future::ok(input_peer));
+        }
+
+        let username = username.trim_start_matches('@').to_string();
+        let client = self.clone();
+
+        Box::new(
+            self.invoke(contacts::ResolveUsername {
+                username: username.clone(),
+            }).and_then(move |user| {
+                client.peers.add_user(&user);
+
+                client
+                    .peers
+                    .by_username(&username)
+                    .and_then(|user| user.input_peer())
+                    .ok_or_else(|| ErrorKind::UnknownPeer(format!("@{}", username)).into())
+            }),
+        )
+    }
+}
+
+/// The peers as saved in a `SessionStore`.
+#[derive(Serialize, Deserialize)]
+#[id = 0x70656572]
+struct StoredPeers {
+    users: Vec<StoredUser>,
+    chats: Vec<StoredChat>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[id = 0x75736572]
+struct StoredUser {
+    id: i32,
+    has_access_hash: bool,
+    access_hash: i64,
+    is_self: bool,
+    contact: bool,
+    username: String,
+    phone: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[id = 0x63686174]
+struct StoredChat {
+    id: i32,
+    title: String,
+}
@@ -1,8 +1,13 @@
+use std::io::Write;
+
 use ser::Serialize;
 use hyper;
 use std::time::{SystemTime, UNIX_EPOCH};
 use error;
+use transport;
 
+/// A plaintext message, outside of any session, as the methods generating an authorization
+/// key are sent.
 #[derive(Debug)]
 pub struct Request<T: Serialize> {
     message_id: u64,
@@ -14,12 +19,12 @@ impl<T: Serialize> Request<T> {
     #[inline]
     pub fn new(body: T) -> Self {
         // Generate a "unique" message id
-        // > Exact unixtime * 2^32
+        // > Exact unixtime * 2^32, divisible by 4
         // FIXME: This can't fail. Attempt to replace this with something from std that
         //        understands that so we don't have an `.unwrap` here
         let now_d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         let now_s = now_d.as_secs();
-        let message_id = ((now_s as u64) << 32) + (now_d.subsec_nanos() as u64);
+        let message_id = (now_s << 32) | ((now_d.subsec_nanos() as u64) << 2);
 
         Request {
             message_id,
@@ -29,47 +34,34 @@ impl<T: Serialize> Request<T> {
 
     /// Converts this request into a `hyper::Request`.
     pub fn to_http_request(&self) -> error::Result<hyper::Request> {
-        let buffer = self.to_vec()?;
+        Ok(transport::http_request(transport::default_uri(), self.to_vec()?))
+    }
 
-        let mut http_request = hyper::Request::new(
-            hyper::Method::Post,
-            // FIXME: This _cannot_ fail, find a way to do this where the API knows this
-            "http://149.154.167.50:443/api".parse().unwrap(),
-        );
+    /// Number of bytes of the serialized request.
+    pub fn serialized_len(&self) -> usize {
+        // auth_key_id + message_id + message_length + message
+        8 + 8 + 4 + self.message_body.serialized_len()
+    }
 
-        http_request
-            .headers_mut()
-            .set(hyper::header::Connection::keep_alive());
+    /// Serialize this request to the passed writer, without buffering the message.
+    pub fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        // auth_key_id, which is 0 without a key
+        0u64.serialize_to(buffer)?;
 
-        http_request
-            .headers_mut()
-            .set(hyper::header::ContentLength(buffer.len() as u64));
+        // message_id
+        self.message_id.serialize_to(buffer)?;
 
-        http_request.set_body(buffer);
+        // message_length
+        (self.message_body.serialized_len() as u32).serialize_to(buffer)?;
 
-        Ok(http_request)
+        // message
+        self.message_body.serialize_to(buffer)
     }
 
     /// Converts this request into a byte vector.
     pub fn to_vec(&self) -> error::Result<Vec<u8>> {
-        let mut result = Vec::new();
-
-        // TODO: Handle the auth_key_id in the request
-        // auth_key_id
-        0u64.serialize_to(&mut result)?;
-
-        // message_id
-        self.message_id.serialize_to(&mut result)?;
-
-        // Prepare inner message to compute message_length
-        let mut message = Vec::new();
-        self.message_body.serialize_to(&mut message)?;
-
-        // message_length
-        (message.len() as u32).serialize_to(&mut result)?;
-
-        // Push the message onto the buffer following the message_length
-        result.extend(message);
+        let mut result = Vec::with_capacity(self.serialized_len());
+        self.serialize_to(&mut result)?;
 
         Ok(result)
     }
@@ -0,0 +1,319 @@
+//! Waiting out the limits of the server, and retrying what failed on its side.
+//!
+//! Every method the client invokes goes through its `RetryPolicy`, which:
+//!
+//! - waits the `X` seconds of a `FLOOD_WAIT_X` error and invokes the method again, unless
+//!   `X` is above `max_flood_wait`, or the flood waits of the call would add up to more
+//!   than `max_total_flood_wait`, in which case the error is returned right away;
+//! - retries internal server errors (`-500`, or any 5xx code) and transport failures,
+//!   waiting twice as long after each;
+//! - throttles the methods given a `RateLimit`, with a token bucket per method, so that
+//!   bulk jobs slow down before the server floods them.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Loop};
+use futures::Future;
+
+use de::DeserializeOwned;
+use error::{self, ErrorKind};
+use rpc::{self, RemoteCall};
+use timer;
+use Client;
+
+type Sleep = Box<dyn Future<Item = (), Error = error::Error>>;
+
+/// The retries of failures of a call so far, and how long its flood waits added up to.
+type Attempts = (u32, Duration);
+
+type Attempt<T> = Box<dyn Future<Item = Loop<T, Attempts>, Error = error::Error>>;
+
+/// Where the time the retries wait for comes from.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// A future resolving once `duration` has elapsed.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+impl<C: Clock + ?Sized> Clock for Rc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        (**self).sleep(duration)
+    }
+}
+
+/// The time of the system, waited for on the timer thread shared by the clients.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        timer::sleep(duration)
+    }
+}
+
+/// At most `calls` invocations of a method `per` period, which may all come at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub calls: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub fn new(calls: u32, per: Duration) -> RateLimit {
+        RateLimit {
+            calls: calls.max(1),
+            per,
+        }
+    }
+
+    /// Tokens earned per second.
+    fn rate(&self) -> f64 {
+        f64::from(self.calls) / self.per.as_secs_f64()
+    }
+}
+
+/// How the client waits for and retries the methods it invokes.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_flood_wait: Duration,
+    max_total_flood_wait: Duration,
+    retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    rate_limits: HashMap<String, RateLimit>,
+    clock: Rc<dyn Clock>,
+}
+
+impl Default for RetryPolicy {
+    /// Flood waits of up to a minute are waited out, up to 5 minutes per call, and
+    /// failures are retried 5 times, from 1 second apart to 30.
+    fn default() -> Self {
+        RetryPolicy {
+            max_flood_wait: Duration::from_secs(60),
+            max_total_flood_wait: Duration::from_secs(5 * 60),
+            retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            rate_limits: HashMap::new(),
+            clock: Rc::new(SystemClock),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy which neither waits nor retries: every error is returned as is.
+    pub fn none() -> Self {
+        RetryPolicy::default()
+            .max_flood_wait(Duration::from_secs(0))
+            .retries(0)
+    }
+
+    /// Return a `FLOOD_WAIT_X` error rather than wait, if `X` is above `max_flood_wait`.
+    pub fn max_flood_wait(mut self, max_flood_wait: Duration) -> Self {
+        self.max_flood_wait = max_flood_wait;
+        self
+    }
+
+    /// Return a `FLOOD_WAIT_X` error rather than wait, if the flood waits of the call,
+    /// `X` included, would add up to more than `max_total_flood_wait`.
+    pub fn max_total_flood_wait(mut self, max_total_flood_wait: Duration) -> Self {
+        self.max_total_flood_wait = max_total_flood_wait;
+        self
+    }
+
+    /// Retry internal server errors and transport failures up to `retries` times.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait `initial` before the first retry, then twice as long before each of the next
+    /// ones, up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Throttle the method with TL name `method`, e.g. `messages.getHistory`.
+    pub fn rate_limit(mut self, method: &str, limit: RateLimit) -> Self {
+        self.rate_limits.insert(method.into(), limit);
+        self
+    }
+
+    /// Take the time from `clock`, e.g. a fake one in tests.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Rc::new(clock);
+        self
+    }
+
+    /// How long to wait before invoking a method again after `error`, after `backoffs`
+    /// retries of failures and `flood_waited` of flood waits; `None` if the error is to be
+    /// returned.
+    fn delay(&self, error: &error::Error, backoffs: u32, flood_waited: Duration) -> Option<Delay> {
+        if let Some(wait) = rpc::flood_wait(error) {
+            return if wait <= self.max_flood_wait
+                && flood_waited + wait <= self.max_total_flood_wait
+            {
+                Some(Delay::FloodWait(wait))
+            } else {
+                None
+            };
+        }
+
+        let failed = match *error.kind() {
+            ErrorKind::Rpc(code, _) => code == -500 || code >= 500,
+            ErrorKind::Io(_) | ErrorKind::Hyper(_) => true,
+            _ => false,
+        };
+
+        if !failed || backoffs >= self.retries {
+            return None;
+        }
+
+        let backoff = self.initial_backoff
+            .checked_mul(1 << backoffs.min(31))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+
+        Some(Delay::Backoff(backoff))
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_flood_wait", &self.max_flood_wait)
+            .field("max_total_flood_wait", &self.max_total_flood_wait)
+            .field("retries", &self.retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("rate_limits", &self.rate_limits)
+            .finish()
+    }
+}
+
+enum Delay {
+    /// Asked for by the server, which doesn't count as a retry, but towards the total
+    /// flood wait of the call.
+    FloodWait(Duration),
+    Backoff(Duration),
+}
+
+/// A policy, with the token buckets of the methods it throttles.
+pub(crate) struct Retry {
+    policy: RetryPolicy,
+    buckets: RefCell<HashMap<&'static str, Bucket>>,
+}
+
+struct Bucket {
+    /// Negative once calls wait for tokens to come.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Retry {
+    pub(crate) fn new(policy: RetryPolicy) -> Retry {
+        Retry {
+            policy,
+            buckets: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for a token of the bucket of `method`, if it has one.
+    fn throttle(&self, method: &'static str) -> Sleep {
+        let limit = match self.policy.rate_limits.get(method) {
+            Some(limit) => *limit,
+            None => return Box::new(future::ok(())),
+        };
+
+        let now = self.policy.clock.now();
+        let mut buckets = self.buckets.borrow_mut();
+        let bucket = buckets.entry(method).or_insert(Bucket {
+            tokens: f64::from(limit.calls),
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate()).min(f64::from(limit.calls));
+        bucket.updated = now;
+
+        // The token is taken right away, so that the calls waiting line up behind it
+        bucket.tokens -= 1.0;
+
+        if bucket.tokens >= 0.0 {
+            Box::new(future::ok(()))
+        } else {
+            let wait = Duration::from_secs_f64(-bucket.tokens / limit.rate());
+            self.policy.clock.sleep(wait)
+        }
+    }
+}
+
+impl Client {
+    /// Wait for and retry the methods invoked through this client, and its clones made
+    /// from now on, as `policy` says.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Client {
+        self.retry = Rc::new(Retry::new(policy));
+        self
+    }
+
+    /// Invoke a method through the retry policy of the client.
+    pub(crate) fn invoke_with_retries<R, F>(
+        &self,
+        query: R,
+        invoke: F,
+    ) -> Box<dyn Future<Item = R::Reply, Error = error::Error>>
+    where
+        R: RemoteCall + Clone + 'static,
+        R::Reply: DeserializeOwned + 'static,
+        F: Fn(R) -> Box<dyn Future<Item = R::Reply, Error = error::Error>> + 'static,
+    {
+        let retry = self.retry.clone();
+        let method = query.tl_name();
+        let invoke = Rc::new(invoke);
+
+        let first: Attempts = (0, Duration::from_secs(0));
+
+        Box::new(future::loop_fn(first, move |(backoffs, flood_waited)| {
+            let retry = retry.clone();
+            let (invoke, query) = (invoke.clone(), query.clone());
+
+            retry.throttle(method).and_then(move |()| invoke(query)).then(
+                move |reply| -> Attempt<R::Reply> {
+                    let error = match reply {
+                        Ok(reply) => return Box::new(future::ok(Loop::Break(reply))),
+                        Err(error) => error,
+                    };
+
+                    let delay = retry.policy.delay(&error, backoffs, flood_waited);
+                    let (wait, attempts) = match delay {
+                        Some(Delay::FloodWait(wait)) => (wait, (backoffs, flood_waited + wait)),
+                        Some(Delay::Backoff(wait)) => (wait, (backoffs + 1, flood_waited)),
+                        None => return Box::new(future::err(error)),
+                    };
+
+                    Box::new(
+                        retry
+                            .policy
+                            .clock
+                            .sleep(wait)
+                            .map(move |()| Loop::Continue(attempts)),
+                    )
+                },
+            )
+        }))
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use de::{Deserialize, Deserializer};
+use error::{self, ErrorKind};
+use schema::mtproto::RpcError;
+use ser::Serialize;
+use {TlConstructor, TlObject};
+
+/// Identifier of `rpc_result`, whose `result` may be any object and so isn't generated.
+pub(crate) const RPC_RESULT_ID: u32 = 0xf35c6d01;
+
+/// A method which can be invoked on the Telegram servers.
+///
+/// Implemented by the generated method types, e.g. `schema::auth::SendCode`. Methods wrapping
+/// another query (`invokeWithLayer`, `initConnection`, ...) are generic over it and reply with
+/// whatever the wrapped query replies with.
+pub trait RemoteCall: Serialize + TlObject {
+    /// Type of the result the server replies with.
+    type Reply;
+}
+
+/// The result of a method, failing with `ErrorKind::Rpc` if it is an `rpc_error`.
+pub(crate) fn reply_body(result: &[u8]) -> error::Result<&[u8]> {
+    let mut reader = Deserializer::new(result);
+
+    if peek_id(&reader) == Some(RpcError::CONSTRUCTOR_ID) {
+        let error = RpcError::deserialize_from(&mut reader)?;
+        bail!(ErrorKind::Rpc(error.error_code, error.error_message));
+    }
+
+    Ok(reader.remaining())
+}
+
+/// How long the server asks to wait with a `FLOOD_WAIT_X` error, of `X` seconds.
+pub(crate) fn flood_wait(error: &error::Error) -> Option<Duration> {
+    match *error.kind() {
+        ErrorKind::Rpc(420, ref message) if message.starts_with("FLOOD_WAIT_") => {
+            message["FLOOD_WAIT_".len()..]
+                .parse()
+                .ok()
+                .map(Duration::from_secs)
+        }
+
+        _ => None,
+    }
+}
+
+fn peek_id(reader: &Deserializer) -> Option<u32> {
+    let remaining = reader.remaining();
+
+    if remaining.len() >= 4 {
+        Some(LittleEndian::read_u32(remaining))
+    } else {
+        None
+    }
+}
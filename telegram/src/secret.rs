@@ -0,0 +1,1694 @@
+//! Secret chats: messages encrypted end to end, which the server only relays.
+//!
+//! The two sides of a chat agree on a key with a Diffie-Hellman exchange: the side
+//! starting the chat sends `g_a` with `messages.requestEncryption`, the other one replies
+//! with `g_b` with `messages.acceptEncryption`, and both derive `g_ab`. Messages are then
+//! serialized, padded and encrypted with AES-256-IGE under a key derived from `g_ab` and
+//! the hash of the message, as in MTProto 1.0.
+//!
+//! Each message carries sequence numbers, so that a repeated one is dropped and missing
+//! ones are asked for again, the messages past them held until they come. The key is
+//! replaced after `REKEY_AFTER` messages with a new exchange.
+//!
+//! The state of each chat (its key, counters, pending exchange and the messages held past
+//! a gap) is saved in a `SessionStore` as it changes. Updates are handed to
+//! `SecretChats::handle_update`, which decrypts the messages of `updateNewEncryptedMessage`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{future, stream, Future, Stream};
+use md5::{Digest, Md5};
+use rand::{self, Rng, RngCore};
+
+use crypto::{self, random_bytes, Dh, Ige, KEY_SIZE};
+use de;
+use download::Download;
+use error::{self, ErrorKind};
+use schema::{self, messages};
+use ser::Serialize;
+use store::SessionStore;
+use {Bytes, Client};
+
+/// Layer of the messages of secret chats this client speaks.
+pub const LAYER: i32 = 20;
+
+/// Number of messages sent and received with a key before it is replaced.
+pub const REKEY_AFTER: u32 = 100;
+
+/// Key of the identifiers of the saved chats in the store.
+const STORE_KEY: &str = "secret_chats";
+
+/// What an update tells about the secret chats.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretEvent {
+    /// `user_id` asks to start a secret chat, which `SecretChats::accept` accepts.
+    Requested { chat_id: i32, user_id: i32 },
+
+    /// The other side accepted the chat: messages can be sent.
+    Ready(i32),
+
+    Message(Box<SecretMessage>),
+
+    /// The chat was closed, by either side.
+    Discarded(i32),
+}
+
+/// A message of a secret chat, decrypted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecretMessage {
+    pub chat_id: i32,
+    pub random_id: i64,
+    pub date: i32,
+    pub message: DecryptedMessage,
+
+    /// The encrypted file attached, whose key is in the media of the message.
+    pub file: Option<schema::EncryptedFile_>,
+}
+
+/// The key and IV a file sent in a secret chat is encrypted with, which the media of the
+/// message sending it holds.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FileKey {
+    pub key: [u8; 32],
+    pub iv: [u8; 32],
+}
+
+impl FileKey {
+    /// A key and IV of the bytes of a `DecryptedMessageMedia`.
+    pub fn new(key: &[u8], iv: &[u8]) -> error::Result<FileKey> {
+        if key.len() != 32 || iv.len() != 32 {
+            bail!("the keys of files are of 32 bytes, and so are their IVs");
+        }
+
+        let mut file_key = FileKey {
+            key: [0; 32],
+            iv: [0; 32],
+        };
+        file_key.key.copy_from_slice(key);
+        file_key.iv.copy_from_slice(iv);
+
+        Ok(file_key)
+    }
+
+    /// A new random key and IV, to upload a file with.
+    pub fn random() -> FileKey {
+        let mut file_key = FileKey {
+            key: [0; 32],
+            iv: [0; 32],
+        };
+        rand::thread_rng().fill_bytes(&mut file_key.key);
+        rand::thread_rng().fill_bytes(&mut file_key.iv);
+
+        file_key
+    }
+
+    /// The fingerprint the uploaded file is sent with: the first 4 bytes of
+    /// `md5(key + iv)`, XOR the next 4.
+    pub fn fingerprint(&self) -> i32 {
+        let digest = Md5::new()
+            .chain_update(self.key)
+            .chain_update(self.iv)
+            .finalize();
+
+        LittleEndian::read_i32(&digest[..4]) ^ LittleEndian::read_i32(&digest[4..8])
+    }
+
+    pub(crate) fn cipher(&self) -> Ige {
+        Ige::new(&self.key, &self.iv)
+    }
+}
+
+impl fmt::Debug for FileKey {
+    /// Only the fingerprint, so that keys don't end up in logs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileKey")
+            .field("fingerprint", &self.fingerprint())
+            .finish()
+    }
+}
+
+type SecretFuture<T> = Box<dyn Future<Item = T, Error = error::Error>>;
+
+/// The secret chats of a client.
+///
+/// Clones share the same chats, so that they can be moved into futures.
+#[derive(Clone)]
+pub struct SecretChats {
+    client: Client,
+    store: Rc<dyn SessionStore>,
+    chats: Rc<RefCell<HashMap<i32, Chat>>>,
+
+    /// The last DH configuration received, whose prime was checked.
+    dh_config: Rc<RefCell<Option<Rc<DhConfig>>>>,
+}
+
+impl SecretChats {
+    /// The secret chats of `client`, with those saved in `store` before.
+    pub fn new<S: SessionStore + 'static>(client: &Client, store: S) -> error::Result<SecretChats> {
+        let ids: StoredIds = match store.load(STORE_KEY)? {
+            Some(value) => de::from_slice(&value)?,
+            None => StoredIds { ids: Vec::new() },
+        };
+
+        let mut chats = HashMap::new();
+        for id in ids.ids {
+            if let Some(value) = store.load(&chat_key(id))? {
+                chats.insert(id, Chat::from_stored(de::from_slice(&value)?));
+            }
+        }
+
+        Ok(SecretChats {
+            client: client.clone(),
+            store: Rc::new(store),
+            chats: Rc::new(RefCell::new(chats)),
+            dh_config: Rc::new(RefCell::new(None)),
+        })
+    }
+
+    /// Identifiers of the chats, whichever their state.
+    pub fn chat_ids(&self) -> Vec<i32> {
+        let mut ids: Vec<i32> = self.chats.borrow().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Whether messages can be sent in the chat.
+    pub fn is_ready(&self, chat_id: i32) -> bool {
+        self.chats
+            .borrow()
+            .get(&chat_id)
+            .is_some_and(|chat| chat.key.is_some() && chat.state == ChatState::Ready)
+    }
+
+    /// The user on the other side of the chat.
+    pub fn user_id(&self, chat_id: i32) -> Option<i32> {
+        self.chats.borrow().get(&chat_id).map(|chat| chat.user_id)
+    }
+
+    /// Fingerprint of the current key of the chat, which both sides can compare.
+    pub fn key_fingerprint(&self, chat_id: i32) -> Option<i64> {
+        self.chats
+            .borrow()
+            .get(&chat_id)
+            .and_then(|chat| chat.key.as_ref())
+            .map(|key| key.fingerprint)
+    }
+
+    /// Layer of the messages of the other side, as it notified it.
+    pub fn layer(&self, chat_id: i32) -> Option<i32> {
+        self.chats.borrow().get(&chat_id).map(|chat| chat.layer)
+    }
+
+    /// Ask `user` to start a secret chat, resolving to its identifier.
+    ///
+    /// The chat is ready once `handle_update` gets the acceptance of the other side.
+    pub fn request(&self, user: schema::InputUser) -> SecretFuture<i32> {
+        let secret_chats = self.clone();
+
+        Box::new(self.dh_config().and_then(move |(config, random)| {
+            let a = exponent(&random);
+            let g_a = config.dh.power(&a);
+
+            let query = messages::RequestEncryption {
+                user_id: user,
+                random_id: rand::random(),
+                g_a: g_a.into(),
+            };
+
+            secret_chats.client.invoke(query).and_then(move |chat| {
+                let waiting = match chat {
+                    schema::EncryptedChat::EncryptedChatWaiting(waiting) => waiting,
+                    chat => bail!("unexpected reply to a secret chat request: {:?}", chat),
+                };
+
+                secret_chats.insert(Chat {
+                    id: waiting.id,
+                    access_hash: waiting.access_hash,
+                    user_id: waiting.participant_id,
+                    admin: true,
+                    state: ChatState::Waiting { a },
+                    ..Chat::default()
+                })?;
+
+                Ok(waiting.id)
+            })
+        }))
+    }
+
+    /// Accept the chat another user requested.
+    pub fn accept(&self, chat_id: i32) -> SecretFuture<()> {
+        let g_a = match self.chats.borrow().get(&chat_id).map(|chat| &chat.state) {
+            Some(ChatState::Requested { g_a }) => g_a.clone(),
+            Some(_) => return Box::new(future::err("the secret chat wasn't requested".into())),
+            None => return Box::new(future::err(unknown_chat(chat_id))),
+        };
+
+        let secret_chats = self.clone();
+
+        Box::new(self.dh_config().and_then(move |(config, random)| {
+            let b = exponent(&random);
+            let g_b = config.dh.power(&b);
+            let key = Key::new(config.dh.shared_key(&g_a, &b)?);
+            let key_fingerprint = key.fingerprint;
+
+            let query = messages::AcceptEncryption {
+                peer: secret_chats.input_chat(chat_id)?,
+                g_b: g_b.into(),
+                key_fingerprint,
+            };
+
+            Ok(secret_chats.client.invoke(query).and_then(move |chat| {
+                match chat {
+                    schema::EncryptedChat::EncryptedChat(ref chat) if chat.id == chat_id => {}
+                    chat => bail!("unexpected reply to a secret chat acceptance: {:?}", chat),
+                }
+
+                secret_chats.update(chat_id, |chat| {
+                    chat.state = ChatState::Ready;
+                    chat.key = Some(key);
+                })?;
+
+                Ok(secret_chats)
+            }))
+        }).flatten()
+            .and_then(move |secret_chats| secret_chats.notify_layer(chat_id)))
+    }
+
+    /// Close the chat, for both sides.
+    pub fn discard(&self, chat_id: i32) -> SecretFuture<()> {
+        let secret_chats = self.clone();
+
+        Box::new(
+            self.client
+                .invoke(messages::DiscardEncryption { chat_id })
+                .and_then(move |_| secret_chats.remove(chat_id)),
+        )
+    }
+
+    /// Send a text message.
+    pub fn send_text(
+        &self,
+        chat_id: i32,
+        text: &str,
+    ) -> SecretFuture<messages::SentEncryptedMessage> {
+        self.send(
+            chat_id,
+            DecryptedMessage::Message {
+                random_id: rand::random(),
+                ttl: 0,
+                message: text.into(),
+                media: DecryptedMessageMedia::Empty,
+            },
+        )
+    }
+
+    /// Send a message, with `messages.sendEncrypted` or, for a service one,
+    /// `messages.sendEncryptedService`.
+    ///
+    /// Once the key served `REKEY_AFTER` messages, a new one is exchanged.
+    pub fn send(
+        &self,
+        chat_id: i32,
+        message: DecryptedMessage,
+    ) -> SecretFuture<messages::SentEncryptedMessage> {
+        self.send_with_file(chat_id, message, None)
+    }
+
+    /// Send a message with a file uploaded with `Upload::encrypt`, with
+    /// `messages.sendEncryptedFile`.
+    ///
+    /// The media of the message holds the key and IV the file was encrypted with, so that
+    /// the other side can decrypt it.
+    pub fn send_file(
+        &self,
+        chat_id: i32,
+        message: DecryptedMessage,
+        file: schema::InputEncryptedFile,
+    ) -> SecretFuture<messages::SentEncryptedMessage> {
+        self.send_with_file(chat_id, message, Some(file))
+    }
+
+    fn send_with_file(
+        &self,
+        chat_id: i32,
+        message: DecryptedMessage,
+        file: Option<schema::InputEncryptedFile>,
+    ) -> SecretFuture<messages::SentEncryptedMessage> {
+        if let (&DecryptedMessage::Service { .. }, Some(_)) = (&message, &file) {
+            return Box::new(future::err("service messages can't have a file".into()));
+        }
+
+        let (peer, data) = match self.encrypt(chat_id, &message) {
+            Ok(encrypted) => encrypted,
+            Err(error) => return Box::new(future::err(error)),
+        };
+
+        let sent = match (message, file) {
+            (DecryptedMessage::Message { random_id, .. }, Some(file)) => {
+                self.client.invoke(messages::SendEncryptedFile {
+                    peer,
+                    random_id,
+                    data,
+                    file,
+                })
+            }
+
+            (DecryptedMessage::Message { random_id, .. }, None) => {
+                self.client.invoke(messages::SendEncrypted {
+                    peer,
+                    random_id,
+                    data,
+                })
+            }
+
+            (DecryptedMessage::Service { random_id, .. }, _) => {
+                self.client.invoke(messages::SendEncryptedService {
+                    peer,
+                    random_id,
+                    data,
+                })
+            }
+        };
+
+        let secret_chats = self.clone();
+
+        Box::new(sent.and_then(move |sent| {
+            secret_chats
+                .rekey_if_used(chat_id)
+                .map(move |()| sent)
+        }))
+    }
+
+    /// Download the file of a message, decrypting it with the key in its media.
+    pub fn download(&self, message: &SecretMessage) -> error::Result<Download> {
+        let file = match message.file {
+            Some(ref file) => file.clone(),
+            None => bail!("the message has no file"),
+        };
+
+        let (key, size) = match message.message {
+            DecryptedMessage::Message { ref media, .. } => match media_file(media) {
+                Some((key, iv, size)) => (FileKey::new(key, iv)?, size.max(0) as u64),
+                None => bail!("the media of the message has no file"),
+            },
+
+            DecryptedMessage::Service { .. } => bail!("service messages have no file"),
+        };
+
+        if key.fingerprint() != file.key_fingerprint {
+            bail!("the key of the file doesn't match its fingerprint");
+        }
+
+        Ok(self.client.download(file).decrypt(&key, size))
+    }
+
+    /// Send a service message, e.g. that messages were read.
+    pub fn send_action(&self, chat_id: i32, action: DecryptedMessageAction) -> SecretFuture<()> {
+        let message = DecryptedMessage::Service {
+            random_id: rand::random(),
+            action,
+        };
+
+        Box::new(self.send(chat_id, message).map(|_| ()))
+    }
+
+    /// Mark the messages received up to `max_date` as read.
+    pub fn read_history(&self, chat_id: i32, max_date: i32) -> SecretFuture<()> {
+        let peer = match self.input_chat(chat_id) {
+            Ok(peer) => peer,
+            Err(error) => return Box::new(future::err(error)),
+        };
+
+        Box::new(
+            self.client
+                .invoke(messages::ReadEncryptedHistory { peer, max_date })
+                .map(|_| ()),
+        )
+    }
+
+    /// Start replacing the key of the chat, which happens on its own after
+    /// `REKEY_AFTER` messages.
+    pub fn rekey(&self, chat_id: i32) -> SecretFuture<()> {
+        let secret_chats = self.clone();
+
+        Box::new(self.dh_config().and_then(move |(config, random)| {
+            let a = exponent(&random);
+            let g_a = config.dh.power(&a);
+            let exchange_id = rand::random();
+
+            secret_chats.update(chat_id, |chat| {
+                chat.exchange = Some(Exchange {
+                    id: exchange_id,
+                    secret: a,
+                    key: None,
+                });
+            })?;
+
+            let action = DecryptedMessageAction::RequestKey {
+                exchange_id,
+                g_a: g_a.into(),
+            };
+
+            Ok(secret_chats.send_action(chat_id, action))
+        }).flatten())
+    }
+
+    /// Handle an update, resolving to what it tells about the secret chats: several
+    /// messages once one fills a gap the others were held behind, and usually one event
+    /// or none.
+    ///
+    /// Replies the protocol calls for, e.g. during a key exchange or to ask for missing
+    /// messages, are sent before the future resolves.
+    pub fn handle_update(&self, update: &schema::Update) -> SecretFuture<Vec<SecretEvent>> {
+        match *update {
+            schema::Update::UpdateEncryption(ref update) => Box::new(
+                self.handle_chat(&update.chat)
+                    .map(|event| event.into_iter().collect()),
+            ),
+
+            schema::Update::UpdateNewEncryptedMessage(ref update) => {
+                self.handle_message(&update.message)
+            }
+
+            _ => Box::new(future::ok(Vec::new())),
+        }
+    }
+
+    fn handle_chat(&self, chat: &schema::EncryptedChat) -> SecretFuture<Option<SecretEvent>> {
+        match *chat {
+            schema::EncryptedChat::EncryptedChatRequested(ref requested) => {
+                if self.chats.borrow().contains_key(&requested.id) {
+                    return Box::new(future::ok(None));
+                }
+
+                let inserted = self.insert(Chat {
+                    id: requested.id,
+                    access_hash: requested.access_hash,
+                    user_id: requested.admin_id,
+                    admin: false,
+                    state: ChatState::Requested {
+                        g_a: requested.g_a.0.clone(),
+                    },
+                    ..Chat::default()
+                });
+
+                Box::new(future::result(inserted.map(|()| {
+                    Some(SecretEvent::Requested {
+                        chat_id: requested.id,
+                        user_id: requested.admin_id,
+                    })
+                })))
+            }
+
+            schema::EncryptedChat::EncryptedChat(ref accepted) => self.handle_accepted(accepted),
+
+            schema::EncryptedChat::EncryptedChatDiscarded(ref discarded) => {
+                let chat_id = discarded.id;
+                Box::new(future::result(
+                    self.remove(chat_id)
+                        .map(|()| Some(SecretEvent::Discarded(chat_id))),
+                ))
+            }
+
+            _ => Box::new(future::ok(None)),
+        }
+    }
+
+    /// The other side accepted a chat requested by this client.
+    fn handle_accepted(
+        &self,
+        accepted: &schema::EncryptedChat_,
+    ) -> SecretFuture<Option<SecretEvent>> {
+        let chat_id = accepted.id;
+        let a = match self.chats.borrow().get(&chat_id).map(|chat| &chat.state) {
+            Some(ChatState::Waiting { a }) => a.clone(),
+
+            // The echo of an acceptance by this client
+            _ => return Box::new(future::ok(None)),
+        };
+
+        let secret_chats = self.clone();
+        let key_fingerprint = accepted.key_fingerprint;
+
+        Box::new(
+            self.finish_exchange(accepted.g_a_or_b.0.clone(), a)
+                .and_then(move |key| secret_chats.chat_key_agreed(chat_id, key, key_fingerprint)),
+        )
+    }
+
+    /// Both sides derived a key for the chat, the other one with `key_fingerprint`.
+    fn chat_key_agreed(
+        &self,
+        chat_id: i32,
+        key: Key,
+        key_fingerprint: i64,
+    ) -> SecretFuture<Option<SecretEvent>> {
+        if key.fingerprint != key_fingerprint {
+            // Someone is in the middle: the chat can't be trusted
+            return Box::new(self.discard(chat_id).and_then(|()| {
+                Err("the key fingerprints of the secret chat differ".into())
+            }));
+        }
+
+        let updated = self.update(chat_id, |chat| {
+            chat.state = ChatState::Ready;
+            chat.key = Some(key);
+        });
+
+        if let Err(error) = updated {
+            return Box::new(future::err(error));
+        }
+
+        Box::new(
+            self.notify_layer(chat_id)
+                .map(move |()| Some(SecretEvent::Ready(chat_id))),
+        )
+    }
+
+    fn handle_message(&self, message: &schema::EncryptedMessage) -> SecretFuture<Vec<SecretEvent>> {
+        let (chat_id, random_id, date, bytes, file) = match *message {
+            schema::EncryptedMessage::EncryptedMessage(ref message) => {
+                let file = match message.file {
+                    schema::EncryptedFile::EncryptedFile(ref file) => Some(file.clone()),
+                    schema::EncryptedFile::EncryptedFileEmpty => None,
+                };
+
+                (message.chat_id, message.random_id, message.date, &message.bytes, file)
+            }
+
+            schema::EncryptedMessage::EncryptedMessageService(ref message) => {
+                (message.chat_id, message.random_id, message.date, &message.bytes, None)
+            }
+        };
+
+        // Messages may still come for a chat once it was discarded
+        if !self.chats.borrow().contains_key(&chat_id) {
+            return Box::new(future::ok(Vec::new()));
+        }
+
+        let received = self.decrypt(chat_id, bytes).and_then(|(message, seq)| {
+            let message = SecretMessage {
+                chat_id,
+                random_id,
+                date,
+                message,
+                file,
+            };
+
+            self.receive(message, seq)
+        });
+
+        match received {
+            Ok(Received::InOrder(messages)) => {
+                let secret_chats = self.clone();
+
+                // One after the other, as each may change the key of the next
+                Box::new(stream::iter_ok(messages).fold(Vec::new(), move |mut events, message| {
+                    secret_chats.handle_received(message).map(move |event| {
+                        events.extend(event);
+                        events
+                    })
+                }))
+            }
+
+            Ok(Received::Gap(Some((start_seq_no, end_seq_no)))) => {
+                let action = DecryptedMessageAction::Resend {
+                    start_seq_no,
+                    end_seq_no,
+                };
+
+                Box::new(self.send_action(chat_id, action).map(|()| Vec::new()))
+            }
+
+            Ok(Received::Gap(None)) | Ok(Received::Duplicate) => Box::new(future::ok(Vec::new())),
+
+            // Messages were lost or replayed in a way the chat can't recover from
+            Ok(Received::Inconsistent) => Box::new(self.discard(chat_id).and_then(move |()| {
+                let message = format!("secret chat {} has inconsistent sequence numbers", chat_id);
+                Err(message.into())
+            })),
+
+            Err(error) => Box::new(future::err(error)),
+        }
+    }
+
+    /// Handle a message received in order, resolving to the event it makes, if any.
+    fn handle_received(&self, message: SecretMessage) -> SecretFuture<Option<SecretEvent>> {
+        let chat_id = message.chat_id;
+        let action = match message.message {
+            DecryptedMessage::Service { ref action, .. } => action.clone(),
+            DecryptedMessage::Message { .. } => {
+                return Box::new(future::ok(Some(SecretEvent::Message(Box::new(message)))))
+            }
+        };
+
+        let event = SecretEvent::Message(Box::new(message));
+
+        let handled = match action {
+            DecryptedMessageAction::RequestKey { exchange_id, g_a } => {
+                self.handle_request_key(chat_id, exchange_id, g_a.into_vec())
+            }
+
+            DecryptedMessageAction::AcceptKey {
+                exchange_id,
+                g_b,
+                key_fingerprint,
+            } => self.handle_accept_key(chat_id, exchange_id, g_b.into_vec(), key_fingerprint),
+
+            DecryptedMessageAction::CommitKey {
+                exchange_id,
+                key_fingerprint,
+            } => self.handle_commit_key(chat_id, exchange_id, key_fingerprint),
+
+            DecryptedMessageAction::AbortKey { exchange_id } => {
+                let aborted = self.update(chat_id, |chat| {
+                    if chat.exchange.as_ref().map(|exchange| exchange.id) == Some(exchange_id) {
+                        chat.exchange = None;
+                    }
+                });
+
+                Box::new(future::result(aborted))
+            }
+
+            DecryptedMessageAction::NotifyLayer { layer } => {
+                Box::new(future::result(self.update(chat_id, |chat| chat.layer = layer)))
+            }
+
+            DecryptedMessageAction::Noop => Box::new(future::ok(())),
+
+            _ => return Box::new(future::ok(Some(event))),
+        };
+
+        // The messages of the protocol stay within it
+        Box::new(handled.map(move |()| match event {
+            SecretEvent::Message(ref message) if is_protocol(&message.message) => None,
+            event => Some(event),
+        }))
+    }
+
+    /// The other side started replacing the key.
+    fn handle_request_key(&self, chat_id: i32, exchange_id: i64, g_a: Vec<u8>) -> SecretFuture<()> {
+        let ours = self.chats
+            .borrow()
+            .get(&chat_id)
+            .and_then(|chat| chat.exchange.as_ref().map(|exchange| exchange.id));
+
+        // When both sides start at once, the larger exchange identifier goes on
+        if let Some(ours) = ours {
+            if ours > exchange_id {
+                return Box::new(future::ok(()));
+            }
+        }
+
+        let secret_chats = self.clone();
+
+        Box::new(self.dh_config().and_then(move |(config, random)| {
+            let b = exponent(&random);
+            let g_b = config.dh.power(&b);
+            let key = Key::new(config.dh.shared_key(&g_a, &b)?);
+            let key_fingerprint = key.fingerprint;
+
+            secret_chats.update(chat_id, |chat| {
+                chat.exchange = Some(Exchange {
+                    id: exchange_id,
+                    secret: b,
+                    key: Some(key),
+                });
+            })?;
+
+            let action = DecryptedMessageAction::AcceptKey {
+                exchange_id,
+                g_b: g_b.into(),
+                key_fingerprint,
+            };
+
+            Ok(secret_chats.send_action(chat_id, action))
+        }).flatten())
+    }
+
+    /// The other side accepted the key this client requested: switch to it, and tell.
+    fn handle_accept_key(
+        &self,
+        chat_id: i32,
+        exchange_id: i64,
+        g_b: Vec<u8>,
+        key_fingerprint: i64,
+    ) -> SecretFuture<()> {
+        let a = match self.chats.borrow().get(&chat_id).and_then(|chat| chat.exchange.as_ref()) {
+            Some(exchange) if exchange.id == exchange_id && exchange.key.is_none() => {
+                exchange.secret.clone()
+            }
+
+            _ => return Box::new(future::ok(())),
+        };
+
+        let secret_chats = self.clone();
+
+        Box::new(self.finish_exchange(g_b, a).and_then(move |key| {
+            secret_chats.new_key_agreed(chat_id, exchange_id, key, key_fingerprint)
+        }))
+    }
+
+    /// Both sides derived a new key, the other one with `key_fingerprint`.
+    fn new_key_agreed(
+        &self,
+        chat_id: i32,
+        exchange_id: i64,
+        key: Key,
+        key_fingerprint: i64,
+    ) -> SecretFuture<()> {
+        if key.fingerprint != key_fingerprint {
+            let aborted = self.update(chat_id, |chat| chat.exchange = None);
+            if let Err(error) = aborted {
+                return Box::new(future::err(error));
+            }
+
+            return self.send_action(chat_id, DecryptedMessageAction::AbortKey { exchange_id });
+        }
+
+        // The commit is the last message under the previous key
+        let committed = self.send_action(
+            chat_id,
+            DecryptedMessageAction::CommitKey {
+                exchange_id,
+                key_fingerprint,
+            },
+        );
+
+        let switched = self.update(chat_id, |chat| {
+            chat.exchange = None;
+            chat.switch_key(key);
+        });
+
+        match switched {
+            Ok(()) => committed,
+            Err(error) => Box::new(future::err(error)),
+        }
+    }
+
+    /// The other side switched to the key this client accepted: switch too.
+    fn handle_commit_key(
+        &self,
+        chat_id: i32,
+        exchange_id: i64,
+        key_fingerprint: i64,
+    ) -> SecretFuture<()> {
+        let key = match self.chats.borrow().get(&chat_id).and_then(|chat| chat.exchange.as_ref()) {
+            Some(exchange) if exchange.id == exchange_id => exchange.key.clone(),
+            _ => None,
+        };
+
+        let key = match key {
+            Some(ref key) if key.fingerprint == key_fingerprint => key.clone(),
+            _ => return self.send_action(chat_id, DecryptedMessageAction::AbortKey { exchange_id }),
+        };
+
+        let switched = self.update(chat_id, |chat| {
+            chat.exchange = None;
+            chat.switch_key(key);
+        });
+
+        match switched {
+            Ok(()) => self.send_action(chat_id, DecryptedMessageAction::Noop),
+            Err(error) => Box::new(future::err(error)),
+        }
+    }
+
+    /// The key of an exchange started by this client with `exponent`, once the other side
+    /// replied with `g_x`.
+    ///
+    /// The DH configuration is fetched again if the client restarted meanwhile.
+    fn finish_exchange(&self, g_x: Vec<u8>, exponent: Vec<u8>) -> SecretFuture<Key> {
+        let cached = self.dh_config.borrow().clone();
+        let config: SecretFuture<Rc<DhConfig>> = match cached {
+            Some(config) => Box::new(future::ok(config)),
+            None => Box::new(self.dh_config().map(|(config, _)| config)),
+        };
+
+        Box::new(config.and_then(move |config| {
+            config.dh.shared_key(&g_x, &exponent).map(Key::new)
+        }))
+    }
+
+    fn notify_layer(&self, chat_id: i32) -> SecretFuture<()> {
+        self.send_action(chat_id, DecryptedMessageAction::NotifyLayer { layer: LAYER })
+    }
+
+    fn rekey_if_used(&self, chat_id: i32) -> SecretFuture<()> {
+        let used = self.chats.borrow().get(&chat_id).is_some_and(|chat| {
+            chat.used >= REKEY_AFTER && chat.exchange.is_none() && chat.layer >= LAYER
+        });
+
+        if used {
+            self.rekey(chat_id)
+        } else {
+            Box::new(future::ok(()))
+        }
+    }
+
+    /// The DH configuration, with random bytes from the server to mix into exponents.
+    fn dh_config(&self) -> SecretFuture<(Rc<DhConfig>, Vec<u8>)> {
+        let version = self.dh_config
+            .borrow()
+            .as_ref()
+            .map_or(0, |config| config.version);
+
+        let cached = self.dh_config.clone();
+        let query = messages::GetDhConfig {
+            version,
+            random_length: KEY_SIZE as i32,
+        };
+
+        Box::new(self.client.invoke(query).and_then(move |config| match config {
+            messages::DhConfig::DhConfig(config) => {
+                let checked = Rc::new(DhConfig::new(&config)?);
+                *cached.borrow_mut() = Some(checked.clone());
+
+                Ok((checked, config.random.into_vec()))
+            }
+
+            messages::DhConfig::DhConfigNotModified(not_modified) => match *cached.borrow() {
+                Some(ref config) => Ok((config.clone(), not_modified.random.0.clone())),
+                None => Err("the DH configuration is unknown, but wasn't sent".into()),
+            },
+        }))
+    }
+
+    /// Serialize and encrypt a message under the current key of the chat, counting it.
+    fn encrypt(
+        &self,
+        chat_id: i32,
+        message: &DecryptedMessage,
+    ) -> error::Result<(schema::InputEncryptedChat, Bytes)> {
+        let peer = self.input_chat(chat_id)?;
+
+        let mut chats = self.chats.borrow_mut();
+        let chat = chats.get_mut(&chat_id).ok_or_else(|| unknown_chat(chat_id))?;
+
+        let key = match chat.key {
+            Some(ref key) if chat.state == ChatState::Ready => key.clone(),
+            _ => bail!("secret chat {} isn't ready", chat_id),
+        };
+
+        let layer = DecryptedMessageLayer {
+            random_bytes: random_bytes(15 + rand::thread_rng().gen_range(0..16)).into(),
+            layer: LAYER,
+            in_seq_no: 2 * chat.in_seq + parity(!chat.admin),
+            out_seq_no: 2 * chat.out_seq + parity(chat.admin),
+            message: message.clone(),
+        };
+
+        let data = encrypt(&key, &layer.to_vec()?);
+
+        chat.out_seq += 1;
+        chat.used += 1;
+        save(&*self.store, chat)?;
+
+        Ok((peer, data.into()))
+    }
+
+    /// Decrypt a message, along with its `in_seq_no` and `out_seq_no` if it has them.
+    fn decrypt(
+        &self,
+        chat_id: i32,
+        data: &[u8],
+    ) -> error::Result<(DecryptedMessage, Option<(i32, i32)>)> {
+        let chats = self.chats.borrow();
+        let chat = chats.get(&chat_id).ok_or_else(|| unknown_chat(chat_id))?;
+
+        if data.len() < 8 {
+            bail!(ErrorKind::UnexpectedEof);
+        }
+
+        // The previous key or the one being exchanged may still be in use on the other side
+        let fingerprint = LittleEndian::read_i64(data);
+        let key = chat.key
+            .iter()
+            .chain(&chat.previous_key)
+            .chain(chat.exchange.iter().filter_map(|exchange| exchange.key.as_ref()))
+            .find(|key| key.fingerprint == fingerprint)
+            .ok_or("a message of a secret chat under an unknown key")?;
+
+        let payload = decrypt(key, data)?;
+
+        if de::from_slice::<u32>(&payload)? == DecryptedMessageLayer::ID {
+            let layer: DecryptedMessageLayer = de::from_slice(&payload)?;
+            Ok((layer.message, Some((layer.in_seq_no, layer.out_seq_no))))
+        } else {
+            // A client of a layer before 17, without sequence numbers
+            Ok((de::from_slice(&payload)?, None))
+        }
+    }
+
+    /// Place a message in the sequence of its chat, counting those which come in order.
+    fn receive(&self, message: SecretMessage, seq: Option<(i32, i32)>) -> error::Result<Received> {
+        let mut chats = self.chats.borrow_mut();
+        let chat = chats
+            .get_mut(&message.chat_id)
+            .ok_or_else(|| unknown_chat(message.chat_id))?;
+
+        let (in_seq_no, out_seq_no) = match seq {
+            Some(seq) => seq,
+            None => {
+                chat.count_received(None);
+                save(&*self.store, chat)?;
+
+                return Ok(Received::InOrder(vec![message]));
+            }
+        };
+
+        let expected = chat.expected_seq_no();
+
+        if out_seq_no < expected || chat.held.contains_key(&out_seq_no) {
+            return Ok(Received::Duplicate);
+        }
+
+        if out_seq_no > expected {
+            chat.held.insert(out_seq_no, (in_seq_no, message));
+
+            // The messages missing are asked for once, but for those held already
+            let mut start_seq_no = chat.resent_until
+                .map_or(expected, |until| expected.max(until + 2));
+            while chat.held.contains_key(&start_seq_no) {
+                start_seq_no += 2;
+            }
+
+            let end_seq_no = out_seq_no - 2;
+            let gap = if start_seq_no > end_seq_no {
+                None
+            } else {
+                chat.resent_until = Some(end_seq_no);
+                Some((start_seq_no, end_seq_no))
+            };
+
+            save(&*self.store, chat)?;
+            return Ok(Received::Gap(gap));
+        }
+
+        // Then the messages held behind it, up to the next gap, all of which are checked
+        // before the chat changes
+        let held = (1..)
+            .map(|count| expected + 2 * count)
+            .take_while(|out_seq_no| chat.held.contains_key(out_seq_no))
+            .collect::<Vec<_>>();
+
+        let in_seq_nos = held.iter().map(|out_seq_no| chat.held[out_seq_no].0);
+        if !chat.is_consistent(Some(in_seq_no).into_iter().chain(in_seq_nos)) {
+            return Ok(Received::Inconsistent);
+        }
+
+        chat.count_received(Some(in_seq_no));
+        let mut received = vec![message];
+
+        for out_seq_no in held {
+            let (in_seq_no, message) = chat.held.remove(&out_seq_no).unwrap();
+            chat.count_received(Some(in_seq_no));
+            received.push(message);
+        }
+
+        save(&*self.store, chat)?;
+        Ok(Received::InOrder(received))
+    }
+
+    fn input_chat(&self, chat_id: i32) -> error::Result<schema::InputEncryptedChat> {
+        match self.chats.borrow().get(&chat_id) {
+            Some(chat) => Ok(schema::InputEncryptedChat {
+                chat_id,
+                access_hash: chat.access_hash,
+            }),
+
+            None => Err(unknown_chat(chat_id)),
+        }
+    }
+
+    fn insert(&self, chat: Chat) -> error::Result<()> {
+        save(&*self.store, &chat)?;
+        self.chats.borrow_mut().insert(chat.id, chat);
+        self.save_ids()
+    }
+
+    fn update<F: FnOnce(&mut Chat)>(&self, chat_id: i32, f: F) -> error::Result<()> {
+        let mut chats = self.chats.borrow_mut();
+        let chat = chats.get_mut(&chat_id).ok_or_else(|| unknown_chat(chat_id))?;
+
+        f(chat);
+        save(&*self.store, chat)
+    }
+
+    /// Forget a chat, and its key with it.
+    fn remove(&self, chat_id: i32) -> error::Result<()> {
+        if self.chats.borrow_mut().remove(&chat_id).is_some() {
+            // The store has no removal: the key is overwritten
+            self.store.save(&chat_key(chat_id), &[])?;
+            self.save_ids()?;
+        }
+
+        Ok(())
+    }
+
+    fn save_ids(&self) -> error::Result<()> {
+        let ids = StoredIds { ids: self.chat_ids() };
+        self.store.save(STORE_KEY, &ids.to_vec()?)
+    }
+}
+
+impl fmt::Debug for SecretChats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecretChats")
+            .field("chat_ids", &self.chat_ids())
+            .finish()
+    }
+}
+
+#[derive(Clone, Default)]
+struct Chat {
+    id: i32,
+    access_hash: i64,
+
+    /// The user on the other side.
+    user_id: i32,
+
+    /// Whether this client requested the chat.
+    admin: bool,
+
+    state: ChatState,
+
+    /// Layer of the other side, 0 until it notifies its own.
+    layer: i32,
+
+    /// Number of messages received and sent.
+    in_seq: i32,
+    out_seq: i32,
+
+    key: Option<Key>,
+    previous_key: Option<Key>,
+    exchange: Option<Exchange>,
+
+    /// Number of messages sent and received under the current key.
+    used: u32,
+
+    /// The last `in_seq_no` of the other side, which can't go back.
+    peer_in_seq_no: i32,
+
+    /// Messages received past a gap, by `out_seq_no`, with their `in_seq_no`.
+    held: BTreeMap<i32, (i32, SecretMessage)>,
+
+    /// The last `out_seq_no` of the messages asked for again.
+    resent_until: Option<i32>,
+}
+
+/// Where a message received stands in the sequence of its chat.
+enum Received {
+    /// Messages to handle, in order: the one received, and those held behind it.
+    InOrder(Vec<SecretMessage>),
+
+    /// A message received again.
+    Duplicate,
+
+    /// A message held past missing ones, the `out_seq_no` of the first and last of which
+    /// are to be asked for, unless they already were.
+    Gap(Option<(i32, i32)>),
+
+    /// Sequence numbers which can't be those of the other side.
+    Inconsistent,
+}
+
+#[derive(Clone, PartialEq, Default)]
+enum ChatState {
+    /// Requested by the other side, which sent `g_a`.
+    Requested { g_a: Vec<u8> },
+
+    /// Requested by this client, with the exponent `a`.
+    Waiting { a: Vec<u8> },
+
+    #[default]
+    Ready,
+}
+
+#[derive(Clone)]
+struct Key {
+    bytes: Vec<u8>,
+    fingerprint: i64,
+}
+
+impl Key {
+    fn new(bytes: Vec<u8>) -> Key {
+        let hash = crypto::sha1(&[&bytes]);
+
+        Key {
+            fingerprint: LittleEndian::read_i64(&hash[12..]),
+            bytes,
+        }
+    }
+}
+
+/// A replacement of the key under way.
+#[derive(Clone)]
+struct Exchange {
+    id: i64,
+
+    /// This side's exponent.
+    secret: Vec<u8>,
+
+    /// The new key, once known to the side which accepted the exchange.
+    key: Option<Key>,
+}
+
+impl Chat {
+    /// `out_seq_no` of the next message of the other side.
+    fn expected_seq_no(&self) -> i32 {
+        2 * self.in_seq + parity(!self.admin)
+    }
+
+    /// Whether the other side may have sent messages with `in_seq_nos`, in order: it can't
+    /// claim to have received more messages than were sent, or fewer than it did before.
+    fn is_consistent<I: IntoIterator<Item = i32>>(&self, in_seq_nos: I) -> bool {
+        let sent = 2 * self.out_seq + parity(self.admin);
+        let mut last = self.peer_in_seq_no;
+
+        in_seq_nos.into_iter().all(|in_seq_no| {
+            let consistent = last <= in_seq_no && in_seq_no <= sent;
+            last = in_seq_no;
+            consistent
+        })
+    }
+
+    /// Count a message received in order, with its `in_seq_no` if it has one.
+    fn count_received(&mut self, in_seq_no: Option<i32>) {
+        if let Some(in_seq_no) = in_seq_no {
+            self.peer_in_seq_no = in_seq_no;
+        }
+
+        self.in_seq += 1;
+        self.used += 1;
+    }
+
+    fn switch_key(&mut self, key: Key) {
+        self.previous_key = self.key.take();
+        self.key = Some(key);
+        self.used = 0;
+    }
+
+    fn to_stored(&self) -> StoredChat {
+        let (state, g, secret) = match self.state {
+            ChatState::Requested { ref g_a } => (0, g_a.clone(), Vec::new()),
+            ChatState::Waiting { ref a } => (1, Vec::new(), a.clone()),
+            ChatState::Ready => (2, Vec::new(), Vec::new()),
+        };
+
+        let key_bytes = |key: &Option<Key>| -> Bytes {
+            key.as_ref().map_or_else(Vec::new, |key| key.bytes.clone()).into()
+        };
+
+        StoredChat {
+            id: self.id,
+            access_hash: self.access_hash,
+            user_id: self.user_id,
+            admin: self.admin,
+            state,
+            g: g.into(),
+            secret: secret.into(),
+            layer: self.layer,
+            in_seq: self.in_seq,
+            out_seq: self.out_seq,
+            key: key_bytes(&self.key),
+            previous_key: key_bytes(&self.previous_key),
+            exchange_id: self.exchange.as_ref().map_or(0, |exchange| exchange.id),
+            exchange_secret: self.exchange
+                .as_ref()
+                .map_or_else(Vec::new, |exchange| exchange.secret.clone())
+                .into(),
+            exchange_key: key_bytes(&self.exchange
+                .as_ref()
+                .and_then(|exchange| exchange.key.clone())),
+            used: self.used as i32,
+            peer_in_seq_no: self.peer_in_seq_no,
+            resent_until: self.resent_until.unwrap_or(-1),
+            held: self.held
+                .iter()
+                .map(|(&out_seq_no, &(in_seq_no, ref message))| StoredMessage {
+                    out_seq_no,
+                    in_seq_no,
+                    random_id: message.random_id,
+                    date: message.date,
+                    message: message.message.clone(),
+                    file: message
+                        .file
+                        .clone()
+                        .map_or(schema::EncryptedFile::EncryptedFileEmpty, Into::into),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_stored(stored: StoredChat) -> Chat {
+        let key = |bytes: Bytes| {
+            Some(bytes.into_vec())
+                .filter(|bytes| !bytes.is_empty())
+                .map(Key::new)
+        };
+
+        let state = match stored.state {
+            0 => ChatState::Requested { g_a: stored.g.into_vec() },
+            1 => ChatState::Waiting { a: stored.secret.into_vec() },
+            _ => ChatState::Ready,
+        };
+
+        let exchange = if stored.exchange_secret.is_empty() {
+            None
+        } else {
+            Some(Exchange {
+                id: stored.exchange_id,
+                secret: stored.exchange_secret.into_vec(),
+                key: key(stored.exchange_key),
+            })
+        };
+
+        let chat_id = stored.id;
+
+        Chat {
+            id: chat_id,
+            access_hash: stored.access_hash,
+            user_id: stored.user_id,
+            admin: stored.admin,
+            state,
+            layer: stored.layer,
+            in_seq: stored.in_seq,
+            out_seq: stored.out_seq,
+            key: key(stored.key),
+            previous_key: key(stored.previous_key),
+            exchange,
+            used: stored.used as u32,
+            peer_in_seq_no: stored.peer_in_seq_no,
+            held: stored
+                .held
+                .into_iter()
+                .map(|held| {
+                    let file = match held.file {
+                        schema::EncryptedFile::EncryptedFile(file) => Some(file),
+                        schema::EncryptedFile::EncryptedFileEmpty => None,
+                    };
+
+                    let message = SecretMessage {
+                        chat_id,
+                        random_id: held.random_id,
+                        date: held.date,
+                        message: held.message,
+                        file,
+                    };
+
+                    (held.out_seq_no, (held.in_seq_no, message))
+                })
+                .collect(),
+            resent_until: Some(stored.resent_until).filter(|&until| until >= 0),
+        }
+    }
+}
+
+/// A DH configuration whose prime and generator were checked.
+struct DhConfig {
+    dh: Dh,
+    version: i32,
+}
+
+impl DhConfig {
+    fn new(config: &messages::DhConfig_) -> error::Result<DhConfig> {
+        Ok(DhConfig {
+            dh: Dh::new(config.g, &config.p)?,
+            version: config.version,
+        })
+    }
+}
+
+/// A secret exponent: local random bytes, mixed with those of the server in case the
+/// local generator is weak.
+fn exponent(server_random: &[u8]) -> Vec<u8> {
+    let mut exponent = random_bytes(KEY_SIZE);
+    for (byte, server) in exponent.iter_mut().zip(server_random) {
+        *byte ^= server;
+    }
+
+    exponent
+}
+
+/// Parity of the sequence numbers of a side: odd for the one which requested the chat.
+fn parity(admin: bool) -> i32 {
+    if admin {
+        1
+    } else {
+        0
+    }
+}
+
+/// `key_fingerprint + msg_key + encrypted_data`, where the data is the length of the
+/// payload, the payload and random padding.
+fn encrypt(key: &Key, payload: &[u8]) -> Vec<u8> {
+    let mut plain = Vec::with_capacity(4 + payload.len() + 16);
+    plain.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    plain.extend_from_slice(payload);
+
+    // The message key is the hash of the data without its padding
+    let msg_key = crypto::sha1(&[&plain])[4..].to_vec();
+    let padding = (16 - plain.len() % 16) % 16;
+    plain.extend(random_bytes(padding));
+
+    let (aes_key, aes_iv) = crypto::aes_key_iv(&key.bytes, &msg_key, 0);
+    Ige::new(&aes_key, &aes_iv).encrypt(&mut plain);
+
+    let mut data = Vec::with_capacity(8 + 16 + plain.len());
+    data.extend_from_slice(&key.fingerprint.to_le_bytes());
+    data.extend(msg_key);
+    data.extend(plain);
+    data
+}
+
+fn decrypt(key: &Key, data: &[u8]) -> error::Result<Vec<u8>> {
+    if data.len() < 8 + 16 + 16 || !(data.len() - 8 - 16).is_multiple_of(16) {
+        bail!("a message of a secret chat of a wrong size");
+    }
+
+    let msg_key = &data[8..24];
+    let mut plain = data[24..].to_vec();
+
+    let (aes_key, aes_iv) = crypto::aes_key_iv(&key.bytes, msg_key, 0);
+    Ige::new(&aes_key, &aes_iv).decrypt(&mut plain);
+
+    let len = LittleEndian::read_u32(&plain) as usize;
+    if len > plain.len() - 4 || crypto::sha1(&[&plain[..4 + len]])[4..] != *msg_key {
+        bail!("a message of a secret chat failed to decrypt");
+    }
+
+    Ok(plain[4..4 + len].to_vec())
+}
+
+/// The key, IV and size of the file of a media, if it has one.
+fn media_file(media: &DecryptedMessageMedia) -> Option<(&[u8], &[u8], i32)> {
+    match *media {
+        DecryptedMessageMedia::Photo {
+            ref key,
+            ref iv,
+            size,
+            ..
+        }
+        | DecryptedMessageMedia::Video {
+            ref key,
+            ref iv,
+            size,
+            ..
+        }
+        | DecryptedMessageMedia::Audio {
+            ref key,
+            ref iv,
+            size,
+            ..
+        }
+        | DecryptedMessageMedia::Document {
+            ref key,
+            ref iv,
+            size,
+            ..
+        } => Some((key, iv, size)),
+
+        _ => None,
+    }
+}
+
+fn is_protocol(message: &DecryptedMessage) -> bool {
+    match *message {
+        DecryptedMessage::Service { ref action, .. } => matches!(
+            *action,
+            DecryptedMessageAction::RequestKey { .. }
+                | DecryptedMessageAction::AcceptKey { .. }
+                | DecryptedMessageAction::CommitKey { .. }
+                | DecryptedMessageAction::AbortKey { .. }
+                | DecryptedMessageAction::Noop
+        ),
+
+        DecryptedMessage::Message { .. } => false,
+    }
+}
+
+fn save(store: &dyn SessionStore, chat: &Chat) -> error::Result<()> {
+    store.save(&chat_key(chat.id), &chat.to_stored().to_vec()?)
+}
+
+fn chat_key(chat_id: i32) -> String {
+    format!("secret_chat.{}", chat_id)
+}
+
+fn unknown_chat(chat_id: i32) -> error::Error {
+    ErrorKind::UnknownPeer(format!("secret chat {}", chat_id)).into()
+}
+
+// The messages of secret chats are encrypted by the clients, so their types aren't part
+// of the schema of the API and are declared here
+
+/// `decryptedMessageLayer`: a message, with the layer and sequence numbers of its sender.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[id = 0x1be31789]
+#[tl(name = "decryptedMessageLayer", combinator = "decryptedMessageLayer random_bytes:bytes \
+     layer:int in_seq_no:int out_seq_no:int message:DecryptedMessage = DecryptedMessageLayer")]
+pub struct DecryptedMessageLayer {
+    pub random_bytes: Bytes,
+    pub layer: i32,
+    pub in_seq_no: i32,
+    pub out_seq_no: i32,
+    pub message: DecryptedMessage,
+}
+
+impl DecryptedMessageLayer {
+    const ID: u32 = 0x1be31789;
+}
+
+/// `DecryptedMessage`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[tl(name = "DecryptedMessage")]
+pub enum DecryptedMessage {
+    #[id = 0x204d3878]
+    #[tl(combinator = "decryptedMessage random_id:long ttl:int message:string \
+                       media:DecryptedMessageMedia = DecryptedMessage")]
+    Message {
+        random_id: i64,
+
+        /// Seconds the message lives for once read, or 0.
+        ttl: i32,
+        message: String,
+        media: DecryptedMessageMedia,
+    },
+
+    #[id = 0x73164160]
+    #[tl(combinator = "decryptedMessageService random_id:long action:DecryptedMessageAction \
+                       = DecryptedMessage")]
+    Service {
+        random_id: i64,
+        action: DecryptedMessageAction,
+    },
+}
+
+/// `DecryptedMessageMedia`: the attachment of a message, whose file is encrypted with
+/// `key` and `iv`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[tl(name = "DecryptedMessageMedia")]
+pub enum DecryptedMessageMedia {
+    #[id = 0x089f5c4a]
+    #[tl(combinator = "decryptedMessageMediaEmpty = DecryptedMessageMedia")]
+    Empty,
+
+    #[id = 0x32798a8c]
+    #[tl(combinator = "decryptedMessageMediaPhoto thumb:bytes thumb_w:int thumb_h:int w:int \
+                       h:int size:int key:bytes iv:bytes = DecryptedMessageMedia")]
+    Photo {
+        thumb: Bytes,
+        thumb_w: i32,
+        thumb_h: i32,
+        w: i32,
+        h: i32,
+        size: i32,
+        key: Bytes,
+        iv: Bytes,
+    },
+
+    #[id = 0x524a415d]
+    #[tl(combinator = "decryptedMessageMediaVideo thumb:bytes thumb_w:int thumb_h:int \
+                       duration:int mime_type:string w:int h:int size:int key:bytes iv:bytes \
+                       = DecryptedMessageMedia")]
+    Video {
+        thumb: Bytes,
+        thumb_w: i32,
+        thumb_h: i32,
+        duration: i32,
+        mime_type: String,
+        w: i32,
+        h: i32,
+        size: i32,
+        key: Bytes,
+        iv: Bytes,
+    },
+
+    #[id = 0x57e0a9cb]
+    #[tl(combinator = "decryptedMessageMediaAudio duration:int mime_type:string size:int \
+                       key:bytes iv:bytes = DecryptedMessageMedia")]
+    Audio {
+        duration: i32,
+        mime_type: String,
+        size: i32,
+        key: Bytes,
+        iv: Bytes,
+    },
+
+    #[id = 0xb095434b]
+    #[tl(combinator = "decryptedMessageMediaDocument thumb:bytes thumb_w:int thumb_h:int \
+                       file_name:string mime_type:string size:int key:bytes iv:bytes \
+                       = DecryptedMessageMedia")]
+    Document {
+        thumb: Bytes,
+        thumb_w: i32,
+        thumb_h: i32,
+        file_name: String,
+        mime_type: String,
+        size: i32,
+        key: Bytes,
+        iv: Bytes,
+    },
+
+    #[id = 0x35480a59]
+    #[tl(combinator = "decryptedMessageMediaGeoPoint lat:double long:double \
+                       = DecryptedMessageMedia")]
+    GeoPoint { lat: f64, long: f64 },
+
+    #[id = 0x588a0a97]
+    #[tl(combinator = "decryptedMessageMediaContact phone_number:string first_name:string \
+                       last_name:string user_id:int = DecryptedMessageMedia")]
+    Contact {
+        phone_number: String,
+        first_name: String,
+        last_name: String,
+        user_id: i32,
+    },
+}
+
+/// `DecryptedMessageAction`: what a service message does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[tl(name = "DecryptedMessageAction")]
+pub enum DecryptedMessageAction {
+    #[id = 0xa1733aec]
+    #[tl(combinator = "decryptedMessageActionSetMessageTTL ttl_seconds:int \
+                       = DecryptedMessageAction")]
+    SetMessageTtl { ttl_seconds: i32 },
+
+    #[id = 0x0c4f40be]
+    #[tl(combinator = "decryptedMessageActionReadMessages random_ids:Vector<long> \
+                       = DecryptedMessageAction")]
+    ReadMessages { random_ids: Vec<i64> },
+
+    #[id = 0x65614304]
+    #[tl(combinator = "decryptedMessageActionDeleteMessages random_ids:Vector<long> \
+                       = DecryptedMessageAction")]
+    DeleteMessages { random_ids: Vec<i64> },
+
+    #[id = 0x8ac1f475]
+    #[tl(combinator = "decryptedMessageActionScreenshotMessages random_ids:Vector<long> \
+                       = DecryptedMessageAction")]
+    ScreenshotMessages { random_ids: Vec<i64> },
+
+    #[id = 0x6719e45c]
+    #[tl(combinator = "decryptedMessageActionFlushHistory = DecryptedMessageAction")]
+    FlushHistory,
+
+    /// Asks for the messages of sequence numbers `start_seq_no` to `end_seq_no` again.
+    #[id = 0x511110b0]
+    #[tl(combinator = "decryptedMessageActionResend start_seq_no:int end_seq_no:int \
+                       = DecryptedMessageAction")]
+    Resend { start_seq_no: i32, end_seq_no: i32 },
+
+    #[id = 0xf3048883]
+    #[tl(combinator = "decryptedMessageActionNotifyLayer layer:int = DecryptedMessageAction")]
+    NotifyLayer { layer: i32 },
+
+    #[id = 0xf3c9611b]
+    #[tl(combinator = "decryptedMessageActionRequestKey exchange_id:long g_a:bytes \
+                       = DecryptedMessageAction")]
+    RequestKey { exchange_id: i64, g_a: Bytes },
+
+    #[id = 0x6fe1735b]
+    #[tl(combinator = "decryptedMessageActionAcceptKey exchange_id:long g_b:bytes \
+                       key_fingerprint:long = DecryptedMessageAction")]
+    AcceptKey {
+        exchange_id: i64,
+        g_b: Bytes,
+        key_fingerprint: i64,
+    },
+
+    #[id = 0xdd05ec6b]
+    #[tl(combinator = "decryptedMessageActionAbortKey exchange_id:long = DecryptedMessageAction")]
+    AbortKey { exchange_id: i64 },
+
+    #[id = 0xec2e0b9b]
+    #[tl(combinator = "decryptedMessageActionCommitKey exchange_id:long key_fingerprint:long \
+                       = DecryptedMessageAction")]
+    CommitKey {
+        exchange_id: i64,
+        key_fingerprint: i64,
+    },
+
+    #[id = 0xa82fdd63]
+    #[tl(combinator = "decryptedMessageActionNoop = DecryptedMessageAction")]
+    Noop,
+}
+
+/// The identifiers of the chats, as saved in a `SessionStore`.
+#[derive(Serialize, Deserialize)]
+#[id = 0x73656372]
+struct StoredIds {
+    ids: Vec<i32>,
+}
+
+/// A chat, as saved in a `SessionStore`.
+#[derive(Serialize, Deserialize)]
+#[id = 0x63686174]
+struct StoredChat {
+    id: i32,
+    access_hash: i64,
+    user_id: i32,
+    admin: bool,
+
+    /// 0 if requested by the other side, with `g`; 1 if waiting for it, with `secret`;
+    /// 2 if ready.
+    state: i32,
+    g: Bytes,
+    secret: Bytes,
+
+    layer: i32,
+    in_seq: i32,
+    out_seq: i32,
+
+    /// Empty when there is none, as for the keys below.
+    key: Bytes,
+    previous_key: Bytes,
+    exchange_id: i64,
+    exchange_secret: Bytes,
+    exchange_key: Bytes,
+    used: i32,
+
+    peer_in_seq_no: i32,
+
+    /// -1 when no messages were asked for again.
+    resent_until: i32,
+    held: Vec<StoredMessage>,
+}
+
+/// A message held past a gap, as saved in a `SessionStore`.
+#[derive(Serialize, Deserialize)]
+#[id = 0x68656c64]
+struct StoredMessage {
+    out_seq_no: i32,
+    in_seq_no: i32,
+    random_id: i64,
+    date: i32,
+    message: DecryptedMessage,
+    file: schema::EncryptedFile,
+}
@@ -1,178 +1,306 @@
-use std::any::Any;
+use std::io::Write;
 
-use byteorder::{WriteBytesExt, LittleEndian, BigEndian};
-use extprim::i128::i128;
-use extprim::u128::u128;
+use byteorder::{WriteBytesExt, LittleEndian};
 
-use error;
+use error::{self, ErrorKind};
+use {Bytes, Int128, Int256};
 
 macro_rules! impl_serialize {
-    ($type:path, $write:path) => {
+    ($type:path, $write:path, $len:expr) => {
         impl Serialize for $type {
             #[inline]
-            fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
+            fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
                 $write(buffer, *self)?;
 
                 Ok(())
             }
+
+            #[inline]
+            fn serialized_len(&self) -> usize {
+                $len
+            }
         }
     };
 }
 
 pub trait Serialize {
-    /// Serialize to the passed buffer.
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()>;
+    /// Serialize to the passed writer.
+    ///
+    /// Values are written piece by piece, so large payloads (e.g. the `bytes` of
+    /// `upload.saveFilePart`) are never copied into an intermediate buffer.
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()>;
+
+    /// Number of bytes `serialize_to` writes.
+    ///
+    /// Lets lengths be written up front and buffers be allocated once.
+    fn serialized_len(&self) -> usize;
+
+    /// Serialize into a new buffer of the exact size.
+    fn to_vec(&self) -> error::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(self.serialized_len());
+        self.serialize_to(&mut buffer)?;
+
+        Ok(buffer)
+    }
 }
 
-impl Serialize for bool {
+/// A constructor which can be serialized without its identifier, as it is when its
+/// type is referred to as bare (e.g. `%Message` or `future_salt`).
+pub trait SerializeBare {
+    /// Serialize the fields following the constructor identifier.
+    fn serialize_bare_to<W: Write>(&self, buffer: &mut W) -> error::Result<()>;
+
+    /// Number of bytes `serialize_bare_to` writes.
+    fn serialized_bare_len(&self) -> usize;
+}
+
+/// Placeholder for a `#` parameter.
+///
+/// The value of the parameter is not stored but computed from the conditional fields
+/// referring to it when serializing, so it can never disagree with them.
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags;
+
+/// A conditional field, present only when a bit of a `#` parameter is set
+/// (e.g. `flags.0?string`).
+pub trait Flag {
+    /// Whether the bit of this field is set.
+    fn is_set(&self) -> bool;
+
+    /// Serialize to the passed writer if the field is present.
+    fn serialize_flagged_to<W: Write>(&self, buffer: &mut W) -> error::Result<()>;
+
+    /// Number of bytes `serialize_flagged_to` writes.
+    fn flagged_len(&self) -> usize;
+}
+
+/// `flags.N?true` fields are only represented by their bit.
+impl Flag for bool {
     #[inline]
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        if *self {
-            buffer.write_i32::<LittleEndian>(-1720552011)?;
-        } else {
-            buffer.write_i32::<LittleEndian>(-1132882121)?;
-        }
+    fn is_set(&self) -> bool {
+        *self
+    }
 
+    #[inline]
+    fn serialize_flagged_to<W: Write>(&self, _buffer: &mut W) -> error::Result<()> {
         Ok(())
     }
+
+    #[inline]
+    fn flagged_len(&self) -> usize {
+        0
+    }
 }
 
-impl Serialize for i8 {
+impl<T: Serialize> Flag for Option<T> {
     #[inline]
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        buffer.push(*self as u8);
+    fn is_set(&self) -> bool {
+        self.is_some()
+    }
 
-        Ok(())
+    #[inline]
+    fn serialize_flagged_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        match *self {
+            Some(ref value) => value.serialize_to(buffer),
+            None => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn flagged_len(&self) -> usize {
+        self.as_ref().map_or(0, Serialize::serialized_len)
     }
 }
 
-impl Serialize for u8 {
+impl Serialize for bool {
     #[inline]
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        buffer.push(*self);
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        if *self {
+            buffer.write_i32::<LittleEndian>(-1720552011)?;
+        } else {
+            buffer.write_i32::<LittleEndian>(-1132882121)?;
+        }
 
         Ok(())
     }
+
+    #[inline]
+    fn serialized_len(&self) -> usize {
+        4
+    }
 }
 
-impl_serialize!(i16, WriteBytesExt::write_i16<LittleEndian>);
-impl_serialize!(i32, WriteBytesExt::write_i32<LittleEndian>);
-impl_serialize!(i64, WriteBytesExt::write_i64<LittleEndian>);
+impl_serialize!(i8, WriteBytesExt::write_i8, 1);
+impl_serialize!(i16, WriteBytesExt::write_i16<LittleEndian>, 2);
+impl_serialize!(i32, WriteBytesExt::write_i32<LittleEndian>, 4);
+impl_serialize!(i64, WriteBytesExt::write_i64<LittleEndian>, 8);
+
+impl_serialize!(u8, WriteBytesExt::write_u8, 1);
+impl_serialize!(u16, WriteBytesExt::write_u16<LittleEndian>, 2);
+impl_serialize!(u32, WriteBytesExt::write_u32<LittleEndian>, 4);
+impl_serialize!(u64, WriteBytesExt::write_u64<LittleEndian>, 8);
+
+impl_serialize!(f32, WriteBytesExt::write_f32<LittleEndian>, 4);
+impl_serialize!(f64, WriteBytesExt::write_f64<LittleEndian>, 8);
 
-impl_serialize!(u16, WriteBytesExt::write_u16<LittleEndian>);
-impl_serialize!(u32, WriteBytesExt::write_u32<LittleEndian>);
-impl_serialize!(u64, WriteBytesExt::write_u64<LittleEndian>);
+impl Serialize for Int128 {
+    #[inline]
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        buffer.write_all(self.as_bytes())?;
 
-impl_serialize!(f32, WriteBytesExt::write_f32<LittleEndian>);
-impl_serialize!(f64, WriteBytesExt::write_f64<LittleEndian>);
+        Ok(())
+    }
 
-impl Serialize for i128 {
     #[inline]
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        self.as_u128().serialize_to(buffer)
+    fn serialized_len(&self) -> usize {
+        16
     }
 }
 
-impl Serialize for u128 {
+impl Serialize for Int256 {
     #[inline]
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        /* TODO: maybe should be
-         *     ((self.high64() >> 32) as u32).serialize_to(buffer);
-         *     (self.high64() as u32).serialize_to(buffer);
-         *     ((self.low64() >> 32) as u32).serialize_to(buffer);
-         *     (self.low64() as u32).serialize_to(buffer);
-         * because https://core.telegram.org/schema/mtproto defines int128 as
-         *     int128 4*[ int ] = Int128;
-         * but this example: https://core.telegram.org/mtproto/samples-auth_key
-         * shows that this implementation is correct.
-         */
-        buffer.write_u64::<BigEndian>(self.high64())?;
-        buffer.write_u64::<BigEndian>(self.low64())?;
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        buffer.write_all(self.as_bytes())?;
 
         Ok(())
     }
-}
 
-impl Serialize for (i128, i128) {
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        /* Currently assumes that int128 is correctly serialized and uses it
-         * under the hood.
-         * Here is how https://core.telegram.org/schema/mtproto defines int256:
-         *     int256 8*[ int ] = Int256;
-         * So here we will do big-endian relatively to int128.
-         */
-        self.1.serialize_to(buffer)?;
-        self.0.serialize_to(buffer)?;
+    #[inline]
+    fn serialized_len(&self) -> usize {
+        32
+    }
+}
 
-        Ok(())
+/// The longest string or bytes value, whose length fits in 3 bytes.
+const MAX_BYTES_LEN: usize = 0xff_ffff;
+
+/// Serialize the contents of a `string` or `bytes` value.
+fn serialize_bytes_to<W: Write>(bytes: &[u8], buffer: &mut W) -> error::Result<()> {
+    let len = bytes.len();
+
+    if len <= 253 {
+        // If L <= 253, the serialization contains one byte with the value of L,
+        // then L bytes of the string followed by 0 to 3 characters containing 0,
+        // such that the overall length of the value be divisible by 4,
+        // whereupon all of this is interpreted as a sequence
+        // of int(L/4)+1 32-bit little-endian integers.
+
+        buffer.write_u8(len as u8)?;
+    } else if len <= MAX_BYTES_LEN {
+        // If L >= 254, the serialization contains byte 254, followed by 3
+        // bytes with the string length L in little-endian order, followed by L
+        // bytes of the string, further followed by 0 to 3 null padding bytes.
+
+        buffer.write_u8(254)?;
+        buffer.write_uint::<LittleEndian>(len as u64, 3)?;
+    } else {
+        bail!(ErrorKind::ValueTooLong(len));
     }
+
+    // Write each character in the string
+    buffer.write_all(bytes)?;
+
+    // [...] string followed by 0 to 3 characters containing 0,
+    // such that the overall length of the value be divisible by 4 [...]
+    let padding = bytes_len(len) - header_len(len) - len;
+    buffer.write_all(&[0; 3][..padding])?;
+
+    Ok(())
 }
 
-impl Serialize for String {
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        let len = self.len();
+/// Length of the header of a `string` or `bytes` value of `len` bytes.
+fn header_len(len: usize) -> usize {
+    if len <= 253 {
+        1
+    } else {
+        4
+    }
+}
 
-        if len <= 253 {
-            // If L <= 253, the serialization contains one byte with the value of L,
-            // then L bytes of the string followed by 0 to 3 characters containing 0,
-            // such that the overall length of the value be divisible by 4,
-            // whereupon all of this is interpreted as a sequence
-            // of int(L/4)+1 32-bit little-endian integers.
+/// Number of bytes `serialize_bytes_to` writes for `len` bytes.
+fn bytes_len(len: usize) -> usize {
+    let header = header_len(len);
+    let padding = (4 - (header + len) % 4) % 4;
 
-            buffer.push(len as u8);
-        } else {
-            // If L >= 254, the serialization contains byte 254, followed by 3
-            // bytes with the string length L in little-endian order, followed by L
-            // bytes of the string, further followed by 0 to 3 null padding bytes.
+    header + len + padding
+}
 
-            buffer.push(254);
-            buffer.write_uint::<LittleEndian>(len as u64, 3)?;
-        }
+impl Serialize for String {
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        serialize_bytes_to(self.as_bytes(), buffer)
+    }
 
-        // Write each character in the string
-        buffer.extend(self.as_bytes());
+    fn serialized_len(&self) -> usize {
+        bytes_len(self.len())
+    }
+}
 
-        // [...] string followed by 0 to 3 characters containing 0,
-        // such that the overall length of the value be divisible by 4 [...]
-        let rem = len % 4;
-        if rem > 0 {
-            for _ in 0..(4 - rem) {
-                buffer.push(0);
-            }
-        }
+impl Serialize for Bytes {
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        serialize_bytes_to(self, buffer)
+    }
 
-        Ok(())
+    fn serialized_len(&self) -> usize {
+        bytes_len(self.len())
     }
 }
 
 impl<T: Serialize> Serialize for Vec<T> {
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
         // Write type identifier (for Vec)
         buffer.write_u32::<LittleEndian>(0x1cb5c415u32)?;
 
         // Write length
-        let len = buffer.len() as u32;
-        buffer.write_u32::<LittleEndian>(len)?;
+        buffer.write_u32::<LittleEndian>(self.len() as u32)?;
 
-        // Write elements
+        // Write elements, boxed; bare vectors go through `SerializeBare`
         for element in self {
-            // FIXME: Ensure vector elements are serialized as bare types
             element.serialize_to(buffer)?;
         }
 
         Ok(())
     }
-}
 
-impl Serialize for Box<Any> {
-    fn serialize_to(&self, buffer: &mut Vec<u8>) -> error::Result<()> {
-        match self.downcast_ref::<Box<Serialize>>() {
-            Some(as_ser) => as_ser.serialize_to(buffer),
+    fn serialized_len(&self) -> usize {
+        8 + self.iter().map(Serialize::serialized_len).sum::<usize>()
+    }
+}
 
-            None => {
-                // FIXME: Return an error
-                panic!("Serialize not implemented")
+/// Values without a constructor identifier are the same bare and boxed.
+macro_rules! impl_serialize_bare {
+    ($($type:ty),*) => {
+        $(
+            impl SerializeBare for $type {
+                #[inline]
+                fn serialize_bare_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+                    self.serialize_to(buffer)
+                }
+
+                #[inline]
+                fn serialized_bare_len(&self) -> usize {
+                    self.serialized_len()
+                }
             }
+        )*
+    };
+}
+
+impl_serialize_bare!(i32, i64, u32, f64, Int128, Int256, String, Bytes);
+
+/// A bare `vector<T>`: the length and the bare elements, without the identifier.
+impl<T: SerializeBare> SerializeBare for Vec<T> {
+    fn serialize_bare_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        buffer.write_u32::<LittleEndian>(self.len() as u32)?;
+
+        for element in self {
+            element.serialize_bare_to(buffer)?;
         }
+
+        Ok(())
+    }
+
+    fn serialized_bare_len(&self) -> usize {
+        4 + self.iter().map(SerializeBare::serialized_bare_len).sum::<usize>()
     }
 }
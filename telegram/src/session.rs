@@ -0,0 +1,436 @@
+//! The sessions of the client with the data centers, encrypted with an authorization key.
+//!
+//! Each connection first generates an authorization key with its data center, with the
+//! Diffie-Hellman exchange of `handshake`, in plaintext messages (`Request`). Methods are
+//! then sent in a session: under a random identifier and the salt the server gave, with
+//! sequence numbers counting the messages, encrypted with the key as in MTProto 1.0.
+//!
+//! Over HTTP, the reply to a method comes in the response to the message invoking it. The
+//! messages of the server which need it are acknowledged with the next message sent.
+
+use std::cell::{Cell, RefCell};
+use std::io::Read;
+use std::mem;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::read::GzDecoder;
+use futures::future::{self, Loop};
+use futures::Future;
+use num_bigint::BigUint;
+use rand;
+
+use crypto::{self, Ige};
+use de::{self, Deserialize, Deserializer};
+use error;
+use rpc;
+use schema::mtproto;
+use ser::Serialize;
+use transport::Transport;
+use {Bytes, Client, TlConstructor};
+
+/// Modulus of the RSA key of the production servers, whose fingerprint is
+/// `0xc3b42b026ce86b21`.
+const TELEGRAM_KEY: &[u8] = b"\
+    C150023E2F70DB7985DED064759CFECF0AF328E69A41DAF4D6F01B538135A6F91F8F8B2A0EC9BA9720CE352E\
+    FCF6C5680FFC424BD634864902DE0B4BD6D49F4E580230E3AE97D95C8B19442B3C0A10D8F5633FECEDD6926A\
+    7F6DAB0DDB7D457F9EA81B8465FCD6FFFEED114011DF91C059CAEDAF97625F6C96ECC74725556934EF781D86\
+    6B34F011FCE4D835A090196E9A5F0E4449AF7EB697DDB9076494CA5F81104A305B6DD27665722C46B60E5DF6\
+    80FB16B210607EF217652E60236C255F6A28315F4083A96791D7214BF64C1DF4FD0DB1944FB26A2A57031B32\
+    EEE64AD15A8BA68885CDE74A5BFC920F6ABF59BA5C75506373E7130F9042DA922179251F";
+
+/// Number of times a message is sent again when the server asks for it, e.g. with a new
+/// salt, before giving up.
+const MAX_RESENDS: u32 = 3;
+
+/// Error codes of `bad_msg_notification` for a message identifier too far behind or ahead
+/// of the time of the server.
+const MSG_ID_TOO_LOW: i32 = 16;
+const MSG_ID_TOO_HIGH: i32 = 17;
+
+/// A public RSA key of the Telegram servers, which encrypts the first secret the client
+/// sends when generating an authorization key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsaKey {
+    n: BigUint,
+    e: BigUint,
+}
+
+impl RsaKey {
+    /// The key of modulus `n` and exponent `e`, both big-endian.
+    pub fn new(n: &[u8], e: &[u8]) -> RsaKey {
+        RsaKey {
+            n: BigUint::from_bytes_be(n),
+            e: BigUint::from_bytes_be(e),
+        }
+    }
+
+    /// The key of the production servers.
+    pub fn telegram() -> RsaKey {
+        RsaKey {
+            n: BigUint::parse_bytes(TELEGRAM_KEY, 16).unwrap(),
+            e: BigUint::from(65537u32),
+        }
+    }
+
+    /// The lower 64 bits of the SHA-1 of the modulus and exponent, serialized as `bytes`,
+    /// by which the server tells which of its keys the client may use.
+    pub fn fingerprint(&self) -> i64 {
+        let mut key = Vec::new();
+        Bytes(self.n.to_bytes_be()).serialize_to(&mut key).unwrap();
+        Bytes(self.e.to_bytes_be()).serialize_to(&mut key).unwrap();
+
+        LittleEndian::read_i64(&crypto::sha1(&[&key])[12..])
+    }
+
+    /// `data` (of at most 255 bytes) raised to the exponent, as 256 bytes.
+    pub(crate) fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        let encrypted = BigUint::from_bytes_be(data).modpow(&self.e, &self.n).to_bytes_be();
+
+        let mut padded = vec![0; 256usize.saturating_sub(encrypted.len())];
+        padded.extend(encrypted);
+        padded
+    }
+}
+
+/// A key shared with a data center, known to both sides by its identifier.
+pub(crate) struct AuthKey {
+    bytes: Vec<u8>,
+    id: i64,
+}
+
+impl AuthKey {
+    pub(crate) fn new(bytes: Vec<u8>) -> AuthKey {
+        let hash = crypto::sha1(&[&bytes]);
+
+        AuthKey {
+            id: LittleEndian::read_i64(&hash[12..]),
+            bytes,
+        }
+    }
+}
+
+/// The identifiers of a message sent in a session, to find what the server replies to it.
+struct Sent {
+    /// The message of the method.
+    msg_id: i64,
+
+    /// The container holding it along with acknowledgements, if any.
+    container_id: Option<i64>,
+}
+
+impl Sent {
+    fn is(&self, msg_id: i64) -> bool {
+        msg_id == self.msg_id || Some(msg_id) == self.container_id
+    }
+}
+
+/// What the server replied to a message.
+enum Reply {
+    /// The result of the method, `rpc_error` included.
+    Result(Vec<u8>),
+
+    /// The message was rejected, e.g. for its salt, and is to be sent again.
+    Resend,
+}
+
+/// A session with a data center.
+pub(crate) struct Session {
+    key: AuthKey,
+    id: i64,
+    salt: Cell<i64>,
+
+    /// Seconds between the local time and the time of the server, which message
+    /// identifiers are based on.
+    time_offset: Cell<i64>,
+    last_msg_id: Cell<i64>,
+
+    /// Number of the content-related messages sent, which the sequence numbers count.
+    content_sent: Cell<i32>,
+
+    /// The content-related messages of the server, to acknowledge with the next message.
+    acks: RefCell<Vec<i64>>,
+}
+
+impl Session {
+    pub(crate) fn new(key: AuthKey, salt: i64, time_offset: i64) -> Session {
+        Session {
+            key,
+            id: rand::random(),
+            salt: Cell::new(salt),
+            time_offset: Cell::new(time_offset),
+            last_msg_id: Cell::new(0),
+            content_sent: Cell::new(0),
+            acks: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Invoke the serialized method `body` through `transport`, resolving to its result.
+    pub(crate) fn invoke(
+        self: Rc<Self>,
+        transport: Rc<dyn Transport>,
+        body: Vec<u8>,
+    ) -> Box<dyn Future<Item = Vec<u8>, Error = error::Error>> {
+        Box::new(future::loop_fn(0, move |resent| {
+            let session = self.clone();
+            let (sent, message) = match self.encrypt(&body) {
+                Ok(encrypted) => encrypted,
+                Err(error) => return future::Either::A(future::err(error)),
+            };
+
+            future::Either::B(transport.send(message).and_then(move |response| {
+                match session.reply(&sent, &response)? {
+                    Reply::Result(result) => Ok(Loop::Break(result)),
+                    Reply::Resend if resent < MAX_RESENDS => Ok(Loop::Continue(resent + 1)),
+                    Reply::Resend => bail!("the message was rejected {} times", resent + 1),
+                }
+            }))
+        }))
+    }
+
+    /// A new message identifier: the time of the server in seconds times 2^32, greater
+    /// than the previous one and divisible by 4.
+    fn msg_id(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let seconds = now.as_secs() as i64 + self.time_offset.get();
+        let msg_id = ((seconds << 32) | (i64::from(now.subsec_nanos()) << 2))
+            .max(self.last_msg_id.get() + 4);
+
+        self.last_msg_id.set(msg_id);
+        msg_id
+    }
+
+    /// The sequence number of the next message: odd for those which need to be
+    /// acknowledged, counting them.
+    fn seq_no(&self, content_related: bool) -> i32 {
+        let sent = self.content_sent.get();
+
+        if content_related {
+            self.content_sent.set(sent + 1);
+            2 * sent + 1
+        } else {
+            2 * sent
+        }
+    }
+
+    /// The message of the method `body`, in a container along with the acknowledgements
+    /// due, encrypted.
+    fn encrypt(&self, body: &[u8]) -> error::Result<(Sent, Vec<u8>)> {
+        let acks = mem::take(&mut *self.acks.borrow_mut());
+
+        let msg_id = self.msg_id();
+        let seq_no = self.seq_no(true);
+
+        let (sent, message_id, message_seq_no, message) = if acks.is_empty() {
+            let sent = Sent {
+                msg_id,
+                container_id: None,
+            };
+
+            (sent, msg_id, seq_no, body.to_vec())
+        } else {
+            let ack = mtproto::MsgsAck { msg_ids: acks }.to_vec()?;
+            let ack_id = self.msg_id();
+            let ack_seq_no = self.seq_no(false);
+
+            // msg_container#73f1f8dc messages:vector<%Message> = MessageContainer
+            let mut container = Vec::new();
+            mtproto::MessageContainer::CONSTRUCTOR_ID.serialize_to(&mut container)?;
+            2u32.serialize_to(&mut container)?;
+
+            let messages = [(msg_id, seq_no, body), (ack_id, ack_seq_no, &ack[..])];
+            for &(id, seq_no, body) in &messages {
+                id.serialize_to(&mut container)?;
+                seq_no.serialize_to(&mut container)?;
+                (body.len() as u32).serialize_to(&mut container)?;
+                container.extend_from_slice(body);
+            }
+
+            let container_id = self.msg_id();
+            let sent = Sent {
+                msg_id,
+                container_id: Some(container_id),
+            };
+
+            (sent, container_id, self.seq_no(false), container)
+        };
+
+        // salt, session_id, message_id, seq_no, message_data_length, message_data
+        let mut plain = Vec::with_capacity(32 + message.len() + 16);
+        self.salt.get().serialize_to(&mut plain)?;
+        self.id.serialize_to(&mut plain)?;
+        message_id.serialize_to(&mut plain)?;
+        message_seq_no.serialize_to(&mut plain)?;
+        (message.len() as u32).serialize_to(&mut plain)?;
+        plain.extend(message);
+
+        // The message key is the hash of the data without its padding
+        let msg_key = crypto::sha1(&[&plain])[4..].to_vec();
+        let padding = (16 - plain.len() % 16) % 16;
+        plain.extend(crypto::random_bytes(padding));
+
+        let (aes_key, aes_iv) = crypto::aes_key_iv(&self.key.bytes, &msg_key, 0);
+        Ige::new(&aes_key, &aes_iv).encrypt(&mut plain);
+
+        let mut data = Vec::with_capacity(8 + 16 + plain.len());
+        self.key.id.serialize_to(&mut data)?;
+        data.extend(msg_key);
+        data.extend(plain);
+
+        Ok((sent, data))
+    }
+
+    /// Decrypt a message of the server, resolving to its identifier and its body.
+    fn decrypt(&self, data: &[u8]) -> error::Result<(i64, Vec<u8>)> {
+        // The server replies with a bare error code to a message it can't read, e.g. -404
+        // for an unknown authorization key
+        if data.len() == 4 {
+            bail!("the data center failed with error {}", LittleEndian::read_i32(data));
+        }
+
+        if data.len() < 8 + 16 + 32 || !(data.len() - 8 - 16).is_multiple_of(16) {
+            bail!("a message of the data center of a wrong size");
+        }
+
+        if LittleEndian::read_i64(data) != self.key.id {
+            bail!("a message of the data center under another authorization key");
+        }
+
+        let msg_key = &data[8..24];
+        let mut plain = data[24..].to_vec();
+
+        let (aes_key, aes_iv) = crypto::aes_key_iv(&self.key.bytes, msg_key, 8);
+        Ige::new(&aes_key, &aes_iv).decrypt(&mut plain);
+
+        let len = LittleEndian::read_u32(&plain[28..]) as usize;
+        if len > plain.len() - 32 || crypto::sha1(&[&plain[..32 + len]])[4..] != *msg_key {
+            bail!("a message of the data center failed to decrypt");
+        }
+
+        if LittleEndian::read_i64(&plain[8..]) != self.id {
+            bail!("a message of the data center for another session");
+        }
+
+        let msg_id = LittleEndian::read_i64(&plain[16..]);
+        if LittleEndian::read_i32(&plain[24..]) % 2 == 1 {
+            self.acks.borrow_mut().push(msg_id);
+        }
+
+        Ok((msg_id, plain[32..32 + len].to_vec()))
+    }
+
+    /// Read the response of the server to a message, for the reply to it.
+    fn reply(&self, sent: &Sent, response: &[u8]) -> error::Result<Reply> {
+        let (msg_id, body) = self.decrypt(response)?;
+
+        let mut reply = None;
+        self.read(sent, msg_id, &body, &mut reply)?;
+
+        match reply {
+            Some(reply) => Ok(reply),
+            None => bail!("the response of the data center holds no reply to the method"),
+        }
+    }
+
+    /// Read a message of the server, `msg_id`, and the messages it contains.
+    fn read(
+        &self,
+        sent: &Sent,
+        msg_id: i64,
+        body: &[u8],
+        reply: &mut Option<Reply>,
+    ) -> error::Result<()> {
+        let mut reader = Deserializer::new(body);
+
+        match de::from_slice::<u32>(body)? {
+            mtproto::MessageContainer::CONSTRUCTOR_ID => {
+                reader.read_slice(4)?;
+
+                for _ in 0..u32::deserialize_from(&mut reader)? {
+                    let msg_id = i64::deserialize_from(&mut reader)?;
+                    let seq_no = i32::deserialize_from(&mut reader)?;
+                    let len = u32::deserialize_from(&mut reader)? as usize;
+
+                    if seq_no % 2 == 1 {
+                        self.acks.borrow_mut().push(msg_id);
+                    }
+
+                    self.read(sent, msg_id, reader.read_slice(len)?, reply)?;
+                }
+            }
+
+            rpc::RPC_RESULT_ID => {
+                reader.read_slice(4)?;
+
+                if sent.is(i64::deserialize_from(&mut reader)?) {
+                    *reply = Some(Reply::Result(unpacked(reader.remaining())?));
+                }
+            }
+
+            mtproto::NewSession::CONSTRUCTOR_ID => {
+                let created = mtproto::NewSession::deserialize_from(&mut reader)?;
+                self.salt.set(created.server_salt);
+            }
+
+            mtproto::BadServerSalt::CONSTRUCTOR_ID => {
+                let bad = mtproto::BadServerSalt::deserialize_from(&mut reader)?;
+                self.salt.set(bad.new_server_salt);
+
+                if sent.is(bad.bad_msg_id) {
+                    *reply = Some(Reply::Resend);
+                }
+            }
+
+            mtproto::BadMsgNotification_::CONSTRUCTOR_ID => {
+                let bad = mtproto::BadMsgNotification_::deserialize_from(&mut reader)?;
+
+                if sent.is(bad.bad_msg_id) {
+                    match bad.error_code {
+                        // The identifiers of the server hold its time
+                        MSG_ID_TOO_LOW | MSG_ID_TOO_HIGH => {
+                            let now = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default();
+
+                            self.time_offset.set((msg_id >> 32) - now.as_secs() as i64);
+                            *reply = Some(Reply::Resend);
+                        }
+
+                        code => bail!("the data center rejected the message with error {}", code),
+                    }
+                }
+            }
+
+            // Acknowledgements, updates, ...
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// The result of a method, unpacked if the server compressed it (`gzip_packed`).
+fn unpacked(result: &[u8]) -> error::Result<Vec<u8>> {
+    if de::from_slice::<u32>(result)? != mtproto::Object::CONSTRUCTOR_ID {
+        return Ok(result.to_vec());
+    }
+
+    let packed = de::from_slice::<mtproto::Object>(result)?.packed_data;
+    let mut unpacked = Vec::new();
+    GzDecoder::new(&packed[..]).read_to_end(&mut unpacked)?;
+
+    Ok(unpacked)
+}
+
+impl Client {
+    /// Use `keys` as the RSA keys of the servers, instead of the key of the production
+    /// servers, e.g. for test servers.
+    pub fn server_keys(mut self, keys: Vec<RsaKey>) -> Client {
+        self.server_keys = Rc::new(keys);
+        self
+    }
+}
@@ -0,0 +1,78 @@
+//! Keeping the state of a client between runs, e.g. the peers it has seen.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use error;
+
+/// Where the state of a client is kept: values by key, e.g. `peers`.
+pub trait SessionStore {
+    /// The value saved under `key`, if any.
+    fn load(&self, key: &str) -> error::Result<Option<Vec<u8>>>;
+
+    /// Save `value` under `key`, replacing the previous one.
+    fn save(&self, key: &str, value: &[u8]) -> error::Result<()>;
+}
+
+/// A store in memory, lost with it.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    values: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, key: &str) -> error::Result<Option<Vec<u8>>> {
+        Ok(self.values.borrow().get(key).cloned())
+    }
+
+    fn save(&self, key: &str, value: &[u8]) -> error::Result<()> {
+        self.values.borrow_mut().insert(key.into(), value.to_vec());
+        Ok(())
+    }
+}
+
+/// A store in a directory, with a file per key.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// A store in `dir`, which is created if it doesn't exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> error::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(FileStore { dir })
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, key: &str) -> error::Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn save(&self, key: &str, value: &[u8]) -> error::Result<()> {
+        // Write to a temporary file first, so that a crash doesn't leave half a value
+        let path = self.dir.join(key);
+        let temporary = self.dir.join(format!("{}.tmp", key));
+
+        fs::write(&temporary, value)?;
+        fs::rename(&temporary, &path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Waiting, without a reactor: a client may run on any executor, or none with `wait`.
+
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex, Once, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::future;
+use futures::sync::oneshot;
+use futures::{Future, Poll};
+
+use error;
+
+/// A deadline, told apart from the others at the same instant by the order it was added in.
+type Deadline = (Instant, u64);
+
+/// The deadlines waited for, each with the sender resolving its `Sleep`.
+#[derive(Default)]
+struct Deadlines {
+    pending: BTreeMap<Deadline, oneshot::Sender<()>>,
+    added: u64,
+}
+
+/// The single thread all the sleeps are waited for on.
+#[derive(Default)]
+struct Timer {
+    deadlines: Mutex<Deadlines>,
+    changed: Condvar,
+}
+
+impl Timer {
+    /// The timer, started on first use.
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        static STARTED: Once = Once::new();
+
+        let timer = TIMER.get_or_init(Timer::default);
+        STARTED.call_once(|| {
+            thread::Builder::new()
+                .name("telegram-timer".into())
+                .spawn(move || timer.run())
+                .expect("failed to start the timer thread");
+        });
+
+        timer
+    }
+
+    fn add(&self, instant: Instant) -> (Deadline, oneshot::Receiver<()>) {
+        let (sender, receiver) = oneshot::channel();
+        let mut deadlines = self.deadlines.lock().unwrap();
+
+        let deadline = (instant, deadlines.added);
+        deadlines.added += 1;
+        deadlines.pending.insert(deadline, sender);
+
+        // It may be earlier than the one the thread waits for
+        self.changed.notify_one();
+
+        (deadline, receiver)
+    }
+
+    fn remove(&self, deadline: &Deadline) {
+        // The thread waking up for nothing is harmless, so it isn't notified
+        self.deadlines.lock().unwrap().pending.remove(deadline);
+    }
+
+    fn run(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+            while let Some(entry) = deadlines.pending.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+
+                let _ = entry.remove().send(());
+            }
+
+            deadlines = match deadlines.pending.keys().next() {
+                Some(&(instant, _)) => {
+                    self.changed.wait_timeout(deadlines, instant - now).unwrap().0
+                }
+                None => self.changed.wait(deadlines).unwrap(),
+            };
+        }
+    }
+}
+
+/// A future resolving once its deadline has passed, which it stops waiting for when dropped.
+struct Sleep {
+    deadline: Option<Deadline>,
+    receiver: oneshot::Receiver<()>,
+}
+
+impl Future for Sleep {
+    type Item = ();
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<(), error::Error> {
+        let elapsed = self
+            .receiver
+            .poll()
+            .map_err(|_| error::Error::from("the timer thread stopped"))?;
+
+        if elapsed.is_ready() {
+            self.deadline = None;
+        }
+
+        Ok(elapsed)
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(ref deadline) = self.deadline {
+            Timer::get().remove(deadline);
+        }
+    }
+}
+
+/// A future resolving once `duration` has elapsed.
+///
+/// All the waits happen on one thread, shared by the clients of the process, and those
+/// which are dropped before they end are forgotten: sleeps are cheap enough to race
+/// against every sent message, or to throttle bulk calls with.
+pub(crate) fn sleep(duration: Duration) -> Box<dyn Future<Item = (), Error = error::Error>> {
+    if duration == Duration::from_secs(0) {
+        return Box::new(future::ok(()));
+    }
+
+    let (deadline, receiver) = Timer::get().add(Instant::now() + duration);
+
+    Box::new(Sleep {
+        deadline: Some(deadline),
+        receiver,
+    })
+}
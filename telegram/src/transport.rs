@@ -0,0 +1,97 @@
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use hyper::{self, Body};
+use hyper::client::HttpConnector;
+use tokio::reactor::Handle;
+
+use error;
+use schema::DcOption;
+
+/// A connection to a Telegram server, carrying serialized messages there and back.
+///
+/// `Client` invokes methods through a transport, which lets it talk to another data
+/// center or, in tests, to an in-process server.
+pub trait Transport {
+    /// Send a serialized message, resolving to the message the server replies with.
+    fn send(&self, message: Vec<u8>) -> Box<dyn Future<Item = Vec<u8>, Error = error::Error>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Rc<T> {
+    fn send(&self, message: Vec<u8>) -> Box<dyn Future<Item = Vec<u8>, Error = error::Error>> {
+        (**self).send(message)
+    }
+}
+
+/// The HTTP transport, posting each message to `/api`.
+pub struct HttpTransport {
+    http_client: hyper::Client<HttpConnector, Body>,
+    uri: hyper::Uri,
+}
+
+impl HttpTransport {
+    /// Connect to the default production server.
+    pub fn new(handle: &Handle) -> HttpTransport {
+        HttpTransport::with_uri(handle, default_uri())
+    }
+
+    /// Connect to the server at `uri`, e.g. `http://149.154.175.50:80/api`.
+    pub fn with_uri(handle: &Handle, uri: hyper::Uri) -> HttpTransport {
+        HttpTransport {
+            http_client: hyper::Client::new(handle),
+            uri,
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, message: Vec<u8>) -> Box<dyn Future<Item = Vec<u8>, Error = error::Error>> {
+        Box::new(
+            self.http_client
+                .request(http_request(self.uri.clone(), message))
+                .and_then(|res| res.body().concat2())
+                .map(|data| data.to_vec())
+                .map_err(|err| err.into()),
+        )
+    }
+}
+
+/// The production data center requests are sent to by default.
+pub(crate) fn default_dc() -> DcOption {
+    DcOption {
+        id: 2,
+        hostname: String::new(),
+        ip_address: "149.154.167.50".into(),
+        port: 443,
+    }
+}
+
+pub(crate) fn default_uri() -> hyper::Uri {
+    dc_uri(&default_dc()).expect("invalid default address")
+}
+
+/// The `/api` endpoint of a data center.
+pub(crate) fn dc_uri(dc: &DcOption) -> error::Result<hyper::Uri> {
+    let address = format!("http://{}:{}/api", dc.ip_address, dc.port);
+
+    address
+        .parse()
+        .map_err(|_| format!("invalid address {} of data center {}", address, dc.id).into())
+}
+
+/// A `POST` of `message` to `uri`, on a kept-alive connection.
+pub(crate) fn http_request(uri: hyper::Uri, message: Vec<u8>) -> hyper::Request {
+    let mut http_request = hyper::Request::new(hyper::Method::Post, uri);
+
+    http_request
+        .headers_mut()
+        .set(hyper::header::Connection::keep_alive());
+
+    http_request
+        .headers_mut()
+        .set(hyper::header::ContentLength(message.len() as u64));
+
+    http_request.set_body(message);
+
+    http_request
+}
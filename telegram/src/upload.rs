@@ -0,0 +1,468 @@
+//! Uploading files, to send them as media.
+//!
+//! A file is uploaded in parts of `PART_SIZE` bytes under a random identifier, with
+//! `upload.saveFilePart`, or `upload.saveBigFilePart` when it is over `BIG_FILE_SIZE`.
+//! The `InputFile` naming it can then be sent, e.g. with `messages.sendMedia`.
+//!
+//! Files sent in secret chats are encrypted as they are uploaded, with `Upload::encrypt`,
+//! which resolves to an `InputEncryptedFile` for `messages.sendEncryptedFile` instead.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use futures::future::{self, Loop};
+use futures::{stream, Async, Future, Poll, Stream};
+use md5::{Digest, Md5};
+use rand::{self, RngCore};
+
+use error;
+use rpc::RemoteCall;
+use schema::{self, upload};
+use secret::FileKey;
+use Client;
+
+/// Size of the parts a file is uploaded in; only the last one may be shorter.
+pub const PART_SIZE: usize = 512 * 1024;
+
+/// Size over which a file is a big one, uploaded with `upload.saveBigFilePart`.
+pub const BIG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Number of parts uploaded at the same time by default.
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Number of times a part is uploaded again by default, when saving it failed.
+const DEFAULT_RETRIES: u32 = 3;
+
+type Source = Box<dyn Stream<Item = Vec<u8>, Error = error::Error>>;
+
+/// The parts read ahead, and the rest of the source if it didn't end.
+type ReadAhead = (Vec<Vec<u8>>, Option<Source>);
+
+type UploadFuture = Box<dyn Future<Item = schema::InputFile, Error = error::Error>>;
+
+/// An upload, resolving to the `InputFile` of the uploaded file.
+///
+/// The upload starts when the future is first polled; until then, it can be configured.
+pub struct Upload {
+    client: Client,
+    name: String,
+    source: Option<Source>,
+    size: Option<u64>,
+    concurrency: usize,
+    retries: u32,
+    progress: Option<Rc<dyn Fn(u64, u64)>>,
+
+    /// The key the parts are encrypted with, for a secret chat.
+    key: Option<FileKey>,
+
+    future: Option<UploadFuture>,
+}
+
+impl Client {
+    /// Upload the contents of `reader` as the file `name`.
+    ///
+    /// The server has no use for empty files: uploading one fails without saving anything.
+    pub fn upload<R: Read + 'static>(&self, reader: R, name: &str) -> Upload {
+        self.upload_stream(read_stream(reader), name)
+    }
+
+    /// Upload the chunks of `stream`, of any size, as the file `name`.
+    pub fn upload_stream<S>(&self, stream: S, name: &str) -> Upload
+    where
+        S: Stream<Item = Vec<u8>, Error = error::Error> + 'static,
+    {
+        Upload {
+            client: self.clone(),
+            name: name.into(),
+            source: Some(Box::new(stream)),
+            size: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            retries: DEFAULT_RETRIES,
+            progress: None,
+            key: None,
+            future: None,
+        }
+    }
+}
+
+impl Upload {
+    /// Size of the file, which is required for files over `BIG_FILE_SIZE`.
+    ///
+    /// Without it, up to `BIG_FILE_SIZE` bytes are read ahead to find the size.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Number of parts uploaded at the same time, 4 by default.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Number of times a part is uploaded again when the server fails to save it, replying
+    /// `false`, 3 by default. Errors are retried by the `RetryPolicy` of the client instead.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Call `progress` with the number of bytes uploaded and the size of the file, each
+    /// time a part was saved.
+    pub fn progress<F: Fn(u64, u64) + 'static>(mut self, progress: F) -> Self {
+        self.progress = Some(Rc::new(progress));
+        self
+    }
+
+    /// Encrypt the file with AES-256-IGE under `key` as it is uploaded, to send it in a
+    /// secret chat.
+    ///
+    /// The file is padded to a multiple of 16 bytes, which is the size it is uploaded
+    /// with; the size given to `Upload::size` remains the one before padding.
+    pub fn encrypt(mut self, key: &FileKey) -> EncryptedUpload {
+        self.key = Some(key.clone());
+
+        EncryptedUpload {
+            upload: self,
+            key_fingerprint: key.fingerprint(),
+        }
+    }
+
+    fn start(&mut self) -> UploadFuture {
+        let source = self.source.take().expect("upload polled after completion");
+        let mut parts: Source = Box::new(Parts {
+            source,
+            buffer: Vec::new(),
+            done: false,
+        });
+
+        if let Some(ref key) = self.key {
+            parts = encrypted(parts, key);
+        }
+
+        let upload = UploadParts {
+            client: self.client.clone(),
+            file_id: rand::random(),
+            name: self.name.clone(),
+            concurrency: self.concurrency,
+            retries: self.retries,
+            progress: self.progress.clone(),
+        };
+
+        match self.size {
+            Some(size) if self.key.is_some() => upload.run(parts, size.div_ceil(16) * 16),
+            Some(size) => upload.run(parts, size),
+
+            None => Box::new(read_ahead(parts).and_then(move |(head, rest)| {
+                let size = head.iter().map(|part| part.len() as u64).sum();
+                if rest.is_some() {
+                    bail!(
+                        "the size of files over {} bytes must be given with `Upload::size`",
+                        BIG_FILE_SIZE
+                    );
+                }
+
+                Ok(upload.run(Box::new(stream::iter_ok(head)), size))
+            }).flatten()),
+        }
+    }
+}
+
+impl Future for Upload {
+    type Item = schema::InputFile;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.future.is_none() {
+            self.future = Some(self.start());
+        }
+
+        self.future.as_mut().unwrap().poll()
+    }
+}
+
+impl fmt::Debug for Upload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Upload")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("concurrency", &self.concurrency)
+            .field("retries", &self.retries)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+/// An upload encrypted for a secret chat, resolving to the `InputEncryptedFile` of the
+/// uploaded file.
+#[derive(Debug)]
+pub struct EncryptedUpload {
+    upload: Upload,
+    key_fingerprint: i32,
+}
+
+impl EncryptedUpload {
+    /// See `Upload::size`.
+    pub fn size(mut self, size: u64) -> Self {
+        self.upload = self.upload.size(size);
+        self
+    }
+
+    /// See `Upload::concurrency`.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.upload = self.upload.concurrency(concurrency);
+        self
+    }
+
+    /// See `Upload::retries`.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.upload = self.upload.retries(retries);
+        self
+    }
+
+    /// See `Upload::progress`; the size is the one of the padded file.
+    pub fn progress<F: Fn(u64, u64) + 'static>(mut self, progress: F) -> Self {
+        self.upload = self.upload.progress(progress);
+        self
+    }
+}
+
+impl Future for EncryptedUpload {
+    type Item = schema::InputEncryptedFile;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let key_fingerprint = self.key_fingerprint;
+
+        Ok(Async::Ready(match try_ready!(self.upload.poll()) {
+            schema::InputFile::InputFile(file) => schema::InputEncryptedFileUploaded {
+                id: file.id,
+                parts: file.parts,
+                md5_checksum: file.md5_checksum,
+                key_fingerprint,
+            }.into(),
+
+            schema::InputFile::InputFileBig(file) => schema::InputEncryptedFileBigUploaded {
+                id: file.id,
+                parts: file.parts,
+                key_fingerprint,
+            }.into(),
+        }))
+    }
+}
+
+/// The upload of the parts of a file of a known size.
+struct UploadParts {
+    client: Client,
+    file_id: i64,
+    name: String,
+    concurrency: usize,
+    retries: u32,
+    progress: Option<Rc<dyn Fn(u64, u64)>>,
+}
+
+impl UploadParts {
+    fn run(self, parts: Source, size: u64) -> UploadFuture {
+        let UploadParts {
+            client,
+            file_id,
+            name,
+            concurrency,
+            retries,
+            progress,
+        } = self;
+
+        // An `InputFile` of no parts is rejected when it is sent
+        if size == 0 {
+            return Box::new(future::err(format!("the file {} is empty", name).into()));
+        }
+
+        let big = size > BIG_FILE_SIZE;
+        let total_parts = size.div_ceil(PART_SIZE as u64) as i32;
+        let md5 = Rc::new(RefCell::new(Md5::new()));
+        let checksum = md5.clone();
+        let mut file_part = 0;
+
+        let saved = parts
+            .map(move |bytes| {
+                let len = bytes.len() as u64;
+                let part = file_part;
+                file_part += 1;
+
+                let saved = if big {
+                    save_part(&client, retries, upload::SaveBigFilePart {
+                        file_id,
+                        file_part: part,
+                        file_total_parts: total_parts,
+                        bytes: bytes.into(),
+                    })
+                } else {
+                    md5.borrow_mut().update(&bytes);
+
+                    save_part(&client, retries, upload::SaveFilePart {
+                        file_id,
+                        file_part: part,
+                        bytes: bytes.into(),
+                    })
+                };
+
+                saved.map(move |()| len)
+            })
+            .buffer_unordered(concurrency);
+
+        Box::new(
+            saved
+                .fold(0, move |uploaded, len| {
+                    let uploaded = uploaded + len;
+                    if let Some(ref progress) = progress {
+                        progress(uploaded, size);
+                    }
+
+                    Ok::<_, error::Error>(uploaded)
+                })
+                .and_then(move |uploaded| {
+                    if uploaded != size {
+                        bail!("read {} bytes of a file of {} bytes", uploaded, size);
+                    }
+
+                    Ok(if big {
+                        schema::InputFileBig {
+                            id: file_id,
+                            parts: total_parts,
+                            name,
+                        }.into()
+                    } else {
+                        let checksum = checksum.borrow_mut().clone().finalize();
+
+                        schema::InputFile_ {
+                            id: file_id,
+                            parts: total_parts,
+                            name,
+                            md5_checksum: format!("{:x}", checksum),
+                        }.into()
+                    })
+                }),
+        )
+    }
+}
+
+/// Save a part, again up to `retries` times if the server fails to; errors are left to
+/// the `RetryPolicy` of the client.
+fn save_part<R>(
+    client: &Client,
+    retries: u32,
+    query: R,
+) -> Box<dyn Future<Item = (), Error = error::Error>>
+where
+    R: RemoteCall<Reply = bool> + Clone + 'static,
+{
+    let client = client.clone();
+
+    Box::new(future::loop_fn(retries, move |retries| {
+        client.invoke(query.clone()).then(move |saved| match saved {
+            Ok(true) => Ok(Loop::Break(())),
+            Ok(false) if retries > 0 => Ok(Loop::Continue(retries - 1)),
+            Ok(false) => Err("the server failed to save a part of the file".into()),
+            Err(error) => Err(error),
+        })
+    }))
+}
+
+/// Read parts until more than `BIG_FILE_SIZE` bytes were read, resolving to them and to
+/// the rest of the stream, or to `None` if it ended.
+fn read_ahead(
+    parts: Source,
+) -> Box<dyn Future<Item = ReadAhead, Error = error::Error>> {
+    Box::new(future::loop_fn(
+        (parts, Vec::new(), 0),
+        |(parts, mut head, len): (Source, Vec<Vec<u8>>, u64)| {
+            parts
+                .into_future()
+                .map_err(|(error, _)| error)
+                .map(move |(part, parts)| match part {
+                    None => Loop::Break((head, None)),
+                    Some(part) => {
+                        let len = len + part.len() as u64;
+                        head.push(part);
+
+                        if len > BIG_FILE_SIZE {
+                            Loop::Break((head, Some(parts)))
+                        } else {
+                            Loop::Continue((parts, head, len))
+                        }
+                    }
+                })
+        },
+    ))
+}
+
+/// The chunks of a source cut into parts of `PART_SIZE` bytes.
+struct Parts {
+    source: Source,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl Stream for Parts {
+    type Item = Vec<u8>;
+    type Error = error::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        while !self.done && self.buffer.len() < PART_SIZE {
+            match try_ready!(self.source.poll()) {
+                Some(chunk) => self.buffer.extend(chunk),
+                None => self.done = true,
+            }
+        }
+
+        if self.buffer.is_empty() {
+            return Ok(Async::Ready(None));
+        }
+
+        let rest = if self.buffer.len() > PART_SIZE {
+            self.buffer.split_off(PART_SIZE)
+        } else {
+            Vec::new()
+        };
+
+        Ok(Async::Ready(Some(::std::mem::replace(&mut self.buffer, rest))))
+    }
+}
+
+/// The parts of `parts` encrypted under `key`, the last one padded to a whole block.
+///
+/// The parts are encrypted one after the other, as they are read, since IGE chains each
+/// block to the previous one; they are all multiples of 16 bytes but the last one.
+fn encrypted(parts: Source, key: &FileKey) -> Source {
+    let mut cipher = key.cipher();
+
+    Box::new(parts.map(move |mut part| {
+        let padding = (16 - part.len() % 16) % 16;
+        let mut random = vec![0; padding];
+        rand::thread_rng().fill_bytes(&mut random);
+        part.extend(random);
+
+        cipher.encrypt(&mut part);
+        part
+    }))
+}
+
+/// The contents of `reader`, in chunks of up to `PART_SIZE` bytes.
+fn read_stream<R: Read + 'static>(mut reader: R) -> Source {
+    Box::new(stream::poll_fn(move || loop {
+        let mut chunk = vec![0; PART_SIZE];
+
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(Async::Ready(None)),
+            Ok(len) => {
+                chunk.truncate(len);
+                return Ok(Async::Ready(Some(chunk)));
+            }
+
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }))
+}
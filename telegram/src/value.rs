@@ -0,0 +1,466 @@
+//! Dynamic representation of TL objects, decoded and encoded with the metadata of the
+//! schema (see `meta`) instead of static types, e.g. to log, inspect or replay traffic.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use de::{Deserialize, Deserializer};
+use error::{self, ErrorKind};
+use meta::{self, Constructor};
+use schema;
+use ser::Serialize;
+use {Bytes, Int128, Int256};
+
+/// A TL object of any constructor or method of the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlValue {
+    pub id: u32,
+
+    /// Full TL name of the constructor, e.g. `user` or `auth.sentCode`.
+    pub name: String,
+
+    /// Values of the parameters by name.
+    ///
+    /// `#` parameters are left out, as they are computed from the conditional parameters,
+    /// and so are conditional parameters which are absent. `flags.N?true` parameters
+    /// which are present hold `Value::Bool(true)`.
+    pub fields: BTreeMap<String, Value>,
+}
+
+/// The value of a parameter of a `TlValue`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i32),
+    Long(i64),
+    Double(f64),
+    Int128(Int128),
+    Int256(Int256),
+    String(String),
+    Bytes(Bytes),
+    Vector(Vec<Value>),
+    Object(TlValue),
+}
+
+impl Value {
+    /// TL name of the kind of value, for error messages.
+    fn kind(&self) -> &'static str {
+        match *self {
+            Value::Bool(_) => "Bool",
+            Value::Int(_) => "int",
+            Value::Long(_) => "long",
+            Value::Double(_) => "double",
+            Value::Int128(_) => "int128",
+            Value::Int256(_) => "int256",
+            Value::String(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::Vector(_) => "vector",
+            Value::Object(_) => "object",
+        }
+    }
+}
+
+fn invalid(reason: String) -> error::Error {
+    ErrorKind::InvalidTlValue(reason).into()
+}
+
+impl TlValue {
+    /// Decode a boxed object of any known constructor from the start of `input`.
+    pub fn decode(input: &[u8]) -> error::Result<TlValue> {
+        ::de::from_slice(input)
+    }
+
+    fn constructor(&self) -> error::Result<&'static Constructor> {
+        schema::registry().lookup(self.id)
+    }
+}
+
+impl<'de> Deserialize<'de> for TlValue {
+    fn deserialize_from(reader: &mut Deserializer<'de>) -> error::Result<Self> {
+        let id = u32::deserialize_from(reader)?;
+
+        deserialize_fields(schema::registry().lookup(id)?, reader)
+    }
+}
+
+/// Deserialize the parameters of `constructor`, following its identifier.
+fn deserialize_fields(constructor: &'static Constructor, reader: &mut Deserializer) -> error::Result<TlValue> {
+    let mut flags = BTreeMap::new();
+    let mut fields = BTreeMap::new();
+
+    for param in constructor.params {
+        if param.kind == "#" {
+            flags.insert(param.name, u32::deserialize_from(reader)?);
+            continue;
+        }
+
+        let kind = match meta::condition(param.kind) {
+            Some((flags_name, bit, kind)) => {
+                if flags.get(flags_name).cloned().unwrap_or(0) & (1 << bit) == 0 {
+                    continue;
+                }
+
+                if kind == "true" {
+                    fields.insert(param.name.to_string(), Value::Bool(true));
+                    continue;
+                }
+
+                kind
+            }
+
+            None => param.kind,
+        };
+
+        fields.insert(param.name.to_string(), deserialize_kind(kind, reader)?);
+    }
+
+    Ok(TlValue {
+        id: constructor.id,
+        name: constructor.name.to_string(),
+        fields,
+    })
+}
+
+/// Deserialize a value of the TL type `kind`.
+fn deserialize_kind(kind: &'static str, reader: &mut Deserializer) -> error::Result<Value> {
+    let value = match kind {
+        "Bool" => Value::Bool(Deserialize::deserialize_from(reader)?),
+        "int" => Value::Int(Deserialize::deserialize_from(reader)?),
+        "long" => Value::Long(Deserialize::deserialize_from(reader)?),
+        "double" => Value::Double(Deserialize::deserialize_from(reader)?),
+        "int128" => Value::Int128(Deserialize::deserialize_from(reader)?),
+        "int256" => Value::Int256(Deserialize::deserialize_from(reader)?),
+        "string" => Value::String(Deserialize::deserialize_from(reader)?),
+        "bytes" => Value::Bytes(Deserialize::deserialize_from(reader)?),
+
+        // The query of a method generic over `{X:Type}`
+        "!X" => Value::Object(TlValue::deserialize_from(reader)?),
+
+        _ => {
+            if let Some((element, boxed)) = meta::vector_element(kind) {
+                if boxed {
+                    match u32::deserialize_from(reader)? {
+                        0x1cb5c415 => {}
+                        id => bail!(ErrorKind::UnexpectedConstructor("Vector", id)),
+                    }
+                }
+
+                let len = u32::deserialize_from(reader)? as usize;
+                let mut elements = Vec::with_capacity(len.min(reader.remaining().len()));
+                for _ in 0..len {
+                    elements.push(deserialize_kind(element, reader)?);
+                }
+
+                Value::Vector(elements)
+            } else if let Some(constructor) = schema::registry().bare(kind) {
+                Value::Object(deserialize_fields(constructor, reader)?)
+            } else {
+                let id = u32::deserialize_from(reader)?;
+                let constructor = schema::registry().lookup(id)?;
+
+                if constructor.kind != kind {
+                    bail!(ErrorKind::UnexpectedConstructor(kind, id));
+                }
+
+                Value::Object(deserialize_fields(constructor, reader)?)
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+/// Counts the bytes written to it.
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Serialize for TlValue {
+    fn serialize_to<W: Write>(&self, buffer: &mut W) -> error::Result<()> {
+        let constructor = self.constructor()?;
+
+        self.id.serialize_to(buffer)?;
+        serialize_fields(constructor, self, buffer)
+    }
+
+    /// The number of bytes written before failing for values not matching the schema.
+    fn serialized_len(&self) -> usize {
+        // Lengths depend on the schema as much as serialization does, so just count
+        let mut counter = Counter(0);
+        let _ = self.serialize_to(&mut counter);
+
+        counter.0
+    }
+}
+
+/// Whether the conditional parameter of type `kind` is present in `value`.
+fn is_set(value: Option<&Value>, kind: &str) -> bool {
+    match value {
+        Some(&Value::Bool(value)) if kind == "true" => value,
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Serialize the parameters of `constructor`, following its identifier.
+fn serialize_fields<W: Write>(
+    constructor: &Constructor,
+    value: &TlValue,
+    buffer: &mut W,
+) -> error::Result<()> {
+    for param in constructor.params {
+        let field = value.fields.get(param.name);
+
+        if param.kind == "#" {
+            // Set the bit of every conditional parameter referring to this one
+            let mut flags = 0u32;
+
+            for other in constructor.params {
+                if let Some((flags_name, bit, kind)) = meta::condition(other.kind) {
+                    if flags_name == param.name && is_set(value.fields.get(other.name), kind) {
+                        flags |= 1 << bit;
+                    }
+                }
+            }
+
+            flags.serialize_to(buffer)?;
+            continue;
+        }
+
+        match (meta::condition(param.kind), field) {
+            (Some((_, _, "true")), _) | (Some(_), None) => {}
+            (Some((_, _, kind)), Some(field)) => serialize_kind(field, kind, buffer)?,
+            (None, Some(field)) => serialize_kind(field, param.kind, buffer)?,
+            (None, None) => {
+                bail!(invalid(format!("missing `{}` of `{}`", param.name, constructor.name)))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Serialize a value as the TL type `kind`.
+fn serialize_kind<W: Write>(value: &Value, kind: &str, buffer: &mut W) -> error::Result<()> {
+    match (kind, value) {
+        ("Bool", Value::Bool(value)) => value.serialize_to(buffer),
+        ("int", Value::Int(value)) => value.serialize_to(buffer),
+        ("long", Value::Long(value)) => value.serialize_to(buffer),
+        ("double", Value::Double(value)) => value.serialize_to(buffer),
+        ("int128", Value::Int128(value)) => value.serialize_to(buffer),
+        ("int256", Value::Int256(value)) => value.serialize_to(buffer),
+        ("string", Value::String(value)) => value.serialize_to(buffer),
+        ("bytes", Value::Bytes(value)) => value.serialize_to(buffer),
+
+        (_, Value::Vector(elements)) if meta::vector_element(kind).is_some() => {
+            let (element, boxed) = meta::vector_element(kind).unwrap();
+
+            if boxed {
+                0x1cb5c415u32.serialize_to(buffer)?;
+            }
+
+            (elements.len() as u32).serialize_to(buffer)?;
+            for value in elements {
+                serialize_kind(value, element, buffer)?;
+            }
+
+            Ok(())
+        }
+
+        (_, Value::Object(object)) if !is_primitive(kind) => match schema::registry().bare(kind) {
+            Some(constructor) if constructor.id == object.id => {
+                serialize_fields(constructor, object, buffer)
+            }
+
+            Some(constructor) => Err(invalid(format!(
+                "`{}` where the bare `{}` is expected",
+                object.name,
+                constructor.name
+            ))),
+
+            None => object.serialize_to(buffer),
+        },
+
+        _ => Err(invalid(format!("{} where `{}` is expected", value.kind(), kind))),
+    }
+}
+
+fn is_primitive(kind: &str) -> bool {
+    match kind {
+        "Bool" | "int" | "long" | "double" | "int128" | "int256" | "string" | "bytes" => true,
+        _ => meta::vector_element(kind).is_some(),
+    }
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use serde_json::{Map, Number};
+    use serde_json::Value as Json;
+
+    use error;
+    use meta;
+    use schema;
+    use super::{invalid, TlValue, Value};
+    use {Int128, Int256};
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn from_hex(hex: &str, bytes: &mut [u8]) -> error::Result<()> {
+        if hex.len() != bytes.len() * 2 || !hex.is_ascii() {
+            bail!(invalid(format!("`{}` is not {} hexadecimal bytes", hex, bytes.len())));
+        }
+
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16)
+                .map_err(|_| invalid(format!("`{}` is not hexadecimal", hex)))?;
+        }
+
+        Ok(())
+    }
+
+    impl TlValue {
+        /// Convert to JSON, with the name of the constructor as `_`, e.g.
+        /// `{"_": "user", "id": 1, ...}`.
+        ///
+        /// `long` values are written as strings, to survive JSON parsers using doubles,
+        /// `bytes` in base64 and `int128`/`int256` in hexadecimal.
+        pub fn to_json(&self) -> Json {
+            let mut object = Map::new();
+            object.insert("_".into(), Json::String(self.name.clone()));
+
+            for (name, value) in &self.fields {
+                object.insert(name.clone(), value.to_json());
+            }
+
+            Json::Object(object)
+        }
+
+        /// Convert from JSON written by `to_json`, interpreting the parameters with the
+        /// types of the schema.
+        pub fn from_json(json: &Json) -> error::Result<TlValue> {
+            let name = json
+                .get("_")
+                .and_then(Json::as_str)
+                .ok_or_else(|| invalid(format!("`{}` has no constructor name `_`", json)))?;
+
+            let constructor = schema::registry().by_name(name)
+                .ok_or_else(|| invalid(format!("unknown constructor `{}`", name)))?;
+
+            let mut fields = ::std::collections::BTreeMap::new();
+
+            for param in constructor.params {
+                let field = match json.get(param.name) {
+                    Some(field) if !field.is_null() => field,
+                    _ => continue,
+                };
+
+                let kind = match meta::condition(param.kind) {
+                    Some((_, _, "true")) => {
+                        if field.as_bool() == Some(true) {
+                            fields.insert(param.name.to_string(), Value::Bool(true));
+                        }
+
+                        continue;
+                    }
+
+                    Some((_, _, kind)) => kind,
+                    None if param.kind == "#" => continue,
+                    None => param.kind,
+                };
+
+                fields.insert(param.name.to_string(), Value::from_json(field, kind)?);
+            }
+
+            Ok(TlValue {
+                id: constructor.id,
+                name: constructor.name.to_string(),
+                fields,
+            })
+        }
+    }
+
+    impl Value {
+        pub fn to_json(&self) -> Json {
+            match *self {
+                Value::Bool(value) => Json::Bool(value),
+                Value::Int(value) => Json::Number(value.into()),
+                Value::Long(value) => Json::String(value.to_string()),
+                Value::Double(value) => Number::from_f64(value).map_or(Json::Null, Json::Number),
+                Value::Int128(ref value) => Json::String(to_hex(value.as_bytes())),
+                Value::Int256(ref value) => Json::String(to_hex(value.as_bytes())),
+                Value::String(ref value) => Json::String(value.clone()),
+                Value::Bytes(ref value) => Json::String(BASE64.encode(&value[..])),
+                Value::Vector(ref values) => Json::Array(values.iter().map(Value::to_json).collect()),
+                Value::Object(ref object) => object.to_json(),
+            }
+        }
+
+        /// Convert from JSON as the TL type `kind`.
+        pub fn from_json(json: &Json, kind: &str) -> error::Result<Value> {
+            let mismatch = || invalid(format!("`{}` is not a `{}`", json, kind));
+
+            let value = match kind {
+                "Bool" => Value::Bool(json.as_bool().ok_or_else(mismatch)?),
+
+                "int" => Value::Int(json
+                    .as_i64()
+                    .and_then(|value| if value as i32 as i64 == value { Some(value as i32) } else { None })
+                    .ok_or_else(mismatch)?),
+
+                "long" => Value::Long(match *json {
+                    Json::String(ref value) => value.parse().map_err(|_| mismatch())?,
+                    _ => json.as_i64().ok_or_else(mismatch)?,
+                }),
+
+                "double" => Value::Double(json.as_f64().ok_or_else(mismatch)?),
+
+                "int128" => {
+                    let mut bytes = [0; 16];
+                    from_hex(json.as_str().ok_or_else(mismatch)?, &mut bytes)?;
+
+                    Value::Int128(Int128(bytes))
+                }
+
+                "int256" => {
+                    let mut bytes = [0; 32];
+                    from_hex(json.as_str().ok_or_else(mismatch)?, &mut bytes)?;
+
+                    Value::Int256(Int256(bytes))
+                }
+
+                "string" => Value::String(json.as_str().ok_or_else(mismatch)?.to_string()),
+
+                "bytes" => Value::Bytes(BASE64
+                    .decode(json.as_str().ok_or_else(mismatch)?)
+                    .map_err(|_| mismatch())?
+                    .into()),
+
+                _ => match meta::vector_element(kind) {
+                    Some((element, _)) => Value::Vector(json
+                        .as_array()
+                        .ok_or_else(mismatch)?
+                        .iter()
+                        .map(|json| Value::from_json(json, element))
+                        .collect::<error::Result<_>>()?),
+
+                    None => Value::Object(TlValue::from_json(json)?),
+                },
+            };
+
+            Ok(value)
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! The builders of methods with conditional parameters, generated from a fixture schema as
+//! the API schema has none.
+
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+// The generated code refers to these from the root of the crate it is included in
+use telegram::{meta, ser, RemoteCall, TlConstructor, TlObject};
+
+#[allow(dead_code, unused_imports, clippy::too_many_arguments)]
+mod schema {
+    include!(concat!(env!("OUT_DIR"), "/flags_schema.rs"));
+}
+
+use telegram::de;
+use telegram::ser::Serialize;
+
+use schema::{messages, InputPeer};
+
+#[test]
+fn required_only() {
+    let query = messages::SendMessage::builder(InputPeer::InputPeerSelf, "hi".into(), 7).build();
+
+    assert!(!query.silent);
+    assert_eq!(query.reply_to_msg_id, None);
+
+    let buffer = query.to_vec().unwrap();
+    assert_eq!(&buffer[..4], &messages::SendMessage::CONSTRUCTOR_ID.to_le_bytes());
+
+    // No flag is set
+    assert_eq!(&buffer[4..8], &[0; 4]);
+    assert_eq!(de::from_slice::<messages::SendMessage>(&buffer).unwrap(), query);
+}
+
+#[test]
+fn conditional_parameters() {
+    let query = messages::SendMessage::builder(InputPeer::InputPeerSelf, "hi".into(), 7)
+        .silent(true)
+        .reply_to_msg_id(42)
+        .build();
+
+    assert!(query.silent);
+    assert!(!query.no_webpage);
+    assert_eq!(query.reply_to_msg_id, Some(42));
+    assert_eq!(query.tl_name(), "messages.sendMessage");
+
+    let buffer = query.to_vec().unwrap();
+    assert_eq!(buffer.len(), query.serialized_len());
+
+    // flags.0 for `reply_to_msg_id` and flags.5 for `silent`, which is only a bit
+    assert_eq!(&buffer[4..8], &[0b10_0001, 0, 0, 0]);
+    assert_eq!(&buffer[12..16], &42i32.to_le_bytes());
+    assert_eq!(de::from_slice::<messages::SendMessage>(&buffer).unwrap(), query);
+}
+
+#[test]
+fn registry() {
+    let registry = meta::Registry::new(&[schema::CONSTRUCTORS]);
+    let method = registry.get(messages::SendMessage::CONSTRUCTOR_ID).unwrap();
+
+    assert_eq!(method.name, "messages.sendMessage");
+    assert!(method.method);
+}
+
+fn reply<R: RemoteCall<Reply = bool>>(_: &R) {}
+
+#[test]
+fn without_conditional_parameters() {
+    // Methods whose parameters are all required are built as structs, with no builder
+    let query = messages::ReadHistory {
+        peer: InputPeer::InputPeerEmpty,
+        max_id: 10,
+    };
+
+    reply(&query);
+    assert_eq!(query.to_vec().unwrap().len(), 12);
+}
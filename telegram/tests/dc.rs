@@ -0,0 +1,273 @@
+//! Invoking methods on other data centers than the home one, against a fake server per
+//! data center.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::Future;
+use telegram::error::ErrorKind;
+use telegram::retry::RetryPolicy;
+use telegram::schema::{self, auth, help, storage, upload};
+use telegram::Client;
+
+use fake::{rpc_error, FakeServer};
+
+const EXPORTED: &[u8] = &[0xde, 0xad, 0xbe, 0xef];
+
+fn dc_option(id: i32) -> schema::DcOption {
+    schema::DcOption {
+        id,
+        hostname: String::new(),
+        ip_address: format!("10.0.0.{}", id),
+        port: 443,
+    }
+}
+
+/// Data centers 2, the home one, and 4.
+fn servers() -> (Rc<FakeServer>, Rc<FakeServer>) {
+    let home = FakeServer::new();
+    let other = FakeServer::new();
+
+    home.on(|_: help::GetConfig| {
+        Ok(schema::Config {
+            this_dc: 2,
+            dc_options: vec![dc_option(2), dc_option(4)],
+            ..Default::default()
+        })
+    });
+
+    home.on(|query: auth::ExportAuthorization| {
+        assert_eq!(query.dc_id, 4);
+
+        Ok(auth::ExportedAuthorization {
+            id: 1,
+            bytes: EXPORTED.to_vec().into(),
+        })
+    });
+
+    other.on(|query: auth::ImportAuthorization| {
+        assert_eq!(query.id, 1);
+        assert_eq!(&query.bytes[..], EXPORTED);
+
+        Ok(auth::Authorization {
+            expires: 0,
+            user: schema::UserSelf::default().into(),
+        })
+    });
+
+    (home, other)
+}
+
+fn client(home: &Rc<FakeServer>, other: &Rc<FakeServer>) -> Client {
+    let (home, other) = (home.clone(), other.clone());
+
+    Client::with_connector(dc_option(2), move |dc| match dc.id {
+        2 => {
+            assert_eq!(dc.ip_address, "10.0.0.2");
+            Ok(home.clone())
+        }
+
+        4 => {
+            assert_eq!(dc.ip_address, "10.0.0.4");
+            Ok(other.clone())
+        }
+
+        id => panic!("connecting to data center {}", id),
+    }).server_keys(vec![fake::rsa_key()])
+}
+
+fn location(dc_id: i32) -> schema::FileLocation {
+    schema::FileLocation_ {
+        dc_id,
+        volume_id: 1,
+        local_id: 2,
+        secret: 3,
+    }.into()
+}
+
+fn answer_get_file(server: &FakeServer) {
+    server.on(|query: upload::GetFile| {
+        Ok(upload::File {
+            type_: storage::FileType::default(),
+            mtime: 0,
+            bytes: vec![query.offset as u8; query.limit as usize].into(),
+        })
+    });
+}
+
+#[test]
+fn get_file_on_other_dc() {
+    let (home, other) = servers();
+    answer_get_file(&other);
+
+    let client = client(&home, &other);
+
+    let file = client.get_file(&location(4), 0, 4).wait().unwrap();
+    assert_eq!(&file.bytes[..], &[0, 0, 0, 0]);
+
+    let file = client.get_file(&location(4), 4, 4).wait().unwrap();
+    assert_eq!(&file.bytes[..], &[4, 4, 4, 4]);
+
+    // The authorization is only exported and imported the first time
+    assert_eq!(home.calls(), ["help.getConfig", "auth.exportAuthorization"]);
+    assert_eq!(
+        other.calls(),
+        ["auth.importAuthorization", "upload.getFile", "upload.getFile"]
+    );
+
+    // Each data center generated its own authorization key with the client
+    assert_eq!((home.auth_keys(), other.auth_keys()), (1, 1));
+    assert_eq!(client.home_dc(), 2);
+}
+
+#[test]
+fn get_file_on_home_dc() {
+    let (home, other) = servers();
+    answer_get_file(&home);
+
+    let client = client(&home, &other);
+    client.get_file(&location(2), 0, 1).wait().unwrap();
+
+    assert_eq!(home.calls(), ["upload.getFile"]);
+    assert!(other.calls().is_empty());
+
+    let error = client
+        .get_file(&schema::FileLocationUnavailable::default().into(), 0, 1)
+        .wait()
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::FileUnavailable => {}
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[test]
+fn file_migrate() {
+    let (home, other) = servers();
+    answer_get_file(&other);
+    home.on(|_: upload::GetFile| Err(rpc_error(303, "FILE_MIGRATE_4")));
+
+    let client = client(&home, &other);
+    client.get_file(&location(2), 0, 1).wait().unwrap();
+
+    assert_eq!(
+        home.calls(),
+        ["upload.getFile", "help.getConfig", "auth.exportAuthorization"]
+    );
+    assert_eq!(other.calls(), ["auth.importAuthorization", "upload.getFile"]);
+    assert_eq!(client.home_dc(), 2);
+}
+
+#[test]
+fn phone_migrate() {
+    let (home, other) = servers();
+
+    home.on(|_: auth::CheckPhone| Err(rpc_error(303, "PHONE_MIGRATE_4")));
+    other.on(|_: auth::CheckPhone| {
+        Ok(auth::CheckedPhone {
+            phone_registered: true,
+            phone_invited: false,
+        })
+    });
+
+    let client = client(&home, &other);
+    let query = auth::CheckPhone {
+        phone_number: "+15550100".into(),
+    };
+
+    assert!(client.invoke(query.clone()).wait().unwrap().phone_registered);
+    assert_eq!(client.home_dc(), 4);
+
+    // The account now lives on data center 4, which needs no authorization imported
+    client.invoke(query).wait().unwrap();
+
+    assert_eq!(home.calls(), ["auth.checkPhone", "help.getConfig"]);
+    assert_eq!(other.calls(), ["auth.checkPhone", "auth.checkPhone"]);
+}
+
+#[test]
+fn failed_export_is_retried() {
+    let (home, other) = servers();
+    answer_get_file(&other);
+    home.on(|_: auth::ExportAuthorization| Err(rpc_error(401, "AUTH_KEY_UNREGISTERED")));
+
+    let client = client(&home, &other);
+
+    let error = client.get_file(&location(4), 0, 1).wait().unwrap_err();
+    match *error.kind() {
+        ErrorKind::Rpc(401, ref message) => assert_eq!(message, "AUTH_KEY_UNREGISTERED"),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    assert!(client.get_file(&location(4), 0, 1).wait().is_err());
+
+    assert_eq!(
+        home.calls(),
+        [
+            "help.getConfig",
+            "auth.exportAuthorization",
+            "auth.exportAuthorization",
+        ]
+    );
+    assert!(other.calls().is_empty());
+}
+
+#[test]
+fn failed_import_is_retried() {
+    let (home, other) = servers();
+    answer_get_file(&other);
+
+    let backoff = Duration::from_millis(1);
+    let policy = RetryPolicy::default().backoff(backoff, backoff);
+    let client = client(&home, &other).retry_policy(policy);
+
+    // The transport failure of the shared import reaches the retry policy as one
+    other.lose_replies(1);
+    client.get_file(&location(4), 0, 1).wait().unwrap();
+
+    assert_eq!(
+        home.calls(),
+        [
+            "help.getConfig",
+            "auth.exportAuthorization",
+            "auth.exportAuthorization",
+        ]
+    );
+    assert_eq!(
+        other.calls(),
+        [
+            "auth.importAuthorization",
+            "auth.importAuthorization",
+            "upload.getFile",
+        ]
+    );
+}
+
+#[test]
+fn single_transport() {
+    let server = FakeServer::new();
+    server.on(|_: help::GetConfig| {
+        Ok(schema::Config {
+            dc_options: vec![dc_option(4)],
+            ..Default::default()
+        })
+    });
+
+    let client = fake::client(server);
+    let error = client.get_file(&location(4), 0, 1).wait().unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::UnknownDc(4) => {}
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
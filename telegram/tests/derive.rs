@@ -0,0 +1,161 @@
+//! Types declared outside of the schema with `#[derive(Serialize, Deserialize)]`.
+
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+
+mod renamed {
+    pub extern crate telegram as tg;
+}
+
+use telegram::de::{self, DeserializeOwned};
+use telegram::schema::{self, mtproto};
+use telegram::ser::{Flags, Serialize};
+
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + ::std::fmt::Debug>(value: &T) -> Vec<u8> {
+    let buffer = value.to_vec().unwrap();
+
+    assert_eq!(buffer.len(), value.serialized_len());
+    assert_eq!(&de::from_slice::<T>(&buffer).unwrap(), value);
+
+    buffer
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[id = 0x0badf00d]
+struct Newtype(i32);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[id = "0x0badf00e"]
+struct Pair<T>(T, T);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[id = 0x0badf00f]
+struct Conditional(
+    #[tl(flags)] Flags,
+    #[tl(flag = "0.0")] Option<i32>,
+    #[tl(flag = "0.3")] bool,
+);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[id = 0x0badf010]
+struct Annotated {
+    #[tl(bare)]
+    inner: Newtype,
+
+    #[tl(skip)]
+    cached: Option<String>,
+
+    #[tl(bare)]
+    salts: Vec<mtproto::FutureSalt>,
+}
+
+// The identifier is checked against the combinator at compile time
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[id = 0x9db1bc6d]
+#[tl(combinator = "peerUser user_id:int = Peer")]
+struct PeerUser {
+    user_id: i32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[tl(crate = "renamed::tg")]
+enum Either {
+    #[id = 0x0badf011]
+    Left(i32, String),
+
+    #[id = 0x0badf012]
+    Right {
+        #[tl(flags)]
+        flags: Flags,
+        #[tl(flag = "flags.1")]
+        value: Option<i64>,
+    },
+}
+
+#[test]
+fn tuple_structs() {
+    assert_eq!(round_trip(&Newtype(1)), [0x0d, 0xf0, 0xad, 0x0b, 1, 0, 0, 0]);
+
+    round_trip(&Pair(String::from("a"), String::from("b")));
+    round_trip(&Pair(1i64, 2i64));
+}
+
+#[test]
+fn flags() {
+    let set = round_trip(&Conditional(Flags, Some(5), true));
+    assert_eq!(&set[4..], &[0x09, 0, 0, 0, 5, 0, 0, 0]);
+
+    let unset = round_trip(&Conditional(Flags, None, false));
+    assert_eq!(&unset[4..], &[0, 0, 0, 0]);
+}
+
+#[test]
+fn bare_and_skipped_fields() {
+    let value = Annotated {
+        inner: Newtype(7),
+        cached: None,
+        salts: vec![mtproto::FutureSalt {
+            valid_since: 1,
+            valid_until: 2,
+            salt: 3,
+        }],
+    };
+
+    let buffer = round_trip(&value);
+
+    // Neither the identifier of `Newtype` nor those of the vector and `future_salt`
+    #[rustfmt::skip]
+    assert_eq!(&buffer[4..], &[
+        7, 0, 0, 0,
+        1, 0, 0, 0,
+        1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+    ][..]);
+
+    let cached = Annotated {
+        cached: Some("cached".into()),
+        ..value
+    };
+
+    assert_eq!(cached.to_vec().unwrap(), buffer);
+}
+
+#[test]
+fn enums() {
+    round_trip(&Either::Left(1, "left".into()));
+    round_trip(&Either::Right {
+        flags: Flags,
+        value: Some(2),
+    });
+    round_trip(&Either::Right {
+        flags: Flags,
+        value: None,
+    });
+}
+
+#[test]
+fn verified_id() {
+    let buffer = round_trip(&PeerUser { user_id: 1 });
+
+    assert_eq!(buffer, schema::PeerUser { user_id: 1 }.to_vec().unwrap());
+}
+
+#[test]
+fn msg_container() {
+    let container = mtproto::MessageContainer {
+        messages: vec![mtproto::Message {
+            msg_id: 1,
+            seqno: 2,
+            bytes: 4,
+            body: mtproto::Object {
+                packed_data: vec![].into(),
+            },
+        }],
+    };
+
+    let buffer = round_trip(&container);
+
+    // The messages are a bare vector of bare `message`s
+    assert_eq!(&buffer[4..8], &[1, 0, 0, 0]);
+    assert_eq!(&buffer[8..16], &[1, 0, 0, 0, 0, 0, 0, 0]);
+}
@@ -0,0 +1,9 @@
+//! Invalid input to `#[derive(Serialize, Deserialize)]`, which fails to compile with an
+//! error rather than a panic of the derive.
+
+extern crate trybuild;
+
+#[test]
+fn compile_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
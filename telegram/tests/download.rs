@@ -0,0 +1,200 @@
+//! Downloading files in chunks from the fake server.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use futures::{future, Async, Future, Stream};
+use telegram::download::{DownloadLocation, CHUNK_SIZE};
+use telegram::schema::{self, storage, upload};
+use telegram::secret::FileKey;
+
+use fake::FakeServer;
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// A server with a file, recording the offsets requested.
+fn server(file: Vec<u8>) -> (Rc<FakeServer>, Rc<RefCell<Vec<i32>>>) {
+    let server = FakeServer::new();
+    let offsets = Rc::new(RefCell::new(Vec::new()));
+
+    let requested = offsets.clone();
+    server.on(move |query: upload::GetFile| {
+        assert_eq!(query.limit as usize, CHUNK_SIZE);
+        assert_eq!(query.offset as usize % CHUNK_SIZE, 0);
+        requested.borrow_mut().push(query.offset);
+
+        let start = (query.offset as usize).min(file.len());
+        let end = (start + query.limit as usize).min(file.len());
+
+        Ok(upload::File {
+            type_: storage::FileType::FileJpeg,
+            mtime: 0,
+            bytes: file[start..end].to_vec().into(),
+        })
+    });
+
+    (server, offsets)
+}
+
+fn photo() -> schema::FileLocation_ {
+    schema::FileLocation_ {
+        dc_id: 2,
+        volume_id: 1,
+        local_id: 2,
+        secret: 3,
+    }
+}
+
+#[test]
+fn stream() {
+    let contents = contents(2 * CHUNK_SIZE + 100);
+    let (server, offsets) = server(contents.clone());
+    let client = fake::client(server);
+
+    let chunks = client.download(photo()).concurrency(2).collect().wait().unwrap();
+
+    assert_eq!(
+        chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+        [CHUNK_SIZE, CHUNK_SIZE, 100]
+    );
+    assert_eq!(chunks.concat(), contents);
+
+    // Without the size of the file, the chunks are requested until a short one, and as
+    // many as the concurrency allows at a time
+    let chunk = CHUNK_SIZE as i32;
+    assert_eq!(*offsets.borrow(), [0, chunk, 2 * chunk, 3 * chunk]);
+}
+
+#[test]
+fn concurrent_chunks() {
+    let contents = contents(3 * CHUNK_SIZE);
+    let (server, offsets) = server(contents.clone());
+    let client = fake::client(server.clone());
+
+    let location = DownloadLocation {
+        size: Some(contents.len() as u64),
+        ..photo().into()
+    };
+
+    server.hold_replies();
+    let mut download = client.download(location).concurrency(2);
+
+    let polled = future::poll_fn(|| Ok::<_, ()>(Async::Ready(download.poll())))
+        .wait()
+        .unwrap();
+    assert!(polled.unwrap().is_not_ready());
+
+    // Both chunks were requested before either reply arrived
+    let chunk = CHUNK_SIZE as i32;
+    assert_eq!(server.calls(), ["upload.getFile", "upload.getFile"]);
+    assert_eq!(*offsets.borrow(), [0, chunk]);
+
+    server.release_replies();
+    let chunks = download.collect().wait().unwrap();
+
+    assert_eq!(chunks.concat(), contents);
+    assert_eq!(*offsets.borrow(), [0, chunk, 2 * chunk]);
+}
+
+#[test]
+fn known_size() {
+    let contents = contents(2 * CHUNK_SIZE);
+    let (server, offsets) = server(contents.clone());
+    let client = fake::client(server);
+
+    let document = schema::Document_ {
+        id: 1,
+        access_hash: 2,
+        size: contents.len() as i32,
+        dc_id: 2,
+        ..Default::default()
+    };
+
+    let mut download = client.download(document);
+    assert_eq!(download.file_type(), None);
+
+    let first = download.by_ref().take(1).collect().wait().unwrap();
+    assert_eq!(first.concat(), &contents[..CHUNK_SIZE]);
+    assert_eq!(download.file_type(), Some(&storage::FileType::FileJpeg));
+
+    let rest = download.collect().wait().unwrap();
+    assert_eq!(rest.concat(), &contents[CHUNK_SIZE..]);
+
+    // No chunk is requested past the end of the file
+    assert_eq!(*offsets.borrow(), [0, CHUNK_SIZE as i32]);
+}
+
+#[test]
+fn resume() {
+    let contents = contents(3 * CHUNK_SIZE);
+    let (server, offsets) = server(contents.clone());
+    let client = fake::client(server);
+
+    let location = DownloadLocation {
+        size: Some(contents.len() as u64),
+        ..photo().into()
+    };
+
+    let offset = CHUNK_SIZE + 1000;
+    let chunks = client
+        .download(location)
+        .offset(offset as u64)
+        .collect()
+        .wait()
+        .unwrap();
+
+    assert_eq!(chunks.concat(), &contents[offset..]);
+    assert_eq!(*offsets.borrow(), [CHUNK_SIZE as i32, 2 * CHUNK_SIZE as i32]);
+}
+
+#[test]
+fn decrypt_from_offset() {
+    let contents = contents(CHUNK_SIZE);
+    let (server, offsets) = server(contents.clone());
+    let client = fake::client(server);
+    let key = FileKey::random();
+
+    // Either order fails on the first poll without asking the server for anything
+    let download = client
+        .download(photo())
+        .offset(16)
+        .decrypt(&key, contents.len() as u64);
+    let (_, download) = download.into_future().wait().err().unwrap();
+    assert!(download.into_future().wait().ok().unwrap().0.is_none());
+
+    let download = client
+        .download(photo())
+        .decrypt(&key, contents.len() as u64)
+        .offset(16);
+    assert!(download.collect().wait().is_err());
+
+    assert!(offsets.borrow().is_empty());
+}
+
+#[test]
+fn save() {
+    let contents = contents(CHUNK_SIZE + 1);
+    let (server, _) = server(contents.clone());
+    let client = fake::client(server);
+
+    let path = std::env::temp_dir().join(format!("telegram-download-{}", std::process::id()));
+    let downloaded = client.download(photo()).save(&path).wait().unwrap();
+
+    assert_eq!(downloaded.size, contents.len() as u64);
+    assert_eq!(downloaded.file_type, storage::FileType::FileJpeg);
+    assert_eq!(fs::read(&path).unwrap(), contents);
+
+    fs::remove_file(&path).unwrap();
+}
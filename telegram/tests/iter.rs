@@ -0,0 +1,244 @@
+//! Paging through dialogs and messages of the fake server.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use telegram::schema::{self, messages};
+
+use fake::{rpc_error, FakeServer};
+
+fn peer() -> schema::InputPeer {
+    schema::InputPeerContact { user_id: 7 }.into()
+}
+
+fn message(id: i32) -> schema::Message {
+    schema::Message_ {
+        id,
+        to_id: schema::PeerUser { user_id: 7 }.into(),
+        message: format!("message {}", id),
+        ..Default::default()
+    }.into()
+}
+
+fn message_id(message: &schema::Message) -> i32 {
+    match *message {
+        schema::Message::Message(ref message) => message.id,
+        _ => panic!("unexpected {:?}", message),
+    }
+}
+
+/// The messages of `ids` below `max_id`, newest first, as the server pages them.
+fn page(ids: &[i32], max_id: i32, limit: i32) -> Vec<schema::Message> {
+    let mut ids: Vec<i32> = ids
+        .iter()
+        .cloned()
+        .filter(|&id| max_id == 0 || id < max_id)
+        .collect();
+
+    ids.sort_by(|a, b| b.cmp(a));
+    ids.into_iter().take(limit as usize).map(message).collect()
+}
+
+#[test]
+fn history() {
+    let server = FakeServer::new();
+    server.on(|query: messages::GetHistory| {
+        assert_eq!(query.peer, peer());
+        assert_eq!(query.offset, 0);
+
+        let ids: Vec<i32> = (1..251).collect();
+        Ok(messages::MessagesSlice {
+            count: 250,
+            messages: page(&ids, query.max_id, query.limit),
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    let (messages, history) = client
+        .iter_history(peer())
+        .into_future()
+        .map_err(|(error, _)| error)
+        .wait()
+        .unwrap();
+
+    assert_eq!(history.total(), Some(250));
+
+    let messages: Vec<_> = messages
+        .into_iter()
+        .chain(history.collect().wait().unwrap())
+        .map(|message| message_id(&message))
+        .collect();
+
+    assert_eq!(messages, (1..251).rev().collect::<Vec<_>>());
+    assert_eq!(server.calls().len(), 3);
+}
+
+#[test]
+fn page_boundaries() {
+    let ids = Rc::new(RefCell::new((1..11).collect::<Vec<i32>>()));
+    let max_ids = Rc::new(RefCell::new(Vec::new()));
+
+    let server = FakeServer::new();
+    let (stored, asked) = (ids.clone(), max_ids.clone());
+    server.on(move |query: messages::GetHistory| {
+        asked.borrow_mut().push(query.max_id);
+
+        // Includes the message at `max_id`, and gets new messages between pages
+        let mut ids = stored.borrow_mut();
+        let below = if query.max_id == 0 { 0 } else { query.max_id + 1 };
+        let messages = page(&ids, below, query.limit);
+        let next = ids.len() as i32 + 1;
+        ids.push(next);
+
+        Ok(messages::MessagesSlice {
+            count: ids.len() as i32,
+            messages,
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server);
+    let messages: Vec<_> = client
+        .iter_history(peer())
+        .page_size(4)
+        .collect()
+        .wait()
+        .unwrap()
+        .iter()
+        .map(message_id)
+        .collect();
+
+    assert_eq!(messages, (1..11).rev().collect::<Vec<_>>());
+    assert_eq!(*max_ids.borrow(), [0, 7, 4, 1]);
+}
+
+#[test]
+fn whole_history() {
+    let server = FakeServer::new();
+    server.on(|_: messages::GetHistory| {
+        Ok(messages::Messages_ {
+            messages: vec![message(2), message(1)],
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    let mut history = client.iter_history(peer()).page_size(2);
+    let messages = history.by_ref().collect().wait().unwrap();
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(history.total(), Some(2));
+    assert_eq!(server.calls(), ["messages.getHistory"]);
+}
+
+#[test]
+fn dialogs() {
+    let server = FakeServer::new();
+    server.on(|query: messages::GetDialogs| {
+        let dialogs: Vec<schema::Dialog> = [30, 20, 10]
+            .iter()
+            .filter(|&&top| query.max_id == 0 || top < query.max_id)
+            .take(query.limit as usize)
+            .map(|&top| schema::Dialog {
+                peer: schema::PeerUser { user_id: top }.into(),
+                top_message: top,
+                ..Default::default()
+            })
+            .collect();
+
+        // The top message of the last dialog is missing
+        let messages = dialogs
+            .iter()
+            .filter(|dialog| dialog.top_message != 10)
+            .map(|dialog| message(dialog.top_message))
+            .collect();
+
+        Ok(messages::DialogsSlice {
+            count: 3,
+            dialogs,
+            messages,
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    let dialogs = client.iter_dialogs().page_size(2).collect().wait().unwrap();
+
+    let tops: Vec<_> = dialogs
+        .iter()
+        .map(|dialog| {
+            let top = dialog.top_message.as_ref().map(message_id);
+            (dialog.dialog.top_message, top)
+        })
+        .collect();
+
+    assert_eq!(tops, [(30, Some(30)), (20, Some(20)), (10, None)]);
+    assert_eq!(server.calls().len(), 2);
+}
+
+#[test]
+fn search() {
+    let server = FakeServer::new();
+    server.on(|query: messages::Search| {
+        assert_eq!(query.peer, peer());
+        assert_eq!(query.q, "cat");
+        assert_eq!(query.filter, schema::MessagesFilter::InputMessagesFilterPhotos);
+
+        Ok(messages::MessagesSlice {
+            count: 1,
+            messages: vec![message(5)],
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server);
+    let found = client
+        .iter_search(peer(), "cat", schema::MessagesFilter::InputMessagesFilterPhotos)
+        .collect()
+        .wait()
+        .unwrap();
+
+    assert_eq!(found, [message(5)]);
+}
+
+#[test]
+fn flood_wait() {
+    let server = FakeServer::new();
+    let attempts = Cell::new(0);
+    server.on(move |_: messages::GetHistory| {
+        attempts.set(attempts.get() + 1);
+
+        if attempts.get() == 1 {
+            return Err(rpc_error(420, "FLOOD_WAIT_0"));
+        }
+
+        Ok(messages::Messages_ {
+            messages: vec![message(1)],
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    let messages = client.iter_history(peer()).collect().wait().unwrap();
+
+    assert_eq!(messages, [message(1)]);
+    assert_eq!(server.calls().len(), 2);
+
+    // Other errors end the stream
+    let server = FakeServer::new();
+    server.on(|_: messages::GetHistory| Err(rpc_error(400, "PEER_ID_INVALID")));
+
+    let client = fake::client(server);
+    assert!(client.iter_history(peer()).collect().wait().is_err());
+}
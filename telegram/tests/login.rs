@@ -0,0 +1,217 @@
+//! Logging in against the fake server, through each of the states of `LoginState`.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use futures::Future;
+use telegram::error::ErrorKind;
+use telegram::login::{self, CheckPassword, GetPassword, LoginState, Password};
+use telegram::schema::{self, auth};
+
+use fake::{rpc_error, FakeServer};
+
+const PHONE_NUMBER: &str = "+15550100";
+const PHONE_CODE_HASH: &str = "4f2e";
+const CODE: &str = "12345";
+
+fn user(id: i32) -> schema::User {
+    schema::UserSelf {
+        id,
+        first_name: "Ada".into(),
+        ..Default::default()
+    }.into()
+}
+
+fn authorization(id: i32) -> auth::Authorization {
+    auth::Authorization {
+        expires: 0,
+        user: user(id),
+    }
+}
+
+/// A server for `PHONE_NUMBER`, sending `CODE`.
+fn server(registered: bool) -> std::rc::Rc<FakeServer> {
+    let server = FakeServer::new();
+
+    server.on(move |query: auth::CheckPhone| {
+        assert_eq!(query.phone_number, PHONE_NUMBER);
+
+        Ok(auth::CheckedPhone {
+            phone_registered: registered,
+            phone_invited: false,
+        })
+    });
+
+    server.on(move |query: auth::SendCode| {
+        assert_eq!(query.api_id, 12345);
+        assert_eq!(query.api_hash, "0123456789abcdef");
+
+        Ok(auth::SentCode_ {
+            phone_registered: registered,
+            phone_code_hash: PHONE_CODE_HASH.into(),
+            send_call_timeout: 120,
+            is_password: false,
+        }.into())
+    });
+
+    server
+}
+
+fn login(server: &std::rc::Rc<FakeServer>) -> login::CodeSent {
+    let client = fake::client(server.clone());
+
+    match client.login(PHONE_NUMBER, 12345, "0123456789abcdef").wait().unwrap() {
+        LoginState::CodeSent(code_sent) => code_sent,
+        state => panic!("unexpected {:?}", state),
+    }
+}
+
+fn rpc_message(error: &telegram::error::Error) -> &str {
+    match *error.kind() {
+        ErrorKind::Rpc(_, ref message) => message,
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[test]
+fn sign_in() {
+    let server = server(true);
+
+    server.on(|query: auth::SignIn| {
+        assert_eq!(query.phone_code_hash, PHONE_CODE_HASH);
+
+        if query.phone_code == CODE {
+            Ok(authorization(1))
+        } else {
+            Err(rpc_error(400, "PHONE_CODE_INVALID"))
+        }
+    });
+
+    server.on(|query: auth::SendSms| {
+        assert_eq!(query.phone_code_hash, PHONE_CODE_HASH);
+        Ok(true)
+    });
+
+    let code_sent = login(&server);
+    assert!(code_sent.phone_registered());
+    assert_eq!(code_sent.send_call_timeout(), 120);
+
+    assert!(code_sent.resend_sms().wait().unwrap());
+
+    let error = code_sent.sign_in("00000").wait().unwrap_err();
+    assert_eq!(rpc_message(&error), "PHONE_CODE_INVALID");
+
+    match code_sent.sign_in(CODE).wait().unwrap() {
+        LoginState::LoggedIn(logged_in) => assert_eq!(logged_in, user(1)),
+        state => panic!("unexpected {:?}", state),
+    }
+
+    assert_eq!(
+        server.calls(),
+        [
+            "auth.checkPhone",
+            "auth.sendCode",
+            "auth.sendSms",
+            "auth.signIn",
+            "auth.signIn",
+        ]
+    );
+}
+
+#[test]
+fn sign_up() {
+    let server = server(false);
+
+    server.on(|query: auth::SignUp| {
+        assert_eq!(query.phone_code, CODE);
+        assert_eq!(query.first_name, "Ada");
+        assert_eq!(query.last_name, "Lovelace");
+
+        Ok(authorization(2))
+    });
+
+    let code_sent = login(&server);
+    assert!(!code_sent.phone_registered());
+
+    let needs_sign_up = match code_sent.sign_in(CODE).wait().unwrap() {
+        LoginState::NeedsSignUp(needs_sign_up) => needs_sign_up,
+        state => panic!("unexpected {:?}", state),
+    };
+
+    match needs_sign_up.sign_up("Ada", "Lovelace").wait().unwrap() {
+        LoginState::LoggedIn(logged_in) => assert_eq!(logged_in, user(2)),
+        state => panic!("unexpected {:?}", state),
+    }
+
+    assert_eq!(
+        server.calls(),
+        ["auth.checkPhone", "auth.sendCode", "auth.signUp"]
+    );
+}
+
+#[test]
+fn password() {
+    let server = server(true);
+
+    server.on(|_: auth::SignIn| Err(rpc_error(401, "SESSION_PASSWORD_NEEDED")));
+
+    server.on(|_: GetPassword| {
+        Ok(Password::Password {
+            current_salt: vec![1, 2, 3].into(),
+            new_salt: vec![4, 5, 6].into(),
+            hint: "the usual".into(),
+            has_recovery: false,
+            email_unconfirmed_pattern: String::new(),
+        })
+    });
+
+    server.on(|query: CheckPassword| {
+        if query.password_hash == login::password_hash(&[1, 2, 3], "hunter2") {
+            Ok(authorization(3))
+        } else {
+            Err(rpc_error(400, "PASSWORD_HASH_INVALID"))
+        }
+    });
+
+    let needs_password = match login(&server).sign_in(CODE).wait().unwrap() {
+        LoginState::NeedsPassword(needs_password) => needs_password,
+        state => panic!("unexpected {:?}", state),
+    };
+
+    assert_eq!(needs_password.hint(), Some("the usual"));
+
+    let error = needs_password.check_password("hunter3").wait().unwrap_err();
+    assert_eq!(rpc_message(&error), "PASSWORD_HASH_INVALID");
+
+    match needs_password.check_password("hunter2").wait().unwrap() {
+        LoginState::LoggedIn(logged_in) => assert_eq!(logged_in, user(3)),
+        state => panic!("unexpected {:?}", state),
+    }
+
+    assert_eq!(
+        server.calls(),
+        [
+            "auth.checkPhone",
+            "auth.sendCode",
+            "auth.signIn",
+            "0x548a30f5",
+            "0x0a63011e",
+            "0x0a63011e",
+        ]
+    );
+}
+
+#[test]
+fn password_hash() {
+    // sha256 of "\x01\x02\x03" "hunter2" "\x01\x02\x03"
+    let hash = login::password_hash(&[1, 2, 3], "hunter2");
+
+    assert_eq!(hash.len(), 32);
+    assert_ne!(hash, login::password_hash(&[1, 2, 4], "hunter2"));
+}
@@ -0,0 +1,244 @@
+//! Sending messages to the fake server, and matching them with their updates.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use telegram::error::ErrorKind;
+use telegram::schema::{self, messages};
+
+use fake::{rpc_error, FakeServer};
+
+fn peer() -> schema::InputPeer {
+    schema::InputPeerContact { user_id: 7 }.into()
+}
+
+fn message(id: i32, text: &str) -> schema::Message {
+    schema::Message_ {
+        id,
+        to_id: schema::PeerUser { user_id: 7 }.into(),
+        message: text.into(),
+        ..Default::default()
+    }.into()
+}
+
+/// Answers `messages.sendMessage` with the message 10, rejecting a random identifier it
+/// was already sent, and records the random identifiers.
+fn server() -> (Rc<FakeServer>, Rc<RefCell<Vec<i64>>>) {
+    let server = FakeServer::new();
+    let random_ids = Rc::new(RefCell::new(Vec::new()));
+    let seen = RefCell::new(HashSet::new());
+
+    let received = random_ids.clone();
+    server.on(move |query: messages::SendMessage| {
+        assert_eq!(query.peer, peer());
+        received.borrow_mut().push(query.random_id);
+
+        if !seen.borrow_mut().insert(query.random_id) {
+            return Err(rpc_error(400, "RANDOM_ID_DUPLICATE"));
+        }
+
+        Ok(messages::SentMessage_ {
+            id: 10,
+            date: 1,
+            pts: 2,
+            seq: 3,
+        }.into())
+    });
+
+    (server, random_ids)
+}
+
+fn update_short(update: schema::Update) -> schema::Updates {
+    schema::UpdateShort { update, date: 1 }.into()
+}
+
+fn new_message(message: schema::Message) -> schema::Update {
+    schema::UpdateNewMessage { message, pts: 2 }.into()
+}
+
+#[test]
+fn send_message() {
+    let (server, random_ids) = server();
+    let client = fake::client(server.clone());
+
+    let updates = {
+        let client = client.clone();
+
+        future::lazy(move || {
+            // Other messages don't resolve the send
+            client.handle_updates(&update_short(new_message(message(9, "other"))));
+            client.handle_updates(&update_short(new_message(message(10, "hello"))));
+
+            Ok(())
+        })
+    };
+
+    let (sent, ()) = client.send_message(peer(), "hello").join(updates).wait().unwrap();
+
+    assert_eq!(sent, message(10, "hello"));
+    assert_eq!(random_ids.borrow().len(), 1);
+}
+
+#[test]
+fn lost_reply() {
+    let (server, random_ids) = server();
+    let client = fake::client(server.clone());
+
+    // The message is sent, but the reply with its identifier is lost
+    server.lose_replies(1);
+
+    let updates = {
+        let (client, random_ids) = (client.clone(), random_ids.clone());
+
+        future::lazy(move || {
+            let random_id = random_ids.borrow()[0];
+
+            // The message arrives before the update telling it is the one sent
+            client.handle_updates(
+                &schema::Updates_ {
+                    updates: vec![
+                        new_message(message(10, "hello")),
+                        schema::UpdateMessageId { id: 10, random_id }.into(),
+                    ],
+                    ..Default::default()
+                }.into(),
+            );
+
+            Ok(())
+        })
+    };
+
+    let (sent, ()) = client.send_message(peer(), "hello").join(updates).wait().unwrap();
+    assert_eq!(sent, message(10, "hello"));
+
+    // The retry had the same random identifier, so the message was sent once
+    let random_ids = random_ids.borrow();
+    assert_eq!(random_ids.len(), 2);
+    assert_eq!(random_ids[0], random_ids[1]);
+}
+
+#[test]
+fn rpc_errors_are_not_retried() {
+    let server = FakeServer::new();
+    server.on(|_: messages::SendMessage| Err(rpc_error(400, "PEER_ID_INVALID")));
+
+    let client = fake::client(server.clone());
+    let error = client.send_message(peer(), "hello").wait().unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::Rpc(400, ref message) => assert_eq!(message, "PEER_ID_INVALID"),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    assert_eq!(server.calls(), ["messages.sendMessage"]);
+}
+
+#[test]
+fn send_media() {
+    let server = FakeServer::new();
+    let random_ids = Rc::new(RefCell::new(Vec::new()));
+
+    let received = random_ids.clone();
+    server.on(move |query: messages::SendMedia| {
+        assert_eq!(query.media, schema::InputMedia::InputMediaEmpty);
+        received.borrow_mut().push(query.random_id);
+
+        Ok(messages::StatedMessage_ {
+            message: message(11, ""),
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    server.lose_replies(1);
+
+    let sent = client
+        .send_media(peer(), schema::InputMedia::InputMediaEmpty)
+        .wait()
+        .unwrap();
+
+    assert_eq!(sent, message(11, ""));
+
+    let random_ids = random_ids.borrow();
+    assert_eq!(random_ids.len(), 2);
+    assert_eq!(random_ids[0], random_ids[1]);
+}
+
+#[test]
+fn send_media_sent_before() {
+    let server = FakeServer::new();
+    let random_ids = Rc::new(RefCell::new(Vec::new()));
+
+    let received = random_ids.clone();
+    server.on(move |query: messages::SendMedia| {
+        received.borrow_mut().push(query.random_id);
+
+        if received.borrow().len() > 1 {
+            return Err(rpc_error(400, "RANDOM_ID_DUPLICATE"));
+        }
+
+        Ok(messages::StatedMessage_ {
+            message: message(11, ""),
+            ..Default::default()
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    server.lose_replies(1);
+
+    let updates = {
+        let (client, random_ids) = (client.clone(), random_ids.clone());
+
+        future::lazy(move || {
+            let random_id = random_ids.borrow()[0];
+
+            client.handle_updates(
+                &schema::Updates_ {
+                    updates: vec![
+                        schema::UpdateMessageId { id: 11, random_id }.into(),
+                        new_message(message(11, "")),
+                    ],
+                    ..Default::default()
+                }.into(),
+            );
+
+            Ok(())
+        })
+    };
+
+    // The retry is rejected as a duplicate, and the message comes with the updates
+    let (sent, ()) = client
+        .send_media(peer(), schema::InputMedia::InputMediaEmpty)
+        .join(updates)
+        .wait()
+        .unwrap();
+
+    assert_eq!(sent, message(11, ""));
+    assert_eq!(random_ids.borrow().len(), 2);
+}
+
+#[test]
+fn update_timeout() {
+    let (server, _) = server();
+    let client = fake::client(server.clone()).update_timeout(Duration::from_millis(10));
+
+    // The updates aren't handed to the client
+    let error = client.send_message(peer(), "hello").wait().unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::UpdateTimeout(_) => {}
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
@@ -0,0 +1,241 @@
+//! The peer cache, filled from the replies of the fake server.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use futures::Future;
+use telegram::de::{Deserialize, Deserializer};
+use telegram::error::ErrorKind;
+use telegram::peers::PeerCache;
+use telegram::schema::{self, contacts, messages, users};
+use telegram::ser::Serialize;
+use telegram::store::{FileStore, MemoryStore};
+
+use fake::{rpc_error, FakeServer};
+
+fn foreign(id: i32, username: &str) -> schema::User {
+    schema::UserForeign {
+        id,
+        username: username.into(),
+        access_hash: i64::from(id) * 1000,
+        ..Default::default()
+    }.into()
+}
+
+fn contact(id: i32) -> schema::User {
+    schema::UserContact {
+        id,
+        access_hash: 1,
+        phone: "15550100".into(),
+        ..Default::default()
+    }.into()
+}
+
+fn user_peer(user_id: i32) -> schema::Peer {
+    schema::PeerUser { user_id }.into()
+}
+
+#[test]
+fn users_of_replies() {
+    let server = FakeServer::new();
+    server.on(|_: contacts::GetContacts| {
+        Ok(contacts::Contacts_ {
+            contacts: vec![schema::Contact {
+                user_id: 5,
+                mutual: true,
+            }],
+            users: vec![contact(5), foreign(6, "Foreign")],
+        }.into())
+    });
+
+    let client = fake::client(server);
+    client
+        .invoke(contacts::GetContacts {
+            hash: String::new(),
+        })
+        .wait()
+        .unwrap();
+
+    assert_eq!(
+        client.input_peer(&user_peer(5)).unwrap(),
+        schema::InputPeerContact { user_id: 5 }.into()
+    );
+    assert_eq!(
+        client.input_peer(&user_peer(6)).unwrap(),
+        schema::InputPeerForeign {
+            user_id: 6,
+            access_hash: 6000,
+        }.into()
+    );
+    assert_eq!(
+        client.input_user(6).unwrap(),
+        schema::InputUserForeign {
+            user_id: 6,
+            access_hash: 6000,
+        }.into()
+    );
+
+    assert_eq!(client.peers().by_phone("+15550100").unwrap().id, 5);
+    assert_eq!(client.peers().by_username("@foreign").unwrap().id, 6);
+
+    let error = client.input_peer(&user_peer(7)).unwrap_err();
+    match *error.kind() {
+        ErrorKind::UnknownPeer(ref peer) => assert_eq!(peer, "user 7"),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    let error = client.input_peer(&schema::PeerChat { chat_id: 8 }.into()).unwrap_err();
+    match *error.kind() {
+        ErrorKind::UnknownPeer(ref peer) => assert_eq!(peer, "chat 8"),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[test]
+fn vector_replies() {
+    let server = FakeServer::new();
+    server.on(|query: users::GetUsers| {
+        assert_eq!(query.id, [schema::InputUser::InputUserSelf]);
+
+        Ok(vec![
+            schema::UserSelf {
+                id: 1,
+                ..Default::default()
+            }.into(),
+            foreign(2, ""),
+        ])
+    });
+
+    let client = fake::client(server);
+    client
+        .invoke(users::GetUsers {
+            id: vec![schema::InputUser::InputUserSelf],
+        })
+        .wait()
+        .unwrap();
+
+    assert_eq!(
+        client.input_peer(&user_peer(1)).unwrap(),
+        schema::InputPeer::InputPeerSelf
+    );
+    assert!(client.peers().user(2).unwrap().access_hash.is_some());
+}
+
+#[test]
+fn updates() {
+    let client = fake::client(FakeServer::new());
+
+    client.handle_updates(
+        &schema::Updates_ {
+            users: vec![foreign(3, "")],
+            chats: vec![schema::Chat_ {
+                id: 4,
+                title: "Chat".into(),
+                ..Default::default()
+            }.into()],
+            ..Default::default()
+        }.into(),
+    );
+
+    assert!(client.input_peer(&user_peer(3)).is_ok());
+    assert_eq!(
+        client.input_peer(&schema::PeerChat { chat_id: 4 }.into()).unwrap(),
+        schema::InputPeerChat { chat_id: 4 }.into()
+    );
+}
+
+#[test]
+fn deserializer() {
+    let reply: messages::Messages = messages::Messages_ {
+        chats: vec![schema::ChatForbidden {
+            id: 4,
+            title: "Chat".into(),
+            date: 1,
+        }.into()],
+        users: vec![foreign(3, "three")],
+        ..Default::default()
+    }.into();
+    let buffer = reply.to_vec().unwrap();
+
+    // The peers are added as the reply is read, without reading it again
+    let peers = PeerCache::new();
+    let read: messages::Messages =
+        Deserialize::deserialize_from(&mut Deserializer::new(&buffer).observing(&peers))
+            .unwrap();
+
+    assert_eq!(read, reply);
+    assert_eq!(peers.user(3).unwrap().username, "three");
+    assert_eq!(peers.chat(4).unwrap().title, "Chat");
+}
+
+#[test]
+fn resolve() {
+    let server = FakeServer::new();
+    server.on(|query: contacts::ResolveUsername| match &query.username[..] {
+        "durov" => Ok(foreign(10, "durov")),
+        _ => Err(rpc_error(400, "USERNAME_NOT_OCCUPIED")),
+    });
+
+    let client = fake::client(server.clone());
+    let expected: schema::InputPeer = schema::InputPeerForeign {
+        user_id: 10,
+        access_hash: 10000,
+    }.into();
+
+    assert_eq!(client.resolve("@durov").wait().unwrap(), expected);
+
+    // Then from the cache, regardless of case
+    assert_eq!(client.resolve("Durov").wait().unwrap(), expected);
+    assert_eq!(server.calls(), ["contacts.resolveUsername"]);
+
+    assert!(client.resolve("@nobody").wait().is_err());
+}
+
+#[test]
+fn persistence() {
+    let client = fake::client(FakeServer::new());
+    client.handle_updates(
+        &schema::Updates_ {
+            users: vec![foreign(3, "three"), contact(5)],
+            chats: vec![schema::ChatForbidden {
+                id: 4,
+                title: "Chat".into(),
+                ..Default::default()
+            }.into()],
+            ..Default::default()
+        }.into(),
+    );
+
+    let dir = std::env::temp_dir().join(format!("telegram-peers-{}", std::process::id()));
+    let file_store = FileStore::new(&dir).unwrap();
+    let memory_store = MemoryStore::new();
+
+    client.peers().save(&file_store).unwrap();
+    client.peers().save(&memory_store).unwrap();
+
+    for store in &[&file_store as &dyn telegram::store::SessionStore, &memory_store] {
+        let restored = fake::client(FakeServer::new());
+        restored.peers().load(*store).unwrap();
+
+        for id in &[3, 5] {
+            assert_eq!(restored.peers().user(*id), client.peers().user(*id));
+        }
+
+        assert_eq!(restored.peers().chat(4).unwrap().title, "Chat");
+        assert_eq!(restored.peers().by_username("three").unwrap().id, 3);
+    }
+
+    // Nothing saved yet
+    fake::client(FakeServer::new())
+        .peers()
+        .load(&MemoryStore::new())
+        .unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
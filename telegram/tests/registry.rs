@@ -0,0 +1,84 @@
+//! The registry describes the constructors and methods of the schema at runtime.
+
+extern crate telegram;
+
+use telegram::de;
+use telegram::error::ErrorKind;
+use telegram::schema::{self, mtproto};
+use telegram::TlConstructor;
+
+#[test]
+fn constructors() {
+    let registry = schema::registry();
+    let res_pq = registry.get(mtproto::ResPq::CONSTRUCTOR_ID).unwrap();
+
+    assert_eq!(res_pq.name, "resPQ");
+    assert_eq!(res_pq.kind, "ResPQ");
+    assert!(!res_pq.method);
+    assert_eq!(res_pq.returns(), None);
+
+    let params = res_pq.params.iter().map(|param| (param.name, param.kind)).collect::<Vec<_>>();
+    assert_eq!(
+        params,
+        [
+            ("nonce", "int128"),
+            ("server_nonce", "int128"),
+            ("pq", "bytes"),
+            ("server_public_key_fingerprints", "Vector<long>"),
+        ]
+    );
+
+    let peers = registry.constructors_of("Peer").iter().map(|c| c.name).collect::<Vec<_>>();
+    assert!(peers.contains(&"peerUser"));
+    assert!(peers.contains(&"peerChat"));
+}
+
+#[test]
+fn methods() {
+    let send_code = schema::registry().by_name("auth.sendCode").unwrap();
+
+    assert!(send_code.method);
+    assert_eq!(send_code.returns(), Some("auth.SentCode"));
+    assert!(schema::registry().constructors_of("auth.SentCode").iter().all(|c| !c.method));
+}
+
+#[test]
+fn builtins() {
+    let registry = schema::registry();
+
+    assert_eq!(registry.lookup(0x997275b5).unwrap().name, "boolTrue");
+    assert_eq!(registry.lookup(0xbc799737).unwrap().name, "boolFalse");
+    assert_eq!(registry.by_name("null").unwrap().kind, "Null");
+
+    let bools = registry.constructors_of("Bool").iter().map(|c| c.name).collect::<Vec<_>>();
+    assert_eq!(bools, ["boolFalse", "boolTrue"]);
+
+    // Not an object, so not in the registry
+    assert!(registry.get(0x1cb5c415).is_none());
+}
+
+#[test]
+fn unknown_constructor() {
+    let registry = schema::registry();
+
+    assert!(registry.get(0xdead_beef).is_none());
+    assert_eq!(
+        registry.lookup(0xdead_beef).unwrap_err().to_string(),
+        "unknown constructor 0xdeadbeef"
+    );
+}
+
+#[test]
+fn deserialize_errors() {
+    // A known constructor of another type
+    let input = mtproto::ResPq::CONSTRUCTOR_ID.to_le_bytes();
+    match *de::from_slice::<schema::Peer>(&input).unwrap_err().kind() {
+        ErrorKind::UnexpectedConstructor("Peer", id) => assert_eq!(id, mtproto::ResPq::CONSTRUCTOR_ID),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    match *de::from_slice::<schema::Peer>(&[0xef, 0xbe, 0xad, 0xde]).unwrap_err().kind() {
+        ErrorKind::UnknownConstructor(0xdead_beef) => {}
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
@@ -0,0 +1,249 @@
+//! The retry policy of the client, against the fake server and a fake clock.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use telegram::error::{self, ErrorKind};
+use telegram::retry::{Clock, RateLimit, RetryPolicy};
+use telegram::schema::{self, help, messages};
+use telegram::Client;
+
+use fake::{rpc_error, FakeServer, RpcError};
+
+/// A clock whose sleeps end at once, moving it forward.
+struct MockClock {
+    start: Instant,
+    elapsed: Cell<Duration>,
+    sleeps: RefCell<Vec<Duration>>,
+}
+
+impl MockClock {
+    fn new() -> Rc<MockClock> {
+        Rc::new(MockClock {
+            start: Instant::now(),
+            elapsed: Cell::new(Duration::from_secs(0)),
+            sleeps: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.borrow().clone()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn sleep(&self, duration: Duration) -> Box<dyn Future<Item = (), Error = error::Error>> {
+        self.sleeps.borrow_mut().push(duration);
+        self.elapsed.set(self.elapsed.get() + duration);
+
+        Box::new(future::ok(()))
+    }
+}
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+/// Answers `help.getNearestDc` with the errors of `errors`, in order, then successfully.
+fn nearest_dc_server(errors: Vec<RpcError>) -> Rc<FakeServer> {
+    let server = FakeServer::new();
+    let errors = RefCell::new(errors.into_iter());
+
+    server.on(move |_: help::GetNearestDc| match errors.borrow_mut().next() {
+        Some(error) => Err(error),
+        None => Ok(schema::NearestDc {
+            country: "NL".into(),
+            this_dc: 2,
+            nearest_dc: 4,
+        }),
+    });
+
+    server
+}
+
+fn client(server: &Rc<FakeServer>, clock: &Rc<MockClock>, policy: RetryPolicy) -> Client {
+    fake::client(server.clone()).retry_policy(policy.clock(clock.clone()))
+}
+
+#[test]
+fn flood_wait() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(vec![
+        rpc_error(420, "FLOOD_WAIT_5"),
+        rpc_error(420, "FLOOD_WAIT_7"),
+    ]);
+
+    // Flood waits don't count as retries
+    let client = client(&server, &clock, RetryPolicy::default().retries(0));
+    let nearest = client.invoke(help::GetNearestDc).wait().unwrap();
+
+    assert_eq!(nearest.nearest_dc, 4);
+    assert_eq!(clock.sleeps(), [secs(5), secs(7)]);
+    assert_eq!(server.calls().len(), 3);
+}
+
+#[test]
+fn long_flood_wait_fails_fast() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(vec![rpc_error(420, "FLOOD_WAIT_3600")]);
+
+    let policy = RetryPolicy::default().max_flood_wait(secs(60));
+    let error = client(&server, &clock, policy)
+        .invoke(help::GetNearestDc)
+        .wait()
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::Rpc(420, ref message) => assert_eq!(message, "FLOOD_WAIT_3600"),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    assert!(clock.sleeps().is_empty());
+    assert_eq!(server.calls().len(), 1);
+}
+
+#[test]
+fn flood_waits_add_up() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(vec![rpc_error(420, "FLOOD_WAIT_30"); 10]);
+
+    // The flood waits of a call stop being waited out once they'd add up to over 100 s
+    let policy = RetryPolicy::default().max_total_flood_wait(secs(100));
+    let error = client(&server, &clock, policy)
+        .invoke(help::GetNearestDc)
+        .wait()
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::Rpc(420, ref message) => assert_eq!(message, "FLOOD_WAIT_30"),
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    assert_eq!(clock.sleeps(), [secs(30); 3]);
+    assert_eq!(server.calls().len(), 4);
+}
+
+#[test]
+fn internal_errors_back_off() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(vec![rpc_error(-500, "INTERNAL"); 4]);
+
+    let policy = RetryPolicy::default().backoff(secs(1), secs(5));
+    client(&server, &clock, policy)
+        .invoke(help::GetNearestDc)
+        .wait()
+        .unwrap();
+
+    assert_eq!(clock.sleeps(), [secs(1), secs(2), secs(4), secs(5)]);
+
+    // Until there are no retries left
+    let clock = MockClock::new();
+    let server = nearest_dc_server(vec![rpc_error(500, "RPC_CALL_FAIL"); 4]);
+
+    let error = client(&server, &clock, RetryPolicy::default().retries(2))
+        .invoke(help::GetNearestDc)
+        .wait()
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::Rpc(500, _) => {}
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    assert_eq!(clock.sleeps(), [secs(1), secs(2)]);
+    assert_eq!(server.calls().len(), 3);
+}
+
+#[test]
+fn transport_failures_back_off() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(Vec::new());
+    server.lose_replies(2);
+
+    client(&server, &clock, RetryPolicy::default())
+        .invoke(help::GetNearestDc)
+        .wait()
+        .unwrap();
+
+    assert_eq!(clock.sleeps(), [secs(1), secs(2)]);
+    assert_eq!(server.calls().len(), 3);
+}
+
+#[test]
+fn other_errors_are_returned() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(vec![rpc_error(400, "BAD_REQUEST")]);
+
+    client(&server, &clock, RetryPolicy::default())
+        .invoke(help::GetNearestDc)
+        .wait()
+        .unwrap_err();
+
+    // And nothing is retried without a policy
+    let server = nearest_dc_server(vec![
+        rpc_error(-500, "INTERNAL"),
+        rpc_error(420, "FLOOD_WAIT_1"),
+    ]);
+    let client = client(&server, &clock, RetryPolicy::none());
+
+    client.invoke(help::GetNearestDc).wait().unwrap_err();
+    client.invoke(help::GetNearestDc).wait().unwrap_err();
+
+    assert!(clock.sleeps().is_empty());
+}
+
+#[test]
+fn rate_limits() {
+    let clock = MockClock::new();
+    let server = nearest_dc_server(Vec::new());
+    server.on(|_: messages::GetHistory| Ok(messages::Messages_::default().into()));
+
+    let policy = RetryPolicy::default().rate_limit(
+        "messages.getHistory",
+        RateLimit::new(2, secs(1)),
+    );
+    let client = client(&server, &clock, policy);
+
+    let history = || messages::GetHistory {
+        peer: schema::InputPeer::InputPeerSelf,
+        offset: 0,
+        max_id: 0,
+        limit: 10,
+    };
+
+    // A burst of 2, then one every half second
+    for _ in 0..4 {
+        client.invoke(history()).wait().unwrap();
+    }
+
+    assert_eq!(clock.sleeps(), [Duration::from_millis(500); 2]);
+
+    // Other methods aren't throttled
+    for _ in 0..4 {
+        client.invoke(help::GetNearestDc).wait().unwrap();
+    }
+
+    assert_eq!(clock.sleeps().len(), 2);
+
+    // The bucket fills up again while the method isn't invoked
+    clock.elapsed.set(clock.elapsed.get() + secs(10));
+    client.invoke(history()).wait().unwrap();
+    client.invoke(history()).wait().unwrap();
+
+    assert_eq!(clock.sleeps().len(), 2);
+}
@@ -0,0 +1,196 @@
+//! Every value deserializes back from its serialization, which is exactly
+//! `serialized_len` bytes long.
+
+extern crate proptest;
+extern crate telegram;
+
+use std::fmt::Debug;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use telegram::de::{Deserialize, DeserializeOwned, Deserializer, IntoOwned};
+use telegram::ser::{Flags, Serialize};
+use telegram::schema::{self, mtproto};
+use telegram::{Bytes, Int128, Int256};
+
+fn round_trip<T>(value: &T) -> Result<(), TestCaseError>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let buffer = value.to_vec().unwrap();
+    prop_assert_eq!(buffer.len(), value.serialized_len());
+
+    let mut reader = Deserializer::new(&buffer);
+    prop_assert_eq!(&T::deserialize_from(&mut reader).unwrap(), value);
+    prop_assert!(reader.remaining().is_empty());
+
+    Ok(())
+}
+
+/// Like `round_trip`, also deserializing the borrowing variant of the type.
+fn round_trip_borrowed<'de, T, B>(value: &T, buffer: &'de mut Vec<u8>) -> Result<(), TestCaseError>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+    B: Deserialize<'de> + IntoOwned<Owned = T>,
+{
+    round_trip(value)?;
+
+    *buffer = value.to_vec().unwrap();
+    let borrowed = telegram::de::from_slice::<B>(buffer).unwrap();
+    prop_assert_eq!(&borrowed.into_owned(), value);
+
+    Ok(())
+}
+
+fn int128() -> BoxedStrategy<Int128> {
+    any::<[u8; 16]>().prop_map(Int128).boxed()
+}
+
+fn int256() -> BoxedStrategy<Int256> {
+    any::<[u8; 32]>().prop_map(Int256).boxed()
+}
+
+fn string() -> BoxedStrategy<String> {
+    // Long enough to use both length encodings
+    vec(any::<char>(), 0..300)
+        .prop_map(|chars| chars.into_iter().collect())
+        .boxed()
+}
+
+fn bytes() -> BoxedStrategy<Bytes> {
+    vec(any::<u8>(), 0..600).prop_map(Bytes).boxed()
+}
+
+fn peer() -> BoxedStrategy<schema::Peer> {
+    prop_oneof![
+        any::<i32>().prop_map(|user_id| schema::PeerUser { user_id }.into()),
+        any::<i32>().prop_map(|chat_id| schema::PeerChat { chat_id }.into()),
+    ].boxed()
+}
+
+fn message() -> BoxedStrategy<schema::Message> {
+    let message = (any::<i32>(), any::<i32>(), any::<i32>(), peer(), any::<i32>(), string())
+        .prop_map(|(flags, id, from_id, to_id, date, message)| {
+            schema::Message_ {
+                flags,
+                id,
+                from_id,
+                to_id,
+                date,
+                message,
+                media: schema::MessageMedia::MessageMediaEmpty,
+            }.into()
+        });
+
+    prop_oneof![
+        any::<i32>().prop_map(|id| schema::MessageEmpty { id }.into()),
+        message,
+    ].boxed()
+}
+
+proptest! {
+    #[test]
+    fn primitives(
+        a in any::<bool>(),
+        b in any::<i32>(),
+        c in any::<u32>(),
+        d in any::<i64>(),
+        e in any::<u64>(),
+        f in any::<f64>().prop_filter("NaN is not equal to itself", |f| !f.is_nan()),
+        g in any::<i8>(),
+        h in any::<u16>(),
+    ) {
+        round_trip(&a)?;
+        round_trip(&b)?;
+        round_trip(&c)?;
+        round_trip(&d)?;
+        round_trip(&e)?;
+        round_trip(&f)?;
+        round_trip(&g)?;
+        round_trip(&h)?;
+    }
+
+    #[test]
+    fn int128_and_int256(a in int128(), b in int256(), c in any::<u128>()) {
+        round_trip(&a)?;
+        round_trip(&b)?;
+
+        prop_assert_eq!(u128::from(Int128::from(c)), c);
+        prop_assert_eq!(Int256::from(b.split()), b);
+    }
+
+    #[test]
+    fn strings_and_bytes(a in string(), b in bytes()) {
+        round_trip(&a)?;
+        round_trip(&b)?;
+
+        // Strings and bytes are padded to a multiple of 4 bytes
+        prop_assert_eq!(a.serialized_len() % 4, 0);
+        prop_assert_eq!(b.serialized_len() % 4, 0);
+    }
+
+    #[test]
+    fn vectors(
+        a in vec(any::<i32>(), 0..50),
+        b in vec(string(), 0..10),
+        c in vec(vec(any::<i64>(), 0..10), 0..10),
+    ) {
+        round_trip(&a)?;
+        round_trip(&b)?;
+        round_trip(&c)?;
+
+        // The length prefix is the number of elements
+        let buffer = a.to_vec().unwrap();
+        prop_assert_eq!(&buffer[4..8], &(a.len() as u32).to_le_bytes()[..]);
+    }
+
+    #[test]
+    fn flags(report_spam in any::<bool>()) {
+        round_trip(&schema::PeerSettings { flags: Flags, report_spam })?;
+    }
+
+    #[test]
+    fn messages(messages in vec(message(), 0..10)) {
+        let value = schema::messages::Messages::from(schema::messages::Messages_ {
+            messages,
+            chats: Vec::new(),
+            users: Vec::new(),
+        });
+
+        round_trip_borrowed::<_, schema::borrowed::messages::Messages>(&value, &mut Vec::new())?;
+    }
+
+    #[test]
+    fn file(mtime in any::<i32>(), bytes in bytes()) {
+        let value = schema::upload::File {
+            type_: schema::storage::FileType::FilePng,
+            mtime,
+            bytes,
+        };
+
+        round_trip_borrowed::<_, schema::borrowed::upload::File>(&value, &mut Vec::new())?;
+    }
+
+    #[test]
+    fn res_pq(
+        nonce in int128(),
+        server_nonce in int128(),
+        pq in bytes(),
+        server_public_key_fingerprints in vec(any::<i64>(), 0..5),
+    ) {
+        round_trip(&mtproto::ResPq {
+            nonce,
+            server_nonce,
+            pq,
+            server_public_key_fingerprints,
+        })?;
+    }
+
+    #[test]
+    fn generic_method(layer in any::<i32>()) {
+        round_trip(&schema::InvokeWithLayer {
+            layer,
+            query: schema::help::GetConfig,
+        })?;
+    }
+}
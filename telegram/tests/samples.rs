@@ -0,0 +1,100 @@
+//! Byte layouts of the samples of https://core.telegram.org/mtproto/samples-auth_key
+
+extern crate telegram;
+
+use telegram::de::{self, Deserialize, Deserializer};
+use telegram::ser::Serialize;
+use telegram::schema::mtproto;
+use telegram::{Int128, Int256, Request};
+
+const NONCE: u128 = 0x3E0549828CCA27E966B301A48FECE2FC;
+const SERVER_NONCE: u128 = 0xA5CF4D33F4A11EA877BA4AA573907330;
+
+#[test]
+fn req_pq() {
+    let request = Request::new(mtproto::ReqPq {
+        nonce: NONCE.into(),
+    });
+
+    let buffer = request.to_vec().unwrap();
+
+    // auth_key_id, message_id (which differs from the sample), message_length
+    assert_eq!(buffer.len(), 40);
+    assert_eq!(&buffer[..8], &[0; 8]);
+    assert_eq!(&buffer[16..20], &[0x14, 0x00, 0x00, 0x00]);
+
+    #[rustfmt::skip]
+    assert_eq!(&buffer[20..], &[
+        0x78, 0x97, 0x46, 0x60, 0x3E, 0x05, 0x49, 0x82, 0x8C, 0xCA, 0x27, 0xE9,
+        0x66, 0xB3, 0x01, 0xA4, 0x8F, 0xEC, 0xE2, 0xFC,
+    ][..]);
+}
+
+#[test]
+fn res_pq() {
+    #[rustfmt::skip]
+    let response = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xC8, 0x83, 0x1E,
+        0xC9, 0x7A, 0xE5, 0x51, 0x40, 0x00, 0x00, 0x00, 0x63, 0x24, 0x16, 0x05,
+        0x3E, 0x05, 0x49, 0x82, 0x8C, 0xCA, 0x27, 0xE9, 0x66, 0xB3, 0x01, 0xA4,
+        0x8F, 0xEC, 0xE2, 0xFC, 0xA5, 0xCF, 0x4D, 0x33, 0xF4, 0xA1, 0x1E, 0xA8,
+        0x77, 0xBA, 0x4A, 0xA5, 0x73, 0x90, 0x73, 0x30, 0x08, 0x17, 0xED, 0x48,
+        0x94, 0x1A, 0x08, 0xF9, 0x81, 0x00, 0x00, 0x00, 0x15, 0xC4, 0xB5, 0x1C,
+        0x01, 0x00, 0x00, 0x00, 0x21, 0x6B, 0xE8, 0x6C, 0x02, 0x2B, 0xB4, 0xC3,
+    ];
+
+    let mut reader = Deserializer::new(&response);
+
+    assert_eq!(u64::deserialize_from(&mut reader).unwrap(), 0);
+    assert_eq!(
+        u64::deserialize_from(&mut reader).unwrap(),
+        0x51E57AC91E83C801
+    );
+    assert_eq!(u32::deserialize_from(&mut reader).unwrap(), 64);
+
+    let body = reader.remaining();
+    let res_pq = mtproto::ResPq::deserialize_from(&mut reader).unwrap();
+
+    assert!(reader.remaining().is_empty());
+    assert_eq!(
+        res_pq,
+        mtproto::ResPq {
+            nonce: NONCE.into(),
+            server_nonce: SERVER_NONCE.into(),
+            pq: vec![0x17, 0xED, 0x48, 0x94, 0x1A, 0x08, 0xF9, 0x81].into(),
+            server_public_key_fingerprints: vec![0xc3b42b026ce86b21u64 as i64],
+        }
+    );
+
+    assert_eq!(res_pq.to_vec().unwrap(), body);
+}
+
+#[test]
+fn p_q_inner_data() {
+    #[rustfmt::skip]
+    let data = [
+        0xEC, 0x5A, 0xC9, 0x83, 0x08, 0x17, 0xED, 0x48, 0x94, 0x1A, 0x08, 0xF9,
+        0x81, 0x00, 0x00, 0x00, 0x04, 0x49, 0x4C, 0x55, 0x3B, 0x00, 0x00, 0x00,
+        0x04, 0x53, 0x91, 0x10, 0x73, 0x00, 0x00, 0x00, 0x3E, 0x05, 0x49, 0x82,
+        0x8C, 0xCA, 0x27, 0xE9, 0x66, 0xB3, 0x01, 0xA4, 0x8F, 0xEC, 0xE2, 0xFC,
+        0xA5, 0xCF, 0x4D, 0x33, 0xF4, 0xA1, 0x1E, 0xA8, 0x77, 0xBA, 0x4A, 0xA5,
+        0x73, 0x90, 0x73, 0x30, 0x31, 0x1C, 0x85, 0xDB, 0x23, 0x4A, 0xA2, 0x64,
+        0x0A, 0xFC, 0x4A, 0x76, 0xA7, 0x35, 0xCF, 0x5B, 0x1F, 0x0F, 0xD6, 0x8B,
+        0xD1, 0x7F, 0xA1, 0x81, 0xE1, 0x22, 0x9A, 0xD8, 0x67, 0xCC, 0x02, 0x4D,
+    ];
+
+    let inner_data = mtproto::PQInnerData::from(mtproto::PQInnerData_ {
+        pq: vec![0x17, 0xED, 0x48, 0x94, 0x1A, 0x08, 0xF9, 0x81].into(),
+        p: vec![0x49, 0x4C, 0x55, 0x3B].into(),
+        q: vec![0x53, 0x91, 0x10, 0x73].into(),
+        nonce: NONCE.into(),
+        server_nonce: SERVER_NONCE.into(),
+        new_nonce: Int256::from((
+            Int128::from(0x311C85DB234AA2640AFC4A76A735CF5Bu128),
+            Int128::from(0x1F0FD68BD17FA181E1229AD867CC024Du128),
+        )),
+    });
+
+    assert_eq!(inner_data.to_vec().unwrap(), &data[..]);
+    assert_eq!(de::from_slice::<mtproto::PQInnerData>(&data).unwrap(), inner_data);
+}
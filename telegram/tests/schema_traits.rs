@@ -0,0 +1,58 @@
+//! The standard and serde traits derived on the generated types, including the methods
+//! generic over the query they wrap.
+
+extern crate telegram;
+#[cfg(feature = "json")]
+extern crate serde_json;
+
+use telegram::schema::{self, help};
+
+/// `invokeWithLayer` wrapping `initConnection`, wrapping `help.getConfig`.
+type Wrapped = schema::InvokeWithLayer<schema::InitConnection<help::GetConfig>>;
+
+fn wrapped() -> Wrapped {
+    schema::InvokeWithLayer {
+        layer: 23,
+        query: schema::InitConnection {
+            api_id: 1,
+            device_model: "test".into(),
+            system_version: "1.0".into(),
+            app_version: "0.2.0".into(),
+            lang_code: "en".into(),
+            query: help::GetConfig,
+        },
+    }
+}
+
+#[test]
+fn clone_and_compare() {
+    let query = wrapped();
+
+    assert_eq!(query.clone(), query);
+    assert_ne!(Wrapped { layer: 22, ..query.clone() }, query);
+
+    let peer: schema::InputPeer = schema::InputPeerContact { user_id: 7 }.into();
+    assert_eq!(peer.clone(), peer);
+}
+
+#[test]
+fn default() {
+    let query = Wrapped::default();
+
+    assert_eq!(query.layer, 0);
+    assert_eq!(query.query.lang_code, "");
+    assert_eq!(query.query.query, help::GetConfig);
+
+    // The first constructor without parameters
+    assert_eq!(schema::InputPeer::default(), schema::InputPeer::InputPeerEmpty);
+}
+
+#[cfg(all(feature = "serde", feature = "json"))]
+#[test]
+fn serde() {
+    let query = wrapped();
+    let json = serde_json::to_string(&query).unwrap();
+
+    assert!(json.contains("\"device_model\":\"test\""), "{}", json);
+    assert_eq!(serde_json::from_str::<Wrapped>(&json).unwrap(), query);
+}
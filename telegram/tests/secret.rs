@@ -0,0 +1,763 @@
+//! Secret chats between two clients, whose fake servers relay what they send to each
+//! other as updates.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Cursor;
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use telegram::download::CHUNK_SIZE;
+use telegram::schema::{self, messages, storage, upload};
+use telegram::secret::{DecryptedMessage, DecryptedMessageAction, DecryptedMessageMedia, FileKey,
+                       SecretChats, SecretEvent, LAYER, REKEY_AFTER};
+use telegram::upload::PART_SIZE;
+use telegram::store::{FileStore, MemoryStore, SessionStore};
+
+use fake::FakeServer;
+
+const PRIME: &str = "\
+    C71CAEB9C6B1C9048E6C522F70F13F73980D40238E3E21C14934D037563D930F48198A0AA7C14058229493D2\
+    2530F4DBFA336F6E0AC925139543AED44CCE7C3720FD51F69458705AC68CD4FE6B6B13ABDC9746512969328\
+    454F18FAF8C595F642477FE96BB2A941D5BCD1D4AC8CC49880708FA9B378E3C4F3A9060BEE67CF9A4A4A695\
+    811051907E162753B56B0F6B410DBA74D8A84B2A14B3144E0EF1284754FD17ED950D5965B4B9DD46582DB11\
+    78D169C6BC465B0D6FF9CA3928FEF5B9AE4E418FC15E83EBEA0F87FA9FF5EED70050DED2849F47BF959D956\
+    850CE929851F0D8115F635B105EE2E4E15D04B2454BF6F4FADF034B10403119CD8E3B92FCC5B";
+
+const CHAT_ID: i32 = 1;
+const ALICE: i32 = 10;
+const BOB: i32 = 20;
+
+type Queue = Rc<RefCell<VecDeque<schema::Update>>>;
+
+/// The updates waiting for each side, and what the servers were asked.
+#[derive(Default)]
+struct Relay {
+    to_alice: Queue,
+    to_bob: Queue,
+
+    /// Replace the fingerprint Bob accepts the chat with, as someone in the middle would.
+    tamper: Cell<bool>,
+    discarded: Cell<u32>,
+
+    /// The parts of the files uploaded, by file and part number.
+    parts: RefCell<BTreeMap<(i64, i32), Vec<u8>>>,
+}
+
+impl Relay {
+    fn file(&self, file_id: i64) -> Vec<u8> {
+        self.parts
+            .borrow()
+            .iter()
+            .filter(|&(&(id, _), _)| id == file_id)
+            .flat_map(|(_, bytes)| bytes.clone())
+            .collect()
+    }
+}
+
+fn prime() -> Vec<u8> {
+    (0..PRIME.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&PRIME[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// A server sending the updates of the other side to `queue`.
+fn server(relay: &Rc<Relay>, admin: bool) -> Rc<FakeServer> {
+    let server = FakeServer::new();
+    let queue = if admin {
+        relay.to_bob.clone()
+    } else {
+        relay.to_alice.clone()
+    };
+
+    server.on(|_: messages::GetDhConfig| {
+        Ok(messages::DhConfig_ {
+            g: 3,
+            p: prime().into(),
+            version: 1,
+            random: vec![7; 256].into(),
+        }.into())
+    });
+
+    let to_bob = queue.clone();
+    server.on(move |query: messages::RequestEncryption| {
+        let requested = schema::EncryptedChatRequested {
+            id: CHAT_ID,
+            access_hash: 2,
+            date: 0,
+            admin_id: ALICE,
+            participant_id: BOB,
+            g_a: query.g_a,
+        };
+        to_bob.borrow_mut().push_back(update(requested.into()));
+
+        Ok(schema::EncryptedChatWaiting {
+            id: CHAT_ID,
+            access_hash: 1,
+            date: 0,
+            admin_id: ALICE,
+            participant_id: BOB,
+        }.into())
+    });
+
+    let (to_alice, accepting) = (queue.clone(), relay.clone());
+    server.on(move |query: messages::AcceptEncryption| {
+        let chat = |g_a_or_b: telegram::Bytes, key_fingerprint| {
+            schema::EncryptedChat_ {
+                id: CHAT_ID,
+                access_hash: 1,
+                date: 0,
+                admin_id: ALICE,
+                participant_id: BOB,
+                g_a_or_b,
+                key_fingerprint,
+            }
+        };
+
+        let fingerprint = if accepting.tamper.get() {
+            query.key_fingerprint ^ 1
+        } else {
+            query.key_fingerprint
+        };
+
+        to_alice
+            .borrow_mut()
+            .push_back(update(chat(query.g_b.clone(), fingerprint).into()));
+
+        Ok(chat(query.g_b, query.key_fingerprint).into())
+    });
+
+    let to_other = queue.clone();
+    server.on(move |query: messages::SendEncrypted| {
+        let message = schema::EncryptedMessage_ {
+            random_id: query.random_id,
+            chat_id: CHAT_ID,
+            date: 0,
+            bytes: query.data,
+            file: schema::EncryptedFile::EncryptedFileEmpty,
+        };
+        to_other.borrow_mut().push_back(new_message(message.into()));
+
+        Ok(messages::SentEncryptedMessage_ { date: 0 }.into())
+    });
+
+    let to_other = queue.clone();
+    server.on(move |query: messages::SendEncryptedService| {
+        let message = schema::EncryptedMessageService {
+            random_id: query.random_id,
+            chat_id: CHAT_ID,
+            date: 0,
+            bytes: query.data,
+        };
+        to_other.borrow_mut().push_back(new_message(message.into()));
+
+        Ok(messages::SentEncryptedMessage_ { date: 0 }.into())
+    });
+
+    let (to_other, sending) = (queue.clone(), relay.clone());
+    server.on(move |query: messages::SendEncryptedFile| {
+        let (id, key_fingerprint) = match query.file {
+            schema::InputEncryptedFile::InputEncryptedFileUploaded(ref file) => {
+                (file.id, file.key_fingerprint)
+            }
+
+            ref file => panic!("unexpected file: {:?}", file),
+        };
+
+        let file = schema::EncryptedFile_ {
+            id,
+            access_hash: 3,
+            size: sending.file(id).len() as i32,
+            dc_id: 2,
+            key_fingerprint,
+        };
+
+        let message = schema::EncryptedMessage_ {
+            random_id: query.random_id,
+            chat_id: CHAT_ID,
+            date: 0,
+            bytes: query.data,
+            file: file.clone().into(),
+        };
+        to_other.borrow_mut().push_back(new_message(message.into()));
+
+        Ok(messages::SentEncryptedFile {
+            date: 0,
+            file: file.into(),
+        }.into())
+    });
+
+    let saving = relay.clone();
+    server.on(move |query: upload::SaveFilePart| {
+        saving
+            .parts
+            .borrow_mut()
+            .insert((query.file_id, query.file_part), query.bytes.to_vec());
+
+        Ok(true)
+    });
+
+    let serving = relay.clone();
+    server.on(move |query: upload::GetFile| {
+        let id = match query.location {
+            schema::InputFileLocation::InputEncryptedFileLocation(ref location) => location.id,
+            ref location => panic!("unexpected location: {:?}", location),
+        };
+
+        let file = serving.file(id);
+        let start = (query.offset as usize).min(file.len());
+        let end = (start + query.limit as usize).min(file.len());
+
+        Ok(upload::File {
+            type_: storage::FileType::FilePartial,
+            mtime: 0,
+            bytes: file[start..end].to_vec().into(),
+        })
+    });
+
+    let discarding = relay.clone();
+    server.on(move |query: messages::DiscardEncryption| {
+        discarding.discarded.set(discarding.discarded.get() + 1);
+        let discarded = schema::EncryptedChatDiscarded { id: query.chat_id };
+        queue.borrow_mut().push_back(update(discarded.into()));
+
+        Ok(true)
+    });
+
+    server
+}
+
+fn update(chat: schema::EncryptedChat) -> schema::Update {
+    schema::UpdateEncryption { chat, date: 0 }.into()
+}
+
+fn new_message(message: schema::EncryptedMessage) -> schema::Update {
+    schema::UpdateNewEncryptedMessage { message, qts: 0 }.into()
+}
+
+/// Hand the updates waiting for `side`, and those they lead to, to it.
+fn deliver(side: &SecretChats, queue: &Queue) -> Vec<SecretEvent> {
+    let mut events = Vec::new();
+
+    loop {
+        let update = match queue.borrow_mut().pop_front() {
+            Some(update) => update,
+            None => return events,
+        };
+
+        events.extend(side.handle_update(&update).wait().unwrap());
+    }
+}
+
+struct Chat {
+    relay: Rc<Relay>,
+    alice: SecretChats,
+    bob: SecretChats,
+}
+
+impl Chat {
+    fn new() -> Chat {
+        Chat::with_stores(MemoryStore::new(), MemoryStore::new())
+    }
+
+    fn with_stores<A, B>(alice_store: A, bob_store: B) -> Chat
+    where
+        A: SessionStore + 'static,
+        B: SessionStore + 'static,
+    {
+        let relay = Rc::new(Relay::default());
+        let alice = fake::client(server(&relay, true));
+        let bob = fake::client(server(&relay, false));
+
+        Chat {
+            alice: SecretChats::new(&alice, alice_store).unwrap(),
+            bob: SecretChats::new(&bob, bob_store).unwrap(),
+            relay,
+        }
+    }
+
+    /// Deliver the updates of both sides until there is none left.
+    fn pump(&self) -> (Vec<SecretEvent>, Vec<SecretEvent>) {
+        let (mut alice, mut bob) = (Vec::new(), Vec::new());
+
+        while !self.relay.to_alice.borrow().is_empty() || !self.relay.to_bob.borrow().is_empty() {
+            bob.extend(deliver(&self.bob, &self.relay.to_bob));
+            alice.extend(deliver(&self.alice, &self.relay.to_alice));
+        }
+
+        (alice, bob)
+    }
+
+    fn start(&self) {
+        let user = schema::InputUserContact { user_id: BOB }.into();
+        assert_eq!(self.alice.request(user).wait().unwrap(), CHAT_ID);
+
+        let requested = deliver(&self.bob, &self.relay.to_bob);
+        assert_eq!(
+            requested,
+            vec![SecretEvent::Requested {
+                chat_id: CHAT_ID,
+                user_id: ALICE,
+            }]
+        );
+
+        self.bob.accept(CHAT_ID).wait().unwrap();
+
+        let (alice, bob) = self.pump();
+        assert_eq!(alice.first(), Some(&SecretEvent::Ready(CHAT_ID)));
+        assert!(bob.iter().all(is_layer));
+    }
+}
+
+fn is_layer(event: &SecretEvent) -> bool {
+    match *event {
+        SecretEvent::Message(ref message) => matches!(
+            message.message,
+            DecryptedMessage::Service {
+                action: DecryptedMessageAction::NotifyLayer { .. },
+                ..
+            }
+        ),
+
+        _ => false,
+    }
+}
+
+fn texts(events: &[SecretEvent]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|event| match *event {
+            SecretEvent::Message(ref message) => match message.message {
+                DecryptedMessage::Message { ref message, .. } => Some(message.clone()),
+                _ => None,
+            },
+
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn handshake() {
+    let chat = Chat::new();
+    assert!(!chat.alice.is_ready(CHAT_ID));
+
+    chat.start();
+
+    assert!(chat.alice.is_ready(CHAT_ID));
+    assert!(chat.bob.is_ready(CHAT_ID));
+    assert!(chat.alice.key_fingerprint(CHAT_ID).is_some());
+    assert_eq!(chat.alice.key_fingerprint(CHAT_ID), chat.bob.key_fingerprint(CHAT_ID));
+
+    assert_eq!(chat.alice.user_id(CHAT_ID), Some(BOB));
+    assert_eq!(chat.bob.user_id(CHAT_ID), Some(ALICE));
+    assert_eq!(chat.alice.layer(CHAT_ID), Some(LAYER));
+    assert_eq!(chat.bob.layer(CHAT_ID), Some(LAYER));
+}
+
+#[test]
+fn messages() {
+    let chat = Chat::new();
+    chat.start();
+
+    chat.alice.send_text(CHAT_ID, "Hi Bob").wait().unwrap();
+    chat.alice.send_text(CHAT_ID, "Are you there?").wait().unwrap();
+    let (_, bob) = chat.pump();
+    assert_eq!(texts(&bob), ["Hi Bob", "Are you there?"]);
+
+    let media = DecryptedMessageMedia::Contact {
+        phone_number: "15550100".into(),
+        first_name: "Carol".into(),
+        last_name: String::new(),
+        user_id: 30,
+    };
+    let message = DecryptedMessage::Message {
+        random_id: 42,
+        ttl: 0,
+        message: "Carol".into(),
+        media: media.clone(),
+    };
+    chat.bob.send(CHAT_ID, message.clone()).wait().unwrap();
+
+    let (alice, _) = chat.pump();
+    match alice[..] {
+        [SecretEvent::Message(ref received)] => {
+            assert_eq!(received.chat_id, CHAT_ID);
+            assert_eq!(received.random_id, 42);
+            assert_eq!(received.message, message);
+            assert_eq!(received.file, None);
+        }
+
+        _ => panic!("unexpected events: {:?}", alice),
+    }
+}
+
+#[test]
+fn duplicates_and_gaps() {
+    let chat = Chat::new();
+    chat.start();
+
+    chat.alice.send_text(CHAT_ID, "once").wait().unwrap();
+    let once = chat.relay.to_bob.borrow().front().cloned().unwrap();
+    chat.pump();
+
+    // Delivered again, e.g. after the difference was fetched
+    assert_eq!(chat.bob.handle_update(&once).wait().unwrap(), []);
+
+    chat.alice.send_text(CHAT_ID, "lost").wait().unwrap();
+    let lost = chat.relay.to_bob.borrow_mut().pop_front().unwrap();
+    chat.alice.send_text(CHAT_ID, "after").wait().unwrap();
+    let after = chat.relay.to_bob.borrow().front().cloned().unwrap();
+
+    // The message past the gap is held, and the missing one asked for
+    let (alice, bob) = chat.pump();
+    assert_eq!(bob, []);
+
+    let resend = alice
+        .iter()
+        .filter_map(|event| match *event {
+            SecretEvent::Message(ref message) => match message.message {
+                DecryptedMessage::Service {
+                    action: DecryptedMessageAction::Resend {
+                        start_seq_no,
+                        end_seq_no,
+                    },
+                    ..
+                } => Some((start_seq_no, end_seq_no)),
+                _ => None,
+            },
+
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    match resend[..] {
+        [(start_seq_no, end_seq_no)] => assert_eq!(start_seq_no, end_seq_no),
+        _ => panic!("unexpected events: {:?}", alice),
+    }
+
+    // Held messages aren't taken twice, nor asked for again
+    assert_eq!(chat.bob.handle_update(&after).wait().unwrap(), []);
+    assert!(chat.relay.to_alice.borrow().is_empty());
+
+    // Once the missing message comes again, the one held follows it
+    let events = chat.bob.handle_update(&lost).wait().unwrap();
+    assert_eq!(texts(&events), ["lost", "after"]);
+
+    chat.alice.send_text(CHAT_ID, "next").wait().unwrap();
+    assert_eq!(texts(&chat.pump().1), ["next"]);
+}
+
+#[test]
+fn gap_across_restart() {
+    let dir = std::env::temp_dir().join(format!("telegram-secret-gap-{}", std::process::id()));
+    let chat = Chat::with_stores(MemoryStore::new(), FileStore::new(&dir).unwrap());
+    chat.start();
+
+    chat.alice.send_text(CHAT_ID, "lost").wait().unwrap();
+    let lost = chat.relay.to_bob.borrow_mut().pop_front().unwrap();
+    chat.alice.send_text(CHAT_ID, "after").wait().unwrap();
+    let after = chat.relay.to_bob.borrow().front().cloned().unwrap();
+
+    // The message past the gap is held, and the missing one asked for
+    chat.pump();
+
+    // Restored mid-gap, the held message and the resend asked for are still known
+    let client = fake::client(server(&chat.relay, false));
+    let restored = SecretChats::new(&client, FileStore::new(&dir).unwrap()).unwrap();
+
+    assert_eq!(restored.handle_update(&after).wait().unwrap(), []);
+    assert!(chat.relay.to_alice.borrow().is_empty());
+
+    let events = restored.handle_update(&lost).wait().unwrap();
+    assert_eq!(texts(&events), ["lost", "after"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn inconsistent_seq_no() {
+    let dir = std::env::temp_dir().join(format!("telegram-secret-seq-{}", std::process::id()));
+    let old = dir.with_extension("old");
+    let chat = Chat::with_stores(FileStore::new(&dir).unwrap(), MemoryStore::new());
+    chat.start();
+
+    std::fs::create_dir_all(&old).unwrap();
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, old.join(path.file_name().unwrap())).unwrap();
+    }
+
+    chat.alice.send_text(CHAT_ID, "forgotten").wait().unwrap();
+    chat.pump();
+
+    // Restored from before it sent a message the other side received
+    let client = fake::client(server(&chat.relay, true));
+    let restored = SecretChats::new(&client, FileStore::new(&old).unwrap()).unwrap();
+
+    chat.bob.send_text(CHAT_ID, "reply").wait().unwrap();
+    let update = chat.relay.to_alice.borrow_mut().pop_front().unwrap();
+
+    assert!(restored.handle_update(&update).wait().is_err());
+    assert!(restored.chat_ids().is_empty());
+    assert_eq!(chat.relay.discarded.get(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&old).unwrap();
+}
+
+#[test]
+fn rekey() {
+    let chat = Chat::new();
+    chat.start();
+    let fingerprint = chat.alice.key_fingerprint(CHAT_ID);
+
+    chat.alice.rekey(CHAT_ID).wait().unwrap();
+    let (alice, bob) = chat.pump();
+
+    // The exchange stays between the clients
+    assert!(alice.is_empty());
+    assert!(bob.is_empty());
+
+    assert_ne!(chat.alice.key_fingerprint(CHAT_ID), fingerprint);
+    assert_eq!(chat.alice.key_fingerprint(CHAT_ID), chat.bob.key_fingerprint(CHAT_ID));
+
+    chat.bob.send_text(CHAT_ID, "new key").wait().unwrap();
+    chat.alice.send_text(CHAT_ID, "indeed").wait().unwrap();
+    let (alice, bob) = chat.pump();
+    assert_eq!(texts(&alice), ["new key"]);
+    assert_eq!(texts(&bob), ["indeed"]);
+}
+
+#[test]
+fn rekey_after_use() {
+    let chat = Chat::new();
+    chat.start();
+    let fingerprint = chat.alice.key_fingerprint(CHAT_ID);
+
+    for i in 0..REKEY_AFTER {
+        chat.alice.send_text(CHAT_ID, &i.to_string()).wait().unwrap();
+        let (_, bob) = chat.pump();
+        assert_eq!(texts(&bob), [i.to_string()]);
+    }
+
+    assert_ne!(chat.alice.key_fingerprint(CHAT_ID), fingerprint);
+    assert_eq!(chat.alice.key_fingerprint(CHAT_ID), chat.bob.key_fingerprint(CHAT_ID));
+}
+
+#[test]
+fn fingerprint_mismatch() {
+    let chat = Chat::new();
+    chat.relay.tamper.set(true);
+
+    let user = schema::InputUserContact { user_id: BOB }.into();
+    chat.alice.request(user).wait().unwrap();
+    deliver(&chat.bob, &chat.relay.to_bob);
+    chat.bob.accept(CHAT_ID).wait().unwrap();
+
+    let accepted = chat.relay.to_alice.borrow_mut().pop_front().unwrap();
+    assert!(chat.alice.handle_update(&accepted).wait().is_err());
+    assert_eq!(chat.relay.discarded.get(), 1);
+    assert!(chat.alice.chat_ids().is_empty());
+
+    let (_, bob) = chat.pump();
+    assert!(bob.contains(&SecretEvent::Discarded(CHAT_ID)));
+    assert!(!chat.bob.is_ready(CHAT_ID));
+}
+
+#[test]
+fn persistence() {
+    let dir = std::env::temp_dir().join(format!("telegram-secret-{}", std::process::id()));
+    let chat = Chat::with_stores(MemoryStore::new(), FileStore::new(&dir).unwrap());
+    chat.start();
+
+    chat.alice.send_text(CHAT_ID, "before").wait().unwrap();
+    chat.pump();
+
+    let client = fake::client(server(&chat.relay, false));
+    let restored = SecretChats::new(&client, FileStore::new(&dir).unwrap()).unwrap();
+    assert_eq!(restored.chat_ids(), [CHAT_ID]);
+    assert_eq!(restored.key_fingerprint(CHAT_ID), chat.bob.key_fingerprint(CHAT_ID));
+
+    chat.alice.send_text(CHAT_ID, "after").wait().unwrap();
+    let events = deliver(&restored, &chat.relay.to_bob);
+    assert_eq!(texts(&events), ["after"]);
+
+    restored.send_text(CHAT_ID, "reply").wait().unwrap();
+    assert_eq!(texts(&deliver(&chat.alice, &chat.relay.to_alice)), ["reply"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn accepted_after_restart() {
+    let dir = std::env::temp_dir().join(format!("telegram-secret-waiting-{}", std::process::id()));
+    let chat = Chat::with_stores(FileStore::new(&dir).unwrap(), MemoryStore::new());
+
+    let user = schema::InputUserContact { user_id: BOB }.into();
+    chat.alice.request(user).wait().unwrap();
+    deliver(&chat.bob, &chat.relay.to_bob);
+    chat.bob.accept(CHAT_ID).wait().unwrap();
+
+    let client = fake::client(server(&chat.relay, true));
+    let restored = SecretChats::new(&client, FileStore::new(&dir).unwrap()).unwrap();
+    assert!(!restored.is_ready(CHAT_ID));
+
+    let events = deliver(&restored, &chat.relay.to_alice);
+    assert_eq!(events.first(), Some(&SecretEvent::Ready(CHAT_ID)));
+    assert_eq!(restored.key_fingerprint(CHAT_ID), chat.bob.key_fingerprint(CHAT_ID));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn test_key() -> FileKey {
+    let key: Vec<u8> = (0..32).collect();
+    let iv: Vec<u8> = (32..64).collect();
+
+    FileKey::new(&key, &iv).unwrap()
+}
+
+fn document(key: &FileKey, size: usize) -> DecryptedMessage {
+    DecryptedMessage::Message {
+        random_id: 7,
+        ttl: 0,
+        message: String::new(),
+        media: DecryptedMessageMedia::Document {
+            thumb: Vec::new().into(),
+            thumb_w: 0,
+            thumb_h: 0,
+            file_name: "notes.txt".into(),
+            mime_type: "text/plain".into(),
+            size: size as i32,
+            key: key.key.to_vec().into(),
+            iv: key.iv.to_vec().into(),
+        },
+    }
+}
+
+#[test]
+fn file_key() {
+    let key = test_key();
+
+    // md5(key + iv) is b2d3f56b c197fd98 ..., and 0x6bf5d3b2 ^ 0x98fd97c1 is 0xf3084473
+    assert_eq!(key.fingerprint(), -217561997);
+    assert!(FileKey::new(&key.key, &key.iv[..16]).is_err());
+    assert_ne!(FileKey::random(), FileKey::random());
+}
+
+#[test]
+fn encrypted_upload() {
+    let relay = Rc::new(Relay::default());
+    let client = fake::client(server(&relay, true));
+    let key = test_key();
+
+    let file = client
+        .upload(Cursor::new(contents(64)), "file")
+        .encrypt(&key)
+        .wait()
+        .unwrap();
+
+    let id = match file {
+        schema::InputEncryptedFile::InputEncryptedFileUploaded(ref file) => {
+            assert_eq!(file.parts, 1);
+            assert_eq!(file.key_fingerprint, key.fingerprint());
+            file.id
+        }
+
+        file => panic!("unexpected file: {:?}", file),
+    };
+
+    // AES-256-IGE, as computed independently
+    let expected = "42e66e1a756cccf5b27acc47523ad074ee39bf54e3db37bbdf415df6b400fca9\
+                    77f708327c9e9341cc3dc8efd31e76463daa65b1f0d0252f790d77f1824a662c";
+    let encrypted: String = relay.file(id).iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(encrypted, expected);
+}
+
+#[test]
+fn padded_upload() {
+    let relay = Rc::new(Relay::default());
+    let client = fake::client(server(&relay, true));
+    let contents = contents(PART_SIZE + 100);
+
+    let file = client
+        .upload(Cursor::new(contents.clone()), "file")
+        .size(contents.len() as u64)
+        .encrypt(&FileKey::random())
+        .wait()
+        .unwrap();
+
+    match file {
+        schema::InputEncryptedFile::InputEncryptedFileUploaded(ref file) => {
+            assert_eq!(file.parts, 2);
+            assert_eq!(relay.file(file.id).len(), PART_SIZE + 112);
+        }
+
+        file => panic!("unexpected file: {:?}", file),
+    }
+}
+
+#[test]
+fn send_file() {
+    let chat = Chat::new();
+    chat.start();
+
+    let alice = fake::client(server(&chat.relay, true));
+    let contents = contents(2 * CHUNK_SIZE + 1000);
+    let key = FileKey::random();
+
+    let file = alice
+        .upload(Cursor::new(contents.clone()), "notes.txt")
+        .encrypt(&key)
+        .wait()
+        .unwrap();
+
+    chat.alice
+        .send_file(CHAT_ID, document(&key, contents.len()), file)
+        .wait()
+        .unwrap();
+
+    let (_, bob) = chat.pump();
+    let message = match bob[..] {
+        [SecretEvent::Message(ref message)] => message.clone(),
+        _ => panic!("unexpected events: {:?}", bob),
+    };
+
+    let file = message.file.clone().unwrap();
+    assert_eq!(file.key_fingerprint, key.fingerprint());
+    assert_eq!(file.size as usize, contents.len() + 8);
+
+    let chunks = chat.bob.download(&message).unwrap().collect().wait().unwrap();
+    assert_eq!(chunks.concat(), contents);
+
+    // The key in the media must be the one the file was encrypted with
+    let mut forged = message.clone();
+    forged.message = document(&FileKey::random(), contents.len());
+    assert!(chat.bob.download(&forged).is_err());
+
+    // Service messages have no file
+    let service = DecryptedMessage::Service {
+        random_id: 8,
+        action: DecryptedMessageAction::Noop,
+    };
+    let file = schema::InputEncryptedFileBigUploaded {
+        id: 1,
+        parts: 1,
+        key_fingerprint: 0,
+    };
+    assert!(chat.alice.send_file(CHAT_ID, service, file.into()).wait().is_err());
+}
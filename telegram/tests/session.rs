@@ -0,0 +1,74 @@
+//! The authorization key a client generates with a data center, and the session its
+//! methods are encrypted in, against a fake server.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::rc::Rc;
+
+use futures::Future;
+use telegram::schema::{self, help};
+use telegram::Client;
+
+use fake::FakeServer;
+
+fn server() -> Rc<FakeServer> {
+    let server = FakeServer::new();
+    server.on(|_: help::GetConfig| {
+        Ok(schema::Config {
+            this_dc: 2,
+            ..Default::default()
+        })
+    });
+
+    server
+}
+
+#[test]
+fn one_key_per_connection() {
+    let server = server();
+    let client = fake::client(server.clone());
+
+    assert_eq!(client.invoke(help::GetConfig).wait().unwrap().this_dc, 2);
+    assert_eq!(client.invoke(help::GetConfig).wait().unwrap().this_dc, 2);
+
+    // The key is generated with the first method, and not counted as a call
+    assert_eq!(server.auth_keys(), 1);
+    assert_eq!(server.calls(), ["help.getConfig", "help.getConfig"]);
+
+    // The replies to the first method, `new_session_created` and the result, are
+    // acknowledged with the second
+    assert_eq!(server.acks().len(), 2);
+}
+
+#[test]
+fn bad_server_salt() {
+    let server = server();
+    let client = fake::client(server.clone());
+    client.invoke(help::GetConfig).wait().unwrap();
+
+    // The method is sent again with the salt the server tells
+    server.change_salts();
+    client.invoke(help::GetConfig).wait().unwrap();
+
+    assert_eq!(server.calls(), ["help.getConfig", "help.getConfig"]);
+}
+
+#[test]
+fn unknown_rsa_key() {
+    let server = server();
+
+    // A client trusting only the key of the production servers
+    let client = Client::with_transport(server.clone());
+    let error = client.invoke(help::GetConfig).wait().unwrap_err();
+
+    assert!(error.to_string().contains("RSA keys"), "{}", error);
+    assert_eq!(server.auth_keys(), 0);
+    assert!(server.calls().is_empty());
+}
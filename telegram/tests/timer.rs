@@ -0,0 +1,120 @@
+//! Waiting on the timer thread shared by the clients, rather than on a thread per wait.
+#![cfg(target_os = "linux")]
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::{future, Future};
+use telegram::retry::{RateLimit, RetryPolicy};
+use telegram::schema::{self, messages};
+
+use fake::FakeServer;
+
+/// Held by the tests counting threads, so they don't see those of each other.
+static COUNTING: Mutex<()> = Mutex::new(());
+
+/// Number of threads of the test process.
+fn threads() -> usize {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let line = status.lines().find(|line| line.starts_with("Threads:")).unwrap();
+
+    line["Threads:".len()..].trim().parse().unwrap()
+}
+
+#[test]
+fn sends() {
+    let _counting = COUNTING.lock().unwrap();
+
+    let server = FakeServer::new();
+    server.on(|_: messages::SendMessage| {
+        Ok(messages::SentMessage_ {
+            id: 10,
+            date: 1,
+            pts: 2,
+            seq: 3,
+        }.into())
+    });
+
+    let client = fake::client(server.clone());
+    let peer: schema::InputPeer = schema::InputPeerContact { user_id: 7 }.into();
+    let before = threads();
+
+    for _ in 0..100 {
+        let updates = {
+            let client = client.clone();
+
+            future::lazy(move || {
+                let message = schema::Message_ {
+                    id: 10,
+                    to_id: schema::PeerUser { user_id: 7 }.into(),
+                    message: "hello".into(),
+                    ..Default::default()
+                };
+
+                client.handle_updates(
+                    &schema::UpdateShort {
+                        update: schema::UpdateNewMessage {
+                            message: message.into(),
+                            pts: 2,
+                        }.into(),
+                        date: 1,
+                    }.into(),
+                );
+
+                Ok(())
+            })
+        };
+
+        client.send_message(peer.clone(), "hello").join(updates).wait().unwrap();
+    }
+
+    // The timeouts of the sends, which resolved long before them, share a single thread
+    assert!(threads() <= before + 1);
+}
+
+#[test]
+fn throttled_calls() {
+    let _counting = COUNTING.lock().unwrap();
+
+    let server = FakeServer::new();
+    server.on(|_: messages::GetHistory| Ok(messages::Messages_::default().into()));
+
+    let policy = RetryPolicy::default().rate_limit(
+        "messages.getHistory",
+        RateLimit::new(1, Duration::from_secs(60)),
+    );
+    let client = fake::client(server.clone()).retry_policy(policy);
+    let before = threads();
+
+    let during = future::lazy(move || {
+        // All but the first call wait for their turn, on the system clock
+        let mut calls: Vec<_> = (0..100)
+            .map(|_| {
+                client.invoke(messages::GetHistory {
+                    peer: schema::InputPeer::InputPeerSelf,
+                    offset: 0,
+                    max_id: 0,
+                    limit: 10,
+                })
+            })
+            .collect();
+
+        for call in &mut calls {
+            let _ = call.poll();
+        }
+
+        Ok::<_, ()>(threads())
+    });
+
+    assert!(during.wait().unwrap() <= before + 1);
+}
@@ -0,0 +1,28 @@
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+
+// The CRC32 of `peerUser user_id:int = Peer` is 0x9db1bc6d
+#[derive(Serialize)]
+#[id = 0x9db1bc6e]
+#[tl(combinator = "peerUser user_id:int = Peer")]
+struct PeerUser {
+    user_id: i32,
+}
+
+#[derive(Deserialize)]
+enum Peer {
+    #[id = 0xbad1bc6d]
+    #[tl(combinator = "peerChat#bad1bc6d chat_id:int = Peer")]
+    Chat { chat_id: i32 },
+}
+
+// Accepted with the opt-out
+#[derive(Serialize)]
+#[id = 0x9db1bc6e]
+#[tl(combinator = "peerUser user_id:int = Peer", unverified_id)]
+struct Unverified {
+    user_id: i32,
+}
+
+fn main() {}
@@ -0,0 +1,26 @@
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+
+#[derive(Serialize)]
+#[id = 0x0badf00d]
+struct Unknown {
+    #[tl(optional)]
+    value: i32,
+}
+
+#[derive(Serialize)]
+#[id = 0x0badf00e]
+struct MissingFlags {
+    #[tl(flag = "flags.0")]
+    value: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[id = 0x0badf00f]
+struct Conflicting {
+    #[tl(flags, skip)]
+    flags: ::telegram::ser::Flags,
+}
+
+fn main() {}
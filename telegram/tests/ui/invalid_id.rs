@@ -0,0 +1,18 @@
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+
+#[derive(Serialize)]
+#[id = "badf00d"]
+struct NotHex(i32);
+
+#[derive(Serialize)]
+#[id = 0x1_0000_0000]
+struct TooLarge(i32);
+
+#[derive(Deserialize)]
+enum Missing {
+    Value { value: i32 },
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+
+#[derive(Serialize)]
+#[id = 0x0badf00d]
+#[tl(unknown)]
+struct Unknown(i32);
+
+#[derive(Deserialize)]
+#[id = 0x0badf00e]
+#[tl(name = 1)]
+struct NotAString(i32);
+
+fn main() {}
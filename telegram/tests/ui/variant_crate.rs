@@ -0,0 +1,26 @@
+extern crate telegram;
+#[macro_use]
+extern crate telegram_derive;
+
+#[derive(Serialize)]
+enum Serialized {
+    #[id = 0x0badf00d]
+    #[tl(crate = "telegram")]
+    Value(i32),
+}
+
+#[derive(Deserialize)]
+enum Deserialized {
+    #[id = 0x0badf00d]
+    #[tl(crate = "telegram")]
+    Value(i32),
+}
+
+#[derive(Serialize)]
+enum Unknown {
+    #[id = 0x0badf00d]
+    #[tl(bare)]
+    Value(i32),
+}
+
+fn main() {}
@@ -0,0 +1,266 @@
+//! Uploading files in parts to the fake server.
+
+extern crate futures;
+extern crate telegram;
+extern crate aes;
+extern crate num_bigint;
+extern crate rand;
+extern crate sha1;
+
+mod fake;
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use futures::{stream, Future};
+use telegram::schema::{self, upload};
+use telegram::retry::RetryPolicy;
+use telegram::upload::{BIG_FILE_SIZE, PART_SIZE};
+
+use fake::{rpc_error, FakeServer};
+
+/// Parts saved by the server, by file and part number.
+type Saved = Rc<RefCell<BTreeMap<(i64, i32), Vec<u8>>>>;
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn server() -> (Rc<FakeServer>, Saved) {
+    let server = FakeServer::new();
+    let saved = Saved::default();
+
+    let parts = saved.clone();
+    server.on(move |query: upload::SaveFilePart| {
+        parts
+            .borrow_mut()
+            .insert((query.file_id, query.file_part), query.bytes.to_vec());
+
+        Ok(true)
+    });
+
+    let parts = saved.clone();
+    server.on(move |query: upload::SaveBigFilePart| {
+        assert_eq!(query.file_total_parts, 21);
+
+        parts
+            .borrow_mut()
+            .insert((query.file_id, query.file_part), query.bytes.to_vec());
+
+        Ok(true)
+    });
+
+    (server, saved)
+}
+
+/// The file saved under `file_id`, checking that its parts are contiguous.
+fn saved_file(saved: &Saved, file_id: i64) -> Vec<u8> {
+    let saved = saved.borrow();
+    let parts: Vec<_> = saved
+        .iter()
+        .filter(|&(&(id, _), _)| id == file_id)
+        .collect();
+
+    for (index, (&(_, file_part), bytes)) in parts.iter().enumerate() {
+        assert_eq!(file_part, index as i32);
+
+        if index + 1 < parts.len() {
+            assert_eq!(bytes.len(), PART_SIZE);
+        }
+    }
+
+    parts.into_iter().flat_map(|(_, bytes)| bytes.clone()).collect()
+}
+
+#[test]
+fn small_file() {
+    let (server, saved) = server();
+    let client = fake::client(server.clone());
+    let contents = contents(1_200_000);
+
+    let progress = Rc::new(RefCell::new(Vec::new()));
+    let reported = progress.clone();
+
+    let input_file = client
+        .upload(Cursor::new(contents.clone()), "cat.jpg")
+        .concurrency(2)
+        .progress(move |uploaded, size| reported.borrow_mut().push((uploaded, size)))
+        .wait()
+        .unwrap();
+
+    let input_file = match input_file {
+        schema::InputFile::InputFile(input_file) => input_file,
+        input_file => panic!("unexpected {:?}", input_file),
+    };
+
+    assert_eq!(input_file.parts, 3);
+    assert_eq!(input_file.name, "cat.jpg");
+    assert_eq!(input_file.md5_checksum, "6bab7d785128fb851e4d0e04188347fc");
+    assert_eq!(saved_file(&saved, input_file.id), contents);
+
+    assert_eq!(
+        *progress.borrow(),
+        [
+            (PART_SIZE as u64, 1_200_000),
+            (2 * PART_SIZE as u64, 1_200_000),
+            (1_200_000, 1_200_000),
+        ]
+    );
+}
+
+#[test]
+fn chunked_stream() {
+    let (server, saved) = server();
+    let client = fake::client(server.clone());
+    let contents = contents(PART_SIZE + 10);
+
+    // Chunks which don't line up with the parts
+    let chunks: Vec<_> = contents.chunks(1000).map(|chunk| chunk.to_vec()).collect();
+    let input_file = client
+        .upload_stream(stream::iter_ok(chunks), "chunks.bin")
+        .wait()
+        .unwrap();
+
+    match input_file {
+        schema::InputFile::InputFile(input_file) => {
+            assert_eq!(input_file.parts, 2);
+            assert_eq!(saved_file(&saved, input_file.id), contents);
+        }
+
+        input_file => panic!("unexpected {:?}", input_file),
+    }
+}
+
+#[test]
+fn big_file() {
+    let (server, saved) = server();
+    let client = fake::client(server.clone());
+    let size = BIG_FILE_SIZE + 1;
+    let contents = contents(size as usize);
+
+    let input_file = client
+        .upload(Cursor::new(contents.clone()), "movie.mp4")
+        .size(size)
+        .concurrency(8)
+        .wait()
+        .unwrap();
+
+    match input_file {
+        schema::InputFile::InputFileBig(input_file) => {
+            assert_eq!(input_file.parts, 21);
+            assert_eq!(input_file.name, "movie.mp4");
+            assert_eq!(saved_file(&saved, input_file.id), contents);
+        }
+
+        input_file => panic!("unexpected {:?}", input_file),
+    }
+
+    assert!(server.calls().iter().all(|call| call == "upload.saveBigFilePart"));
+}
+
+#[test]
+fn big_file_needs_size() {
+    let (server, _) = server();
+    let client = fake::client(server.clone());
+
+    let error = client
+        .upload(Cursor::new(contents(BIG_FILE_SIZE as usize + 1)), "movie.mp4")
+        .wait()
+        .unwrap_err();
+
+    assert!(error.to_string().contains("Upload::size"));
+    assert!(server.calls().is_empty());
+}
+
+#[test]
+fn wrong_size() {
+    let (server, _) = server();
+    let client = fake::client(server.clone());
+
+    let error = client
+        .upload(Cursor::new(contents(5)), "short.txt")
+        .size(10)
+        .wait()
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), "read 5 bytes of a file of 10 bytes");
+}
+
+#[test]
+fn empty_file() {
+    let (server, _) = server();
+    let client = fake::client(server.clone());
+
+    let error = client
+        .upload(Cursor::new(Vec::new()), "empty.txt")
+        .wait()
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), "the file empty.txt is empty");
+
+    let error = client
+        .upload(Cursor::new(Vec::new()), "empty.txt")
+        .size(0)
+        .wait()
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), "the file empty.txt is empty");
+    assert!(server.calls().is_empty());
+}
+
+#[test]
+fn retries() {
+    let server = FakeServer::new();
+    let attempts = Rc::new(Cell::new(0));
+
+    let attempted = attempts.clone();
+    server.on(move |query: upload::SaveFilePart| {
+        attempted.set(attempted.get() + 1);
+
+        // The first two attempts fail with `false`
+        assert_eq!(query.file_part, 0);
+        Ok(attempted.get() > 2)
+    });
+
+    let client = fake::client(server.clone());
+    client
+        .upload(Cursor::new(contents(10)), "retried.txt")
+        .wait()
+        .unwrap();
+
+    assert_eq!(attempts.get(), 3);
+
+    attempts.set(0);
+    let error = client
+        .upload(Cursor::new(contents(10)), "retried.txt")
+        .retries(1)
+        .wait()
+        .unwrap_err();
+
+    assert_eq!(error.to_string(), "the server failed to save a part of the file");
+    assert_eq!(attempts.get(), 2);
+}
+
+#[test]
+fn errors_not_retried() {
+    let server = FakeServer::new();
+    let attempts = Rc::new(Cell::new(0));
+
+    let attempted = attempts.clone();
+    server.on(move |_: upload::SaveFilePart| {
+        attempted.set(attempted.get() + 1);
+        Err::<bool, _>(rpc_error(500, "INTERNAL"))
+    });
+
+    // Errors are only retried by the client, not once more per part
+    let client = fake::client(server.clone()).retry_policy(RetryPolicy::none());
+    let error = client
+        .upload(Cursor::new(contents(10)), "failed.txt")
+        .wait()
+        .unwrap_err();
+
+    assert!(error.to_string().contains("INTERNAL"), "{}", error);
+    assert_eq!(attempts.get(), 1);
+}
@@ -0,0 +1,103 @@
+//! Objects of the static types decode to `TlValue` and encode back to the same bytes.
+
+extern crate telegram;
+
+use telegram::error::ErrorKind;
+use telegram::ser::{Flags, Serialize};
+use telegram::schema::{self, mtproto};
+use telegram::value::Value;
+use telegram::TlValue;
+
+fn round_trip<T: Serialize>(object: &T) -> TlValue {
+    let buffer = object.to_vec().unwrap();
+    let value = TlValue::decode(&buffer).unwrap();
+
+    assert_eq!(value.to_vec().unwrap(), buffer);
+    assert_eq!(value.serialized_len(), buffer.len());
+
+    value
+}
+
+#[test]
+fn message() {
+    let value = round_trip(&schema::Message::from(schema::Message_ {
+        flags: 0,
+        id: 1,
+        from_id: 2,
+        to_id: schema::PeerUser { user_id: 3 }.into(),
+        date: 4,
+        message: "hello".into(),
+        media: schema::MessageMedia::MessageMediaEmpty,
+    }));
+
+    assert_eq!(value.name, "message");
+    assert_eq!(value.fields["message"], Value::String("hello".into()));
+
+    match value.fields["to_id"] {
+        Value::Object(ref peer) => assert_eq!(peer.name, "peerUser"),
+        ref value => panic!("unexpected {:?}", value),
+    }
+}
+
+#[test]
+fn flags() {
+    let set = round_trip(&schema::PeerSettings {
+        flags: Flags,
+        report_spam: true,
+    });
+    assert_eq!(set.fields.get("report_spam"), Some(&Value::Bool(true)));
+    assert_eq!(set.fields.get("flags"), None);
+
+    let unset = round_trip(&schema::PeerSettings {
+        flags: Flags,
+        report_spam: false,
+    });
+    assert!(unset.fields.is_empty());
+}
+
+#[test]
+fn mtproto_and_generic_methods() {
+    round_trip(&mtproto::ResPq {
+        nonce: 1u128.into(),
+        server_nonce: 2u128.into(),
+        pq: vec![1, 2, 3].into(),
+        server_public_key_fingerprints: vec![4, 5],
+    });
+
+    let value = round_trip(&schema::InvokeWithLayer {
+        layer: 23,
+        query: schema::help::GetConfig,
+    });
+
+    match value.fields["query"] {
+        Value::Object(ref query) => assert_eq!(query.name, "help.getConfig"),
+        ref value => panic!("unexpected {:?}", value),
+    }
+}
+
+#[test]
+fn unknown_constructor() {
+    match *TlValue::decode(&[0xff, 0xff, 0xff, 0xff]).unwrap_err().kind() {
+        ErrorKind::UnknownConstructor(0xffff_ffff) => {}
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json() {
+    let value = round_trip(&mtproto::ResPq {
+        nonce: 1u128.into(),
+        server_nonce: 2u128.into(),
+        pq: vec![1, 2, 3].into(),
+        server_public_key_fingerprints: vec![-4],
+    });
+
+    let json = value.to_json();
+    assert_eq!(json["_"], "resPQ");
+    assert_eq!(json["pq"], "AQID");
+    assert_eq!(json["nonce"], "00000000000000000000000000000001");
+    assert_eq!(json["server_public_key_fingerprints"][0], "-4");
+
+    assert_eq!(TlValue::from_json(&json).unwrap(), value);
+}
@@ -1,3 +1,6 @@
+// error-chain 0.10 implements the deprecated `description` and `cause` methods
+#![allow(deprecated)]
+
 error_chain! {
     foreign_links {
         Io(::std::io::Error);
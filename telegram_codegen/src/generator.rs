@@ -1,107 +1,185 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
 use error;
-use parser::{Schema, Parameter};
+use names::{camel_case, snake_case, Namespace};
+use parser::{self, Schema, Parameter};
 
 struct Constructor {
     id: i32,
     name: String,
     params: Vec<Parameter>,
+
+    /// Full TL type of a constructor, result type of a method.
+    kind: String,
+
+    /// Rust names of the parameters, in order.
+    fields: Vec<String>,
+
+    /// Rust name of the struct holding this constructor; the type itself for types with
+    /// a single constructor.
+    struct_name: String,
+
+    /// Rust name of the enum variant for this constructor.
+    variant_name: String,
 }
 
-#[derive(Default)]
+impl Constructor {
+    /// Whether any parameter is of the generic `!X` type, which implies
+    /// a `{X:Type}` type parameter.
+    fn is_generic(&self) -> bool {
+        self.params.iter().any(|param| param.kind == "!X")
+    }
+
+    /// Whether any parameter is conditional, e.g. `flags.0?int`, which only a builder can
+    /// leave out while keeping the flags consistent.
+    fn has_conditional(&self) -> bool {
+        self.params.iter().any(|param| param.condition().is_some())
+    }
+
+    /// The TL combinator without its identifier, e.g.
+    /// `invokeWithLayer {X:Type} layer:int query:!X = X`, whose CRC32 the derives check
+    /// the identifier against.
+    fn combinator(&self) -> String {
+        let mut combinator = self.name.clone();
+
+        if self.is_generic() {
+            combinator.push_str(" {X:Type}");
+        }
+
+        for param in &self.params {
+            combinator.push_str(&format!(" {}:{}", param.name, param.kind));
+        }
+
+        combinator + " = " + &self.kind
+    }
+}
+
+/// Constructors whose identifier isn't the CRC32 of their combinator as found in the
+/// schema: `msg_container` is declared upstream with `vector<message>`, which the JSON
+/// schema spells `vector<%Message>`.
+const UNVERIFIED_IDS: &[&str] = &["msg_container"];
+
+/// Types whose values are added to the peer cache of the deserializer as they are read,
+/// wherever they are in a reply.
+const OBSERVED_TYPES: &[&str] = &["User", "Chat", "Contact"];
+
 struct Type {
+    /// Name of the TL type without its module, e.g. `SentCode` for `auth.SentCode`
+    /// or `sendCode` for the method `auth.sendCode`.
+    name: String,
+
     constructors: Vec<Constructor>,
+
+    /// Rust name of the type.
+    rust_name: String,
+
+    /// Rust name of the builder of a method with conditional parameters.
+    builder_name: String,
+
+    method: bool,
+
+    /// Result type of a method; `None` for types and methods with an unknown result.
+    reply: Option<String>,
 }
 
 #[derive(Default)]
 struct Module {
-    types: HashMap<String, Type>,
+    types: Vec<Type>,
 }
 
-fn translate_typename(
-    typename: &str,
-    current_module: &Option<String>,
-    predicates: &HashMap<String, String>,
-) -> String {
+/// The modules of the schema, the root one (`None`) first, so that they are always
+/// generated in the same order.
+type Modules = BTreeMap<Option<String>, Module>;
+
+/// Rust names assigned to the TL types, to translate references to them.
+#[derive(Default)]
+struct Names {
+    /// Full TL type name (e.g. `auth.SentCode`) -> Rust name
+    types: HashMap<String, String>,
+
+    /// Full TL predicate name -> full TL type name
+    predicates: HashMap<String, String>,
+}
+
+/// Path to the Rust type named `rust_name` in `module`, relative to `current_module`.
+fn type_path(module: Option<&str>, rust_name: &str, current_module: &Option<String>) -> String {
+    match (module, current_module.as_ref()) {
+        (Some(module), Some(current_module)) if module == current_module => rust_name.to_string(),
+        (Some(module), Some(_)) => format!("super::{}::{}", module, rust_name),
+        (Some(module), None) => format!("self::{}::{}", module, rust_name),
+        (None, Some(_)) => format!("super::{}", rust_name),
+        (None, None) => rust_name.to_string(),
+    }
+}
+
+fn translate_typename(typename: &str, current_module: &Option<String>, names: &Names) -> String {
     if typename == "!X" {
-        "Box<::std::any::Any>".into()
+        "X".into()
+    } else if typename == "#" {
+        // The value of a `#` parameter is computed from the conditional parameters
+        "::ser::Flags".into()
+    } else if let Some(index) = typename.find('?') {
+        // Conditional parameter, e.g. `flags.0?string`
+        match &typename[index + 1..] {
+            "true" => "bool".into(),
+            typename => format!(
+                "Option<{}>",
+                translate_typename(typename, current_module, names)
+            ),
+        }
+    } else if typename == "X" {
+        // Result of a method generic over `{X:Type}`
+        "X::Reply".into()
     } else if typename.contains('%') {
         // FIXME: Implement bare types properly!
-        translate_typename(&typename.replace('%', ""), current_module, predicates)
+        translate_typename(&typename.replace('%', ""), current_module, names)
     } else if typename.contains("Vector<") || typename.contains("vector<") {
-        let s = typename.split(|c| c == '<' || c == '>').collect::<Vec<_>>();
-        let typename = translate_typename(s[1], current_module, predicates);
+        let s = typename.split(['<', '>']).collect::<Vec<_>>();
+        let typename = translate_typename(s[1], current_module, names);
 
         format!("Vec<{}>", typename)
-    } else if typename.contains('.') {
-        let s = typename.splitn(2, '.').collect::<Vec<_>>();
-
-        if let Some(ref current_module) = *current_module {
-            if current_module == s[0] {
-                return s[1].to_string();
-            }
-        }
-
-        if current_module.is_some() {
-            format!("super::{}::{}", s[0], s[1])
-        } else {
-            format!("self::{}::{}", s[0], s[1])
-        }
-    } else if predicates.contains_key(typename) {
-        predicates[typename].clone()
+    } else if let Some(kind) = names.predicates.get(typename) {
+        // A bare constructor refers to its type
+        translate_typename(kind, current_module, names)
     } else {
         match typename {
             // Primitive conversion
             "string" => "String".to_string(),
             "Bool" => "bool".to_string(),
             "int" => "i32".to_string(),
-            "int128" => "i128".to_string(),
-            "int256" => "(i128, i128)".to_string(),
-            "Vec<int>" => "Vec<i32>".to_string(),
-            "Vec<long>" => "Vec<i64>".to_string(),
+            "int128" => "::Int128".to_string(),
+            "int256" => "::Int256".to_string(),
             "long" => "i64".to_string(),
             "double" => "f64".to_string(),
-            "bytes" => "Vec<u8>".to_string(),
+            "bytes" => "::Bytes".to_string(),
 
             _ => {
-                if current_module.is_some() {
-                    format!("super::{}", typename)
+                let s = typename.splitn(2, '.').collect::<Vec<_>>();
+                let (module, name) = if s.len() == 1 {
+                    (None, s[0])
                 } else {
-                    typename.into()
-                }
-            }
-        }
-    }
-}
+                    (Some(s[0]), s[1])
+                };
 
-fn translate_id(id: &str, current_module: &Option<String>) -> String {
-    if id.contains('.') {
-        let s = id.splitn(2, '.').collect::<Vec<_>>();
+                let rust_name = names
+                    .types
+                    .get(typename)
+                    .cloned()
+                    .unwrap_or_else(|| camel_case(name));
 
-        if let Some(ref current_module) = *current_module {
-            if current_module == s[0] {
-                return s[1].to_string();
+                type_path(module, &rust_name, current_module)
             }
         }
-
-        format!("{}::{}", s[0], s[1])
-    } else {
-        match id {
-            // Keyword
-            "type" => "type_".to_string(),
-            _ => id.to_string(),
-        }
     }
 }
 
 fn to_constructor(
     id: &str,
     predicate: &str,
-    params: &Vec<Parameter>,
+    params: &[Parameter],
     kind: &str,
 ) -> error::Result<Option<(Option<String>, String, Constructor)>> {
     // Recognized primitive types are ignored when defined
@@ -115,13 +193,6 @@ fn to_constructor(
         return Ok(None);
     }
 
-    // Check for exceptions
-    if kind == "PeerSettings" {
-        // 1 - PeerSettings doesn't seem to exist (along with the associated method) but
-        //     its still in the schema with a seemingly illegal definition
-        return Ok(None);
-    }
-
     // Split kind into <module>.<name>
     let s = kind.splitn(2, '.').collect::<Vec<_>>();
     let (module, name) = if s.len() == 1 {
@@ -131,19 +202,785 @@ fn to_constructor(
     };
 
     // Translate
+    // Rust names are assigned once all constructors are known
     let c = Constructor {
         id: id.parse::<i32>()?,
         name: predicate.to_string(),
-        params: params.clone(),
+        params: params.to_vec(),
+        kind: kind.to_string(),
+        fields: Vec::new(),
+        struct_name: String::new(),
+        variant_name: String::new(),
     };
 
     Ok(Some((module, name.to_string(), c)))
 }
 
+/// Write the derives shared by all generated structs and enums, the methods generic over
+/// `{X:Type}` included: their derives only apply when the wrapped query has the trait.
+fn write_derives(f: &mut File, default: bool) -> error::Result<()> {
+    // Emitted first so the `Serialize` and `Deserialize` derives below don't get to see it
+    writeln!(
+        f,
+        "#[cfg_attr(feature = \"serde\", derive(::serde::Serialize, ::serde::Deserialize))]"
+    )?;
+
+    if default {
+        writeln!(f, "#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]")?;
+    } else {
+        writeln!(f, "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]")?;
+    }
+
+    Ok(())
+}
+
+/// Write the identifier of a constructor, along with the combinator it is checked against.
+fn write_id(f: &mut File, indent: &str, constructor: &Constructor) -> error::Result<()> {
+    writeln!(f, "{}#[id = \"0x{:x}\"]", indent, constructor.id)?;
+    writeln!(f, "{}#[tl(combinator = {:?})]", indent, constructor.combinator())?;
+
+    // The schema spells `msg_container` differently from the combinator its id is the CRC of
+    if UNVERIFIED_IDS.contains(&&*constructor.name) {
+        writeln!(f, "{}#[tl(unverified_id)]", indent)?;
+    }
+
+    Ok(())
+}
+
+/// Write `#[tl(observed)]` if `type_` is one of `OBSERVED_TYPES`.
+fn write_observed(f: &mut File, type_: &Type, module_name: &Option<String>) -> error::Result<()> {
+    if module_name.is_none() && OBSERVED_TYPES.contains(&&*type_.name) {
+        writeln!(f, "#[tl(observed)]")?;
+    }
+
+    Ok(())
+}
+
+/// Write the attributes keeping the TL name of a renamed item.
+fn write_tl_name(f: &mut File, indent: &str, tl_name: &str, rust_name: &str) -> error::Result<()> {
+    writeln!(f, "{}#[tl(name = {:?})]", indent, tl_name)?;
+
+    if tl_name != rust_name {
+        writeln!(
+            f,
+            "{}#[cfg_attr(feature = \"serde\", serde(rename = {:?}))]",
+            indent,
+            tl_name
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write a constructor as a standalone struct, along with its
+/// `TlObject`/`TlConstructor` implementations.
+fn write_struct(
+    f: &mut File,
+    type_: &Type,
+    constructor: &Constructor,
+    module_name: &Option<String>,
+    names: &Names,
+) -> error::Result<()> {
+    let name = &constructor.struct_name;
+
+    // Methods generic over `{X:Type}` take the wrapped query as a type parameter
+    let (generics, generics_decl) = if constructor.is_generic() {
+        ("<X>", "<X: ::RemoteCall>")
+    } else {
+        ("", "")
+    };
+
+    if let Some(ref reply) = type_.reply {
+        writeln!(
+            f,
+            "impl{} ::RemoteCall for {}{} {{",
+            generics_decl,
+            name,
+            generics
+        )?;
+        writeln!(
+            f,
+            "  type Reply = {};",
+            translate_typename(reply, module_name, names)
+        )?;
+        writeln!(f, "}}")?;
+    }
+
+    writeln!(f, "impl{} ::TlObject for {}{} {{", generics_decl, name, generics)?;
+    writeln!(f, "  fn constructor_id(&self) -> u32 {{ 0x{:x} }}", constructor.id)?;
+    writeln!(f, "  fn tl_name(&self) -> &'static str {{ {:?} }}", constructor.name)?;
+    writeln!(f, "}}")?;
+
+    writeln!(
+        f,
+        "impl{} ::TlConstructor for {}{} {{",
+        generics_decl,
+        name,
+        generics
+    )?;
+    writeln!(f, "  const CONSTRUCTOR_ID: u32 = 0x{:x};", constructor.id)?;
+    writeln!(f, "  const TL_NAME: &'static str = {:?};", constructor.name)?;
+    writeln!(f, "}}")?;
+
+    write_derives(f, true)?;
+    write_id(f, "", constructor)?;
+    writeln!(f, "#[tl(name = {:?})]", constructor.name)?;
+
+    // The constructors of an enum are observed through it
+    if type_.constructors.len() == 1 {
+        write_observed(f, type_, module_name)?;
+    }
+
+    if constructor.params.is_empty() {
+        // A single constructor with no parameters is a unit
+        writeln!(f, "pub struct {};", name)?;
+        return Ok(());
+    }
+
+    writeln!(f, "pub struct {}{} {{", name, generics_decl)?;
+
+    for (param, field) in constructor.params.iter().zip(&constructor.fields) {
+        if param.kind == "#" {
+            writeln!(f, "    #[tl(flags)]")?;
+        } else if let Some(condition) = param.condition() {
+            writeln!(f, "    #[tl(flag = {:?})]", condition)?;
+        } else if param.is_bare() {
+            writeln!(f, "    #[tl(bare)]")?;
+        }
+
+        if param.name != *field {
+            write_tl_name(f, "    ", &param.name, field)?;
+        }
+
+        writeln!(
+            f,
+            "    pub {}: {},",
+            field,
+            translate_typename(&param.kind, module_name, names)
+        )?;
+    }
+
+    writeln!(f, "}}")?;
+
+    // Without conditional parameters, the builder would only take all of them
+    if type_.method && constructor.has_conditional() {
+        write_builder(f, type_, constructor, module_name, names)?;
+    }
+
+    Ok(())
+}
+
+/// Write a builder for a method, taking the required parameters up front and the
+/// conditional ones through setters. `#` parameters are left out, their value is
+/// computed from the conditional parameters on serialization.
+fn write_builder(
+    f: &mut File,
+    type_: &Type,
+    constructor: &Constructor,
+    module_name: &Option<String>,
+    names: &Names,
+) -> error::Result<()> {
+    let name = &constructor.struct_name;
+    let builder_name = &type_.builder_name;
+
+    let (generics, generics_decl) = if constructor.is_generic() {
+        ("<X>", "<X: ::RemoteCall>")
+    } else {
+        ("", "")
+    };
+
+    let arguments = constructor
+        .params
+        .iter()
+        .zip(&constructor.fields)
+        .filter(|&(param, _)| param.kind != "#" && param.condition().is_none())
+        .map(|(param, field)| {
+            format!(
+                "{}: {}",
+                field,
+                translate_typename(&param.kind, module_name, names)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    writeln!(f, "impl{} {}{} {{", generics_decl, name, generics)?;
+    writeln!(
+        f,
+        "  pub fn builder({}) -> {}{} {{",
+        arguments.join(", "),
+        builder_name,
+        generics
+    )?;
+    writeln!(f, "    {} {{", builder_name)?;
+    writeln!(f, "      inner: {} {{", name)?;
+
+    for (param, field) in constructor.params.iter().zip(&constructor.fields) {
+        if param.kind == "#" || param.condition().is_some() {
+            writeln!(f, "        {}: Default::default(),", field)?;
+        } else {
+            writeln!(f, "        {},", field)?;
+        }
+    }
+
+    writeln!(f, "      }},")?;
+    writeln!(f, "    }}")?;
+    writeln!(f, "  }}")?;
+    writeln!(f, "}}")?;
+
+    writeln!(f, "#[derive(Debug, Clone, PartialEq)]")?;
+    writeln!(f, "pub struct {}{} {{", builder_name, generics_decl)?;
+    writeln!(f, "  inner: {}{},", name, generics)?;
+    writeln!(f, "}}")?;
+
+    writeln!(f, "impl{} {}{} {{", generics_decl, builder_name, generics)?;
+
+    for (param, field) in constructor.params.iter().zip(&constructor.fields) {
+        let (kind, value) = match param.kind.split('?').nth(1) {
+            Some("true") => ("bool".to_string(), "value"),
+            Some(kind) => (translate_typename(kind, module_name, names), "Some(value)"),
+            None => continue,
+        };
+
+        writeln!(f, "  pub fn {}(mut self, value: {}) -> Self {{", field, kind)?;
+        writeln!(f, "    self.inner.{} = {};", field, value)?;
+        writeln!(f, "    self")?;
+        writeln!(f, "  }}")?;
+    }
+
+    writeln!(f, "  pub fn build(self) -> {}{} {{", name, generics)?;
+    writeln!(f, "    self.inner")?;
+    writeln!(f, "  }}")?;
+    writeln!(f, "}}")?;
+
+    Ok(())
+}
+
+/// Write a type with several constructors as an enum. Constructors with parameters are
+/// pulled into standalone structs wrapped by the variants.
+fn write_enum(
+    f: &mut File,
+    type_: &Type,
+    module_name: &Option<String>,
+    names: &Names,
+) -> error::Result<()> {
+    let name = &type_.rust_name;
+
+    for constructor in &type_.constructors {
+        if constructor.params.is_empty() {
+            continue;
+        }
+
+        let struct_name = &constructor.struct_name;
+        let variant_name = &constructor.variant_name;
+
+        write_struct(f, type_, constructor, module_name, names)?;
+
+        writeln!(f, "impl From<{}> for {} {{", struct_name, name)?;
+        writeln!(f, "  fn from(value: {}) -> Self {{", struct_name)?;
+        writeln!(f, "    {}::{}(value)", name, variant_name)?;
+        writeln!(f, "  }}")?;
+        writeln!(f, "}}")?;
+
+        writeln!(
+            f,
+            "impl ::std::convert::TryFrom<{}> for {} {{",
+            name,
+            struct_name
+        )?;
+        writeln!(f, "  type Error = {};", name)?;
+        writeln!(
+            f,
+            "  fn try_from(value: {}) -> Result<Self, Self::Error> {{",
+            name
+        )?;
+        writeln!(f, "    match value {{")?;
+        writeln!(f, "      {}::{}(value) => Ok(value),", name, variant_name)?;
+        writeln!(f, "      value => Err(value),")?;
+        writeln!(f, "    }}")?;
+        writeln!(f, "  }}")?;
+        writeln!(f, "}}")?;
+    }
+
+    // The variant picked by `Default`: the first constructor without parameters,
+    // falling back to the first constructor
+    let default_index = type_
+        .constructors
+        .iter()
+        .position(|constructor| constructor.params.is_empty());
+
+    write_derives(f, default_index.is_some())?;
+    writeln!(f, "#[tl(name = {:?})]", type_.name)?;
+    write_observed(f, type_, module_name)?;
+    writeln!(f, "pub enum {} {{", name)?;
+
+    for (index, constructor) in type_.constructors.iter().enumerate() {
+        write_tl_name(f, "  ", &constructor.name, &constructor.variant_name)?;
+
+        if constructor.params.is_empty() {
+            write_id(f, "  ", constructor)?;
+
+            if default_index == Some(index) {
+                writeln!(f, "  #[default]")?;
+            }
+
+            writeln!(f, "  {},", constructor.variant_name)?;
+        } else {
+            // The wrapped struct serializes its own identifier
+            writeln!(
+                f,
+                "  {}({}),",
+                constructor.variant_name,
+                constructor.struct_name
+            )?;
+        }
+    }
+
+    writeln!(f, "}}")?;
+
+    // Enums without a parameterless constructor can't derive `Default`
+    if default_index.is_none() {
+        writeln!(f, "impl Default for {} {{", name)?;
+        writeln!(f, "  fn default() -> Self {{")?;
+        writeln!(
+            f,
+            "    {}::{}(Default::default())",
+            name,
+            type_.constructors[0].variant_name
+        )?;
+        writeln!(f, "  }}")?;
+        writeln!(f, "}}")?;
+    }
+
+    writeln!(f, "impl ::TlObject for {} {{", name)?;
+
+    writeln!(f, "  fn constructor_id(&self) -> u32 {{")?;
+    writeln!(f, "    match *self {{")?;
+    for constructor in &type_.constructors {
+        writeln!(
+            f,
+            "      {} => 0x{:x},",
+            variant_pattern(name, constructor),
+            constructor.id
+        )?;
+    }
+    writeln!(f, "    }}")?;
+    writeln!(f, "  }}")?;
+
+    writeln!(f, "  fn tl_name(&self) -> &'static str {{")?;
+    writeln!(f, "    match *self {{")?;
+    for constructor in &type_.constructors {
+        writeln!(
+            f,
+            "      {} => {:?},",
+            variant_pattern(name, constructor),
+            constructor.name
+        )?;
+    }
+    writeln!(f, "    }}")?;
+    writeln!(f, "  }}")?;
+
+    writeln!(f, "}}")?;
+
+    Ok(())
+}
+
+/// Pattern matching the enum variant of a constructor regardless of its contents.
+fn variant_pattern(name: &str, constructor: &Constructor) -> String {
+    if constructor.params.is_empty() {
+        format!("{}::{}", name, constructor.variant_name)
+    } else {
+        format!("{}::{}(_)", name, constructor.variant_name)
+    }
+}
+
+/// The type a parameter refers to once its condition, bareness and vector are stripped,
+/// e.g. `Message` for `flags.0?Vector<message>`.
+fn base_typename(typename: &str, names: &Names) -> String {
+    let typename = typename.rsplit('?').next().unwrap().replace('%', "");
+    let typename = match typename.find('<') {
+        Some(index) => typename[index + 1..typename.len() - 1].to_string(),
+        None => typename,
+    };
+
+    names.predicates.get(&typename).cloned().unwrap_or(typename)
+}
+
+/// Whether a parameter holds a string or bytes, possibly through another type.
+fn borrows(typename: &str, names: &Names, borrowing: &HashSet<String>) -> bool {
+    let typename = base_typename(typename, names);
+
+    typename == "string" || typename == "bytes" || borrowing.contains(&typename)
+}
+
+fn constructor_borrows(constructor: &Constructor, names: &Names, borrowing: &HashSet<String>) -> bool {
+    constructor
+        .params
+        .iter()
+        .any(|param| borrows(&param.kind, names, borrowing))
+}
+
+/// Like `translate_typename`, for the types of the `borrowed` module, which refer to
+/// strings and bytes as `&'a str` and `&'a [u8]`.
+fn translate_borrowed_typename(
+    typename: &str,
+    current_module: &Option<String>,
+    names: &Names,
+    borrowing: &HashSet<String>,
+) -> String {
+    let translate = |typename: &str| {
+        translate_borrowed_typename(typename, current_module, names, borrowing)
+    };
+
+    if let Some(index) = typename.find('?') {
+        match &typename[index + 1..] {
+            "true" => "bool".into(),
+            typename => format!("Option<{}>", translate(typename)),
+        }
+    } else if typename.contains('%') {
+        translate(&typename.replace('%', ""))
+    } else if typename.contains("Vector<") || typename.contains("vector<") {
+        let s = typename.split(['<', '>']).collect::<Vec<_>>();
+
+        format!("Vec<{}>", translate(s[1]))
+    } else if let Some(kind) = names.predicates.get(typename) {
+        translate(kind)
+    } else if typename == "string" {
+        "&'a str".into()
+    } else if typename == "bytes" {
+        "&'a [u8]".into()
+    } else if borrowing.contains(typename) {
+        format!("{}<'a>", translate_typename(typename, current_module, names))
+    } else {
+        translate_typename(typename, current_module, names)
+    }
+}
+
+/// Path from the `borrowed` module mirroring `module` to the owned type `rust_name`.
+fn owned_path(module: &Option<String>, rust_name: &str) -> String {
+    match *module {
+        Some(ref module) => format!("super::super::{}::{}", module, rust_name),
+        None => format!("super::{}", rust_name),
+    }
+}
+
+/// Write the borrowing variant of a constructor struct, or re-export the owned one
+/// if it holds no strings or bytes.
+fn write_borrowed_struct(
+    f: &mut File,
+    constructor: &Constructor,
+    module_name: &Option<String>,
+    names: &Names,
+    borrowing: &HashSet<String>,
+) -> error::Result<()> {
+    let name = &constructor.struct_name;
+    let owned = owned_path(module_name, name);
+
+    if !constructor_borrows(constructor, names, borrowing) {
+        writeln!(f, "pub use {};", owned)?;
+        return Ok(());
+    }
+
+    writeln!(f, "impl<'a> ::TlObject for {}<'a> {{", name)?;
+    writeln!(f, "  fn constructor_id(&self) -> u32 {{ 0x{:x} }}", constructor.id)?;
+    writeln!(f, "  fn tl_name(&self) -> &'static str {{ {:?} }}", constructor.name)?;
+    writeln!(f, "}}")?;
+
+    writeln!(f, "impl<'a> ::TlConstructor for {}<'a> {{", name)?;
+    writeln!(f, "  const CONSTRUCTOR_ID: u32 = 0x{:x};", constructor.id)?;
+    writeln!(f, "  const TL_NAME: &'static str = {:?};", constructor.name)?;
+    writeln!(f, "}}")?;
+
+    writeln!(f, "#[derive(Debug, Clone, PartialEq, Deserialize)]")?;
+    write_id(f, "", constructor)?;
+    writeln!(f, "#[tl(name = {:?})]", constructor.name)?;
+    writeln!(f, "pub struct {}<'a> {{", name)?;
+
+    for (param, field) in constructor.params.iter().zip(&constructor.fields) {
+        if param.kind == "#" {
+            writeln!(f, "    #[tl(flags)]")?;
+        } else if let Some(condition) = param.condition() {
+            writeln!(f, "    #[tl(flag = {:?})]", condition)?;
+        } else if param.is_bare() {
+            writeln!(f, "    #[tl(bare)]")?;
+        }
+
+        writeln!(
+            f,
+            "    pub {}: {},",
+            field,
+            translate_borrowed_typename(&param.kind, module_name, names, borrowing)
+        )?;
+    }
+
+    writeln!(f, "}}")?;
+
+    writeln!(f, "impl<'a> ::de::IntoOwned for {}<'a> {{", name)?;
+    writeln!(f, "  type Owned = {};", owned)?;
+    writeln!(f, "  fn into_owned(self) -> Self::Owned {{")?;
+    writeln!(f, "    {} {{", owned)?;
+
+    for (param, field) in constructor.params.iter().zip(&constructor.fields) {
+        if borrows(&param.kind, names, borrowing) {
+            writeln!(f, "      {0}: ::de::IntoOwned::into_owned(self.{0}),", field)?;
+        } else {
+            writeln!(f, "      {0}: self.{0},", field)?;
+        }
+    }
+
+    writeln!(f, "    }}")?;
+    writeln!(f, "  }}")?;
+    writeln!(f, "}}")?;
+
+    Ok(())
+}
+
+/// Write the borrowing variant of a type with several constructors.
+fn write_borrowed_enum(
+    f: &mut File,
+    type_: &Type,
+    module_name: &Option<String>,
+    names: &Names,
+    borrowing: &HashSet<String>,
+) -> error::Result<()> {
+    let name = &type_.rust_name;
+    let owned = owned_path(module_name, name);
+
+    for constructor in type_.constructors.iter().filter(|c| !c.params.is_empty()) {
+        write_borrowed_struct(f, constructor, module_name, names, borrowing)?;
+    }
+
+    writeln!(f, "#[derive(Debug, Clone, PartialEq, Deserialize)]")?;
+    writeln!(f, "#[tl(name = {:?})]", type_.name)?;
+    writeln!(f, "pub enum {}<'a> {{", name)?;
+
+    for constructor in &type_.constructors {
+        if constructor.params.is_empty() {
+            write_id(f, "  ", constructor)?;
+            writeln!(f, "  {},", constructor.variant_name)?;
+        } else if constructor_borrows(constructor, names, borrowing) {
+            writeln!(f, "  {}({}<'a>),", constructor.variant_name, constructor.struct_name)?;
+        } else {
+            writeln!(f, "  {}({}),", constructor.variant_name, constructor.struct_name)?;
+        }
+    }
+
+    writeln!(f, "}}")?;
+
+    writeln!(f, "impl<'a> ::de::IntoOwned for {}<'a> {{", name)?;
+    writeln!(f, "  type Owned = {};", owned)?;
+    writeln!(f, "  fn into_owned(self) -> Self::Owned {{")?;
+    writeln!(f, "    match self {{")?;
+
+    for constructor in &type_.constructors {
+        let variant = &constructor.variant_name;
+
+        if constructor.params.is_empty() {
+            writeln!(f, "      {}::{} => {}::{},", name, variant, owned, variant)?;
+        } else if constructor_borrows(constructor, names, borrowing) {
+            writeln!(
+                f,
+                "      {}::{}(value) => {}::{}(::de::IntoOwned::into_owned(value)),",
+                name,
+                variant,
+                owned,
+                variant
+            )?;
+        } else {
+            writeln!(f, "      {}::{}(value) => {}::{}(value),", name, variant, owned, variant)?;
+        }
+    }
+
+    writeln!(f, "    }}")?;
+    writeln!(f, "  }}")?;
+    writeln!(f, "}}")?;
+
+    Ok(())
+}
+
+/// Write the `borrowed` module, mirroring the types of the schema with variants
+/// borrowing their strings and bytes from the deserialized input. Types holding
+/// neither are re-exported as they are.
+fn write_borrowed(
+    f: &mut File,
+    modules: &Modules,
+    names: &Names,
+) -> error::Result<()> {
+    // Full TL names of the types holding strings or bytes, possibly through other types
+    let mut borrowing = HashSet::new();
+
+    loop {
+        let mut changed = false;
+
+        for (module_name, module) in modules {
+            for type_ in module.types.iter().filter(|type_| !type_.method) {
+                let full_name = match *module_name {
+                    Some(ref module_name) => format!("{}.{}", module_name, type_.name),
+                    None => type_.name.clone(),
+                };
+
+                if !borrowing.contains(&full_name) &&
+                    type_.constructors.iter().any(|constructor| {
+                        constructor_borrows(constructor, names, &borrowing)
+                    })
+                {
+                    borrowing.insert(full_name);
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    writeln!(f, "pub mod borrowed {{")?;
+
+    for (module_name, module) in modules {
+        if let Some(ref module_name) = *module_name {
+            writeln!(f, "pub mod {} {{", module_name)?;
+        }
+
+        for type_ in module.types.iter().filter(|type_| !type_.method) {
+            let full_name = match *module_name {
+                Some(ref module_name) => format!("{}.{}", module_name, type_.name),
+                None => type_.name.clone(),
+            };
+
+            if !borrowing.contains(&full_name) {
+                writeln!(f, "pub use {};", owned_path(module_name, &type_.rust_name))?;
+            } else if type_.constructors.len() == 1 {
+                write_borrowed_struct(f, &type_.constructors[0], module_name, names, &borrowing)?;
+            } else {
+                write_borrowed_enum(f, type_, module_name, names, &borrowing)?;
+            }
+        }
+
+        if module_name.is_some() {
+            writeln!(f, "}}")?;
+        }
+    }
+
+    writeln!(f, "}}")?;
+
+    Ok(())
+}
+
+/// Write the metadata of every constructor and method, as `CONSTRUCTORS`: first the
+/// `builtins`, which have no generated type, then those of each module.
+fn write_constructors(
+    f: &mut File,
+    modules: &Modules,
+    builtins: &[&parser::Constructor],
+) -> error::Result<()> {
+    writeln!(f, "pub static CONSTRUCTORS: &[::meta::Constructor] = &[")?;
+
+    for builtin in builtins {
+        writeln!(
+            f,
+            "  ::meta::Constructor {{ id: 0x{:08x}, name: {:?}, kind: {:?}, method: false, params: &[] }},",
+            builtin.id.parse::<i32>()?,
+            builtin.predicate,
+            builtin.kind
+        )?;
+    }
+
+    for module in modules.values() {
+        for (type_, constructor) in module
+            .types
+            .iter()
+            .flat_map(|type_| type_.constructors.iter().map(move |constructor| (type_, constructor)))
+        {
+            let params = constructor
+                .params
+                .iter()
+                .map(|param| {
+                    format!(
+                        "::meta::Param {{ name: {:?}, kind: {:?} }}",
+                        param.name,
+                        param.kind
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            writeln!(
+                f,
+                "  ::meta::Constructor {{ id: 0x{:08x}, name: {:?}, kind: {:?}, method: {}, params: &[{}] }},",
+                constructor.id,
+                constructor.name,
+                constructor.kind,
+                type_.method,
+                params.join(", ")
+            )?;
+        }
+    }
+
+    writeln!(f, "];")?;
+
+    Ok(())
+}
+
+/// Strip the module from a TL identifier, e.g. `auth.sendCode` into `sendCode`.
+fn without_module(name: &str) -> &str {
+    name.rsplit('.').next().unwrap()
+}
+
+/// Assign Rust names to the items of a module. Names are claimed in order of precedence
+/// (modules, types, methods, constructor structs), so that collisions like the constructor
+/// `message` of the type `Message` are resolved in favour of the latter.
+fn assign_names(module: &mut Module, module_names: &[String]) {
+    let mut namespace = Namespace::default();
+
+    for module_name in module_names {
+        namespace.claim(module_name.clone());
+    }
+
+    for type_ in module.types.iter_mut().filter(|type_| !type_.method) {
+        type_.rust_name = namespace.claim(camel_case(&type_.name));
+    }
+
+    for type_ in module.types.iter_mut().filter(|type_| type_.method) {
+        type_.rust_name = namespace.claim(camel_case(&type_.name));
+
+        if type_.constructors.iter().any(Constructor::has_conditional) {
+            type_.builder_name = namespace.claim(format!("{}Builder", camel_case(&type_.name)));
+        }
+    }
+
+    for type_ in &mut module.types {
+        let mut variants = Namespace::default();
+        let single = type_.constructors.len() == 1;
+
+        for constructor in &mut type_.constructors {
+            let name = without_module(&constructor.name);
+
+            constructor.variant_name = variants.claim(camel_case(name));
+            constructor.struct_name = if single {
+                type_.rust_name.clone()
+            } else if !constructor.params.is_empty() {
+                namespace.claim(camel_case(name))
+            } else {
+                String::new()
+            };
+
+            let mut fields = Namespace::default();
+            constructor.fields = constructor
+                .params
+                .iter()
+                .map(|param| fields.claim(snake_case(&param.name)))
+                .collect();
+        }
+    }
+}
+
 /// Generate Rust definitions to the file from the schema
 pub fn generate<P: AsRef<Path>>(filename: P, schema: &Schema) -> error::Result<()> {
-    let mut modules = HashMap::<Option<String>, Module>::new();
-    let mut predicates = HashMap::<String, String>::new();
+    let mut modules = Modules::new();
+    let mut names = Names::default();
+
+    // Constructors of the types mapped onto Rust ones, still described in `CONSTRUCTORS`
+    // but for `vector`, which isn't an object: its elements are read by the type holding it
+    let mut builtins = Vec::new();
 
     // Translate: Constructors
     for constructor in &schema.constructors {
@@ -155,27 +992,62 @@ pub fn generate<P: AsRef<Path>>(filename: P, schema: &Schema) -> error::Result<(
         )? {
             Some(value) => value,
             None => {
+                if constructor.predicate != "vector" {
+                    builtins.push(constructor);
+                }
+
                 continue;
             }
         };
 
         // Add a map for predicate -> typename
-        predicates
+        names
+            .predicates
             .entry(c.name.clone())
-            .or_insert_with(|| name.to_string());
+            .or_insert_with(|| constructor.kind.clone());
 
         // Build up type in module
-        let module_ = &mut modules.entry(module).or_insert_with(Default::default);
-        let type_ = &mut module_
-            .types
-            .entry(name.to_string())
-            .or_insert_with(Default::default);
-        type_.constructors.push(c);
+        let module_ = &mut modules.entry(module).or_default();
+        let index = match module_.types.iter().position(|type_| type_.name == name) {
+            Some(index) => index,
+            None => {
+                module_.types.push(Type {
+                    name: name.clone(),
+                    constructors: Vec::new(),
+                    rust_name: String::new(),
+                    builder_name: String::new(),
+                    method: false,
+                    reply: None,
+                });
+
+                module_.types.len() - 1
+            }
+        };
+
+        module_.types[index].constructors.push(c);
+    }
+
+    // TL types which are generated or mapped onto a Rust primitive type
+    let mut known_types = ["Bool", "int", "long", "double", "string", "bytes", "X"]
+        .iter()
+        .map(|kind| kind.to_string())
+        .collect::<HashSet<_>>();
+
+    for constructor in &schema.constructors {
+        if to_constructor(
+            &constructor.id,
+            &constructor.predicate,
+            &constructor.params,
+            &constructor.kind,
+        )?.is_some()
+        {
+            known_types.insert(constructor.kind.clone());
+        }
     }
 
     // Translate: Methods
     for method in &schema.methods {
-        let (module, name, c) =
+        let (module, name, mut c) =
             match to_constructor(&method.id, &method.method, &method.params, &method.method)? {
                 Some(value) => value,
                 None => {
@@ -183,17 +1055,50 @@ pub fn generate<P: AsRef<Path>>(filename: P, schema: &Schema) -> error::Result<(
                 }
             };
 
-        // Add a map for predicate -> typename
-        predicates
-            .entry(c.name.clone())
-            .or_insert_with(|| name.to_string());
+        c.kind = method.kind.clone();
 
         // Build up type in module
-        let module_ = &mut modules.entry(module).or_insert_with(Default::default);
-        let type_ = Type {
-            constructors: vec![c],
+        let module_ = &mut modules.entry(module).or_default();
+        // Methods returning a type which isn't generated (e.g. `HttpWait`) can't be
+        // invoked, as their result could not be represented
+        let reply = method.kind.replace("Vector<", "").replace('>', "");
+        let reply = if known_types.contains(&reply) {
+            Some(method.kind.clone())
+        } else {
+            None
         };
-        module_.types.insert(name.to_string(), type_);
+
+        module_.types.push(Type {
+            name,
+            constructors: vec![c],
+            rust_name: String::new(),
+            builder_name: String::new(),
+            method: true,
+            reply,
+        });
+    }
+
+    let module_names = modules
+        .keys()
+        .filter_map(|module_name| module_name.clone())
+        .collect::<Vec<_>>();
+
+    for (module_name, module) in &mut modules {
+        // Only the root module holds other modules
+        if module_name.is_none() {
+            assign_names(module, &module_names);
+        } else {
+            assign_names(module, &[]);
+        }
+
+        for type_ in module.types.iter().filter(|type_| !type_.method) {
+            let full_name = match *module_name {
+                Some(ref module_name) => format!("{}.{}", module_name, type_.name),
+                None => type_.name.clone(),
+            };
+
+            names.types.insert(full_name, type_.rust_name.clone());
+        }
     }
 
     // Output buffered information
@@ -204,78 +1109,13 @@ pub fn generate<P: AsRef<Path>>(filename: P, schema: &Schema) -> error::Result<(
             writeln!(f, "pub mod {} {{", module_name)?;
         }
 
-        if module.types.values().any(|type_| {
-            type_.constructors.iter().any(|constructor| {
-                constructor.params.iter().any(|param| {
-                    param.kind.len() >= 3 && &param.kind[..3] == "int" &&
-                        param.kind[3..]
-                            .parse::<u64>()
-                            .ok()
-                            .map_or(false, |bitness| bitness >= 128)
-                })
-            })
-        }) {
-            writeln!(f, "use extprim::i128::i128;")?;
-        }
-
-        for (name, type_) in &module.types {
-            writeln!(f, "#[derive(Debug, Serialize)]")?;
-
-            // Open type
+        for type_ in &module.types {
             if type_.constructors.len() == 1 {
                 // A single constructor is output as a struct
-                writeln!(f, "#[id = \"0x{:x}\"]", type_.constructors[0].id)?;
-
-                if type_.constructors[0].params.is_empty() {
-                    // A single constructor with no parameters is a unit
-                    writeln!(f, "pub struct {};", name)?;
-                    continue;
-                } else {
-                    writeln!(f, "pub struct {} {{", name)?;
-                }
+                write_struct(&mut f, type_, &type_.constructors[0], module_name, &names)?;
             } else {
-                writeln!(f, "pub enum {} {{", name)?;
-            }
-
-            for constructor in &type_.constructors {
-                let constructor_name = translate_id(&constructor.name, module_name);
-
-                if constructor.params.is_empty() {
-                    // No parameters
-                    writeln!(f, "  #[id = \"0x{:x}\"]", constructor.id)?;
-                    writeln!(f, "  {},", constructor_name)?;
-                } else {
-                    // Open constructor (if more than 1)
-                    if type_.constructors.len() > 1 {
-                        writeln!(f, "  #[id = \"0x{:x}\"]", constructor.id)?;
-                        writeln!(f, "  {} {{", constructor_name)?;
-                    }
-
-                    // Write out parameters
-                    for param in &constructor.params {
-                        write!(f, "    ")?;
-
-                        if type_.constructors.len() == 1 {
-                            write!(f, "pub ")?;
-                        }
-
-                        writeln!(
-                            f,
-                            "{}: {},",
-                            translate_id(&param.name, module_name),
-                            translate_typename(&param.kind, module_name, &predicates)
-                        )?;
-                    }
-
-                    // Close constructor (if more than 1)
-                    if type_.constructors.len() > 1 {
-                        writeln!(f, "  }},")?;
-                    }
-                }
+                write_enum(&mut f, type_, module_name, &names)?;
             }
-
-            // Close type
-            writeln!(f, "}}")?;
         }
 
         if module_name.is_some() {
@@ -284,5 +1124,8 @@ pub fn generate<P: AsRef<Path>>(filename: P, schema: &Schema) -> error::Result<(
         }
     }
 
+    write_constructors(&mut f, &modules, &builtins)?;
+    write_borrowed(&mut f, &modules, &names)?;
+
     Ok(())
 }
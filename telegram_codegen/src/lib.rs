@@ -10,6 +10,7 @@ extern crate serde_json;
 
 
 mod error;
+mod names;
 mod parser;
 mod generator;
 
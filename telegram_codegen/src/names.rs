@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+
+/// Strict and reserved keywords of Rust, which can't be used as identifiers.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "try",
+    "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Split a TL identifier into its words, e.g. `req_DH_params` into `req`, `DH`, `params`
+/// and `inputPeerUser` into `input`, `Peer`, `User`.
+fn words(name: &str) -> Vec<String> {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut word = String::new();
+
+    for (index, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '.' {
+            if !word.is_empty() {
+                words.push(word);
+                word = String::new();
+            }
+
+            continue;
+        }
+
+        if c.is_uppercase() && !word.is_empty() {
+            let previous = chars[index - 1];
+            let next = chars.get(index + 1);
+
+            // Start a new word on `aB`, `1B` and on the last capital of an acronym (`DHParams`)
+            if previous.is_lowercase() || previous.is_numeric() ||
+                (previous.is_uppercase() && next.is_some_and(|next| next.is_lowercase()))
+            {
+                words.push(word);
+                word = String::new();
+            }
+        }
+
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+/// Convert a TL identifier to `CamelCase`, as used for types and variants.
+pub fn camel_case(name: &str) -> String {
+    words(name)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap();
+
+            first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect::<String>()
+        })
+        .collect()
+}
+
+/// Convert a TL identifier to `snake_case`, as used for fields.
+pub fn snake_case(name: &str) -> String {
+    words(name)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// The identifiers taken in a Rust namespace (the items of a module, the variants of an
+/// enum or the fields of a struct).
+#[derive(Default)]
+pub struct Namespace {
+    taken: HashSet<String>,
+}
+
+impl Namespace {
+    /// Claim `name`, appending underscores as long as it is a keyword or already taken
+    /// (e.g. `type` becomes `type_`, the constructor `message` of `Message` becomes `Message_`).
+    pub fn claim(&mut self, name: String) -> String {
+        let mut name = name;
+
+        while KEYWORDS.contains(&name.as_str()) || self.taken.contains(&name) {
+            name.push('_');
+        }
+
+        self.taken.insert(name.clone());
+
+        name
+    }
+}
@@ -9,6 +9,29 @@ pub struct Parameter {
     pub kind: String,
 }
 
+impl Parameter {
+    /// Condition of a conditional parameter, e.g. `flags.3` for `flags.3?int`.
+    pub fn condition(&self) -> Option<&str> {
+        self.kind.find('?').map(|index| &self.kind[..index])
+    }
+
+    /// Whether the parameter is of a bare constructor rather than a boxed type, e.g.
+    /// `%Message`, `future_salt` or a bare `vector<..>` of those.
+    pub fn is_bare(&self) -> bool {
+        let kind = self.kind.rsplit('?').next().unwrap();
+
+        match kind {
+            "int" | "long" | "double" | "string" | "bytes" | "int128" | "int256" | "true" | "#" => {
+                false
+            }
+            _ => {
+                kind.starts_with('%') || kind.starts_with("vector<")
+                    || kind.rsplit('.').next().unwrap().starts_with(char::is_lowercase)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Constructor {
     pub id: String,
@@ -1,168 +1,975 @@
+//! `#[derive(Serialize, Deserialize)]` for TL objects of the `telegram` crate.
+//!
+//! - `#[id = 0x..]` on a struct or an enum variant: the constructor identifier.
+//! - `#[tl(combinator = "..")]`: the TL combinator (e.g. `peerUser user_id:int = Peer`),
+//!   whose CRC32 the identifier is checked against, unless `#[tl(unverified_id)]`.
+//! - `#[tl(name = "..")]`: the TL name, used in errors.
+//! - `#[tl(observed)]` on a struct or an enum: deserialized values are passed to
+//!   `de::Observed`, e.g. to add them to the peer cache of the deserializer.
+//! - `#[tl(crate = "..")]`: the path of the `telegram` crate, `::telegram` by default.
+//! - `#[tl(flags)]`, `#[tl(flag = "flags.N")]`: a `#` parameter and a field conditional
+//!   on one of its bits (fields of tuples are referred to by index, e.g. `0.N`).
+//! - `#[tl(bare)]`: a field serialized without its constructor identifier.
+//! - `#[tl(skip)]`: a field which isn't serialized, deserialized as its `Default`.
+
 extern crate proc_macro;
 extern crate syn;
 
 #[macro_use]
 extern crate quote;
 
-use std::u32;
-
-use proc_macro::TokenStream;
-use syn::{Attribute, Body, Field, Lit, MetaItem, StrStyle, VariantData};
+use proc_macro::{Delimiter, Group, Ident, TokenStream, TokenTree};
+use syn::{Attribute, Body, Field, Lit, MetaItem, NestedMetaItem, StrStyle, VariantData};
 
-#[proc_macro_derive(Serialize, attributes(id))]
+#[proc_macro_derive(Serialize, attributes(id, tl))]
 pub fn serialize(input: TokenStream) -> TokenStream {
-    // Construct a string representation of the type definition
-    let s = input.to_string();
+    expand(input, impl_serialize)
+}
 
-    // Parse the string representation
-    let ast = syn::parse_derive_input(&s).unwrap();
+#[proc_macro_derive(Deserialize, attributes(id, tl))]
+pub fn deserialize(input: TokenStream) -> TokenStream {
+    expand(input, impl_deserialize)
+}
 
-    // Build the impl
-    let gen = impl_serialize(&ast);
+/// Name standing in for the `crate` key of `#[tl(crate = "..")]` while parsing.
+const CRATE: &str = "tl_crate";
+
+/// Parse the type definition and build the impl, reporting invalid input as a
+/// compile error rather than a panic of the derive.
+fn expand(
+    input: TokenStream,
+    impl_: fn(&syn::DeriveInput) -> Result<quote::Tokens, String>,
+) -> TokenStream {
+    // Construct a string representation of the type definition
+    let s = rename_crate_keys(input).to_string();
+
+    let gen = syn::parse_derive_input(&s)
+        .and_then(|ast| impl_(&ast))
+        .unwrap_or_else(|message| quote! { compile_error!(#message); });
 
     // Return the generated impl
     gen.parse().unwrap()
 }
 
-enum BodyType {
-    Struct,
-    Enum,
+/// Rename the `crate` key of the `#[tl(..)]` attributes of the type, its variants and its
+/// fields to `CRATE`, as syn doesn't parse keywords in attributes.
+fn rename_crate_keys(input: TokenStream) -> TokenStream {
+    let mut tokens = Vec::new();
+
+    for token in input {
+        let attribute = match tokens.last() {
+            Some(TokenTree::Punct(punct)) => punct.as_char() == '#',
+            _ => false,
+        };
+
+        let token = match token {
+            TokenTree::Group(ref group) => {
+                let stream = match group.delimiter() {
+                    Delimiter::Bracket if attribute => rename_tl_crate_key(group.stream()),
+                    _ => rename_crate_keys(group.stream()),
+                };
+
+                let mut renamed = Group::new(group.delimiter(), stream);
+                renamed.set_span(group.span());
+                TokenTree::Group(renamed)
+            }
+
+            token => token,
+        };
+
+        tokens.push(token);
+    }
+
+    tokens.into_iter().collect()
 }
 
-fn impl_serialize(ast: &syn::DeriveInput) -> quote::Tokens {
+/// Rename the `crate` key of the contents of an attribute, if it is `tl(..)`.
+fn rename_tl_crate_key(attribute: TokenStream) -> TokenStream {
+    let mut tokens = attribute.into_iter().collect::<Vec<_>>();
+
+    let items = match (tokens.first(), tokens.get(1)) {
+        (Some(TokenTree::Ident(name)), Some(TokenTree::Group(items)))
+            if name.to_string() == "tl" && items.delimiter() == Delimiter::Parenthesis =>
+        {
+            items.clone()
+        }
+
+        _ => return tokens.into_iter().collect(),
+    };
+
+    let mut renamed = items.stream().into_iter().collect::<Vec<_>>();
+
+    for index in 0..renamed.len() {
+        let is_key = match (&renamed[index], renamed.get(index + 1)) {
+            (TokenTree::Ident(key), Some(TokenTree::Punct(punct))) => {
+                key.to_string() == "crate" && punct.as_char() == '='
+            }
+
+            _ => false,
+        };
+
+        if is_key {
+            renamed[index] = TokenTree::Ident(Ident::new(CRATE, renamed[index].span()));
+        }
+    }
+
+    let mut group = Group::new(Delimiter::Parenthesis, renamed.into_iter().collect());
+    group.set_span(items.span());
+    tokens[1] = TokenTree::Group(group);
+
+    tokens.into_iter().collect()
+}
+
+fn impl_serialize(ast: &syn::DeriveInput) -> Result<quote::Tokens, String> {
     let item_name = &ast.ident;
 
-    let full_serialize_to_body = match ast.body {
+    let krate = crate_path(&ast.attrs)?;
+
+    let (impl_generics, ty_generics, _) = ast.generics.split_for_impl();
+    let where_clause = where_clause(&ast.generics, &quote! { #krate::ser::Serialize });
+
+    match ast.body {
         Body::Struct(ref data) => {
-            match *data {
-                VariantData::Struct(ref fields) => {
-                    impl_serialize_to_body(BodyType::Struct, &ast.attrs, Some(fields))
+            let fields = tl_fields(data.fields())?;
+
+            // Place expressions for the values of the fields
+            let accessors = data.fields()
+                .iter()
+                .enumerate()
+                .map(|(index, field)| match field.ident {
+                    Some(ref field_name) => quote! { self.#field_name },
+                    None => {
+                        let index = syn::Ident::new(index.to_string());
+                        quote! { self.#index }
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let body = impl_serialize_fields(&krate, &fields, &accessors);
+            let bare_serialize_to = body.serialize_to;
+            let bare_serialized_len = body.serialized_len;
+
+            let id = constructor_id(&ast.attrs)?.map(|value| {
+                quote! {
+                    #krate::ser::Serialize::serialize_to(&#value, buffer)?;
                 }
+            });
 
-                VariantData::Tuple(_) => unreachable!(),
+            let id_len = if id.is_some() { 4usize } else { 0 };
 
-                VariantData::Unit => impl_serialize_to_body(BodyType::Struct, &ast.attrs, None),
-            }
+            Ok(quote! {
+                impl #impl_generics #krate::ser::Serialize for #item_name #ty_generics #where_clause {
+                    fn serialize_to<W: ::std::io::Write>(&self, buffer: &mut W) -> #krate::error::Result<()> {
+                        // Identifier
+                        #id
+
+                        #krate::ser::SerializeBare::serialize_bare_to(self, buffer)
+                    }
+
+                    fn serialized_len(&self) -> usize {
+                        #id_len + #krate::ser::SerializeBare::serialized_bare_len(self)
+                    }
+                }
+
+                impl #impl_generics #krate::ser::SerializeBare for #item_name #ty_generics #where_clause {
+                    fn serialize_bare_to<W: ::std::io::Write>(&self, buffer: &mut W) -> #krate::error::Result<()> {
+                        #bare_serialize_to
+                    }
+
+                    fn serialized_bare_len(&self) -> usize {
+                        #bare_serialized_len
+                    }
+                }
+            })
         }
 
         Body::Enum(ref variants) => {
             let mut tokens_variants = quote::Tokens::new();
+            let mut tokens_len_variants = quote::Tokens::new();
 
             for variant in variants {
-                let ref variant_name = variant.ident;
-
-                match variant.data {
-                    VariantData::Struct(ref fields) => {
-                        let serialize_to_body =
-                            impl_serialize_to_body(BodyType::Enum, &variant.attrs, Some(fields));
-
-                        let quoted_fields = fields
-                            .iter()
-                            .map(|field| match field.ident {
-                                Some(ref ident) => quote! { ref #ident },
-                                None => unreachable!(),
-                            })
-                            .collect::<Vec<_>>();
-
-                        tokens_variants.append(quote! {
-                            #item_name::#variant_name { #(#quoted_fields),* } => {
-                                #serialize_to_body
-                            },
+                check_variant(variant)?;
+
+                let variant_name = &variant.ident;
+                let fields = tl_fields(variant.data.fields())?;
+
+                // Skipped fields are left unbound
+                let bindings = variant.data
+                    .fields()
+                    .iter()
+                    .zip(&fields)
+                    .enumerate()
+                    .map(|(index, (field, tl_field))| {
+                        let ident = match field.ident {
+                            Some(ref ident) => ident.clone(),
+                            None => syn::Ident::new(format!("field{}", index)),
+                        };
+
+                        let binding = match tl_field.kind {
+                            FieldKind::Skip => quote! { _ },
+                            _ => quote! { ref #ident },
+                        };
+
+                        (ident, binding)
+                    })
+                    .collect::<Vec<_>>();
+
+                let accessors = bindings
+                    .iter()
+                    .map(|(ident, _)| quote! { (*#ident) })
+                    .collect::<Vec<_>>();
+
+                let pattern = match variant.data {
+                    VariantData::Struct(_) => {
+                        let bindings = bindings.iter().map(|(ident, binding)| {
+                            quote! { #ident: #binding }
                         });
+
+                        quote! { #item_name::#variant_name { #(#bindings),* } }
                     }
 
-                    VariantData::Tuple(_) => unreachable!(),
+                    VariantData::Tuple(_) => {
+                        let bindings = bindings.iter().map(|(_, binding)| binding);
 
-                    VariantData::Unit => {
-                        let serialize_to_body =
-                            impl_serialize_to_body(BodyType::Enum, &variant.attrs, None);
+                        quote! { #item_name::#variant_name(#(#bindings),*) }
+                    }
 
-                        tokens_variants.append(quote! {
-                            #item_name::#variant_name => {
-                                #serialize_to_body
-                            },
-                        });
+                    VariantData::Unit => quote! { #item_name::#variant_name },
+                };
+
+                let mut body = impl_serialize_fields(&krate, &fields, &accessors);
+
+                if let Some(id) = constructor_id(&variant.attrs)? {
+                    body = body.with_id(&krate, id);
+                }
+
+                body.append_arms(&pattern, &mut tokens_variants, &mut tokens_len_variants);
+            }
+
+            Ok(quote! {
+                impl #impl_generics #krate::ser::Serialize for #item_name #ty_generics #where_clause {
+                    fn serialize_to<W: ::std::io::Write>(&self, buffer: &mut W) -> #krate::error::Result<()> {
+                        match *self {
+                            #tokens_variants
+                        }
+                    }
+
+                    fn serialized_len(&self) -> usize {
+                        match *self {
+                            #tokens_len_variants
+                        }
                     }
                 }
+            })
+        }
+    }
+}
+
+/// The where clause of an impl, bounding every type parameter by `bound`.
+fn where_clause(generics: &syn::Generics, bound: &quote::Tokens) -> quote::Tokens {
+    let mut predicates = generics
+        .where_clause
+        .predicates
+        .iter()
+        .map(|predicate| quote! { #predicate })
+        .collect::<Vec<_>>();
+
+    for param in &generics.ty_params {
+        let ident = &param.ident;
+        predicates.push(quote! { #ident: #bound });
+    }
+
+    if predicates.is_empty() {
+        quote! {}
+    } else {
+        quote! { where #(#predicates),* }
+    }
+}
+
+/// The bodies of `serialize_to` and `serialized_len` for a struct or an enum variant.
+struct SerializeBody {
+    serialize_to: quote::Tokens,
+    serialized_len: quote::Tokens,
+}
+
+impl SerializeBody {
+    /// Prefix the fields with the constructor identifier.
+    fn with_id(self, krate: &quote::Tokens, id: u32) -> SerializeBody {
+        let serialize_to = self.serialize_to;
+        let serialized_len = self.serialized_len;
+
+        SerializeBody {
+            serialize_to: quote! {
+                #krate::ser::Serialize::serialize_to(&#id, buffer)?;
+                #serialize_to
+            },
+            serialized_len: quote! { 4 + #serialized_len },
+        }
+    }
+
+    /// Append the match arms of an enum variant matched by `pattern`.
+    fn append_arms(
+        self,
+        pattern: &quote::Tokens,
+        serialize_to: &mut quote::Tokens,
+        serialized_len: &mut quote::Tokens,
+    ) {
+        let serialize_to_body = self.serialize_to;
+        let serialized_len_body = self.serialized_len;
+
+        serialize_to.append(quote! {
+            #pattern => {
+                #serialize_to_body
+            },
+        });
+
+        serialized_len.append(quote! {
+            #pattern => {
+                #serialized_len_body
+            },
+        });
+    }
+}
+
+/// How a field is represented on the wire.
+enum FieldKind {
+    Plain,
+    /// `#[tl(flags)]`: a `#` parameter computed from the conditional fields.
+    Flags,
+    /// `#[tl(flag = "flags.N")]`: a field present when bit `N` of the `#` parameter at
+    /// the given index is set.
+    Flag(usize, u32),
+    /// `#[tl(skip)]`: a field which isn't part of the TL object, deserialized as its
+    /// `Default`.
+    Skip,
+}
+
+/// A field of a struct or an enum variant, as described by its `#[tl(..)]` attributes.
+struct TlField {
+    kind: FieldKind,
+    /// `#[tl(bare)]`: the field is serialized without its constructor identifier,
+    /// e.g. `%Message` or `vector<future_salt>`.
+    bare: bool,
+}
+
+/// The items of the `#[tl(..)]` attributes.
+fn tl_items(attrs: &[Attribute]) -> Vec<&NestedMetaItem> {
+    attrs
+        .iter()
+        .filter_map(|attr| match attr.value {
+            MetaItem::List(ref name, ref items) if name.as_ref() == "tl" => Some(items),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Check the `#[tl(..)]` attributes of a struct, an enum or an enum variant, returning
+/// the path of the `telegram` crate given by `#[tl(crate = "..")]`, `::telegram` by default.
+fn crate_path(attrs: &[Attribute]) -> Result<quote::Tokens, String> {
+    let mut path = quote! { ::telegram };
+
+    for item in tl_items(attrs) {
+        match *item {
+            NestedMetaItem::MetaItem(MetaItem::NameValue(ref name, Lit::Str(..)))
+                if name.as_ref() == "name" || name.as_ref() == "combinator" => {}
+
+            NestedMetaItem::MetaItem(MetaItem::Word(ref word))
+                if word.as_ref() == "unverified_id" || word.as_ref() == "observed" => {}
+
+            NestedMetaItem::MetaItem(MetaItem::NameValue(
+                ref name,
+                Lit::Str(ref value, StrStyle::Cooked),
+            )) if name.as_ref() == CRATE =>
+            {
+                let value = syn::parse_path(value)
+                    .map_err(|_| format!("invalid crate path `#[tl(crate = {:?})]`", value))?;
+
+                path = quote! { #value };
             }
 
-            quote! {
-                match *self {
-                    #tokens_variants
+            _ => {
+                let item = quote! { #item }.to_string().replace(CRATE, "crate");
+                return Err(format!("unsupported attribute `#[tl({})]`", item));
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+/// Check the `#[tl(..)]` attributes of an enum variant, which are those of a struct but
+/// for `#[tl(crate = "..")]` and `#[tl(observed)]`, given once on the enum.
+fn check_variant(variant: &syn::Variant) -> Result<(), String> {
+    if observed(&variant.attrs) {
+        return Err(format!(
+            "unsupported attribute `#[tl(observed)]` on variant `{}`, \
+             the enum is observed as a whole",
+            variant.ident
+        ));
+    }
+
+    for item in tl_items(&variant.attrs) {
+        if let NestedMetaItem::MetaItem(MetaItem::NameValue(ref name, _)) = *item {
+            if name.as_ref() == CRATE {
+                return Err(format!(
+                    "unsupported attribute `#[tl(crate = ..)]` on variant `{}`, \
+                     the crate path is given on the enum",
+                    variant.ident
+                ));
+            }
+        }
+    }
+
+    crate_path(&variant.attrs).map(|_| ())
+}
+
+/// The identifier of `#[id = 0x..]` (or `#[id = "0x.."]`).
+fn constructor_id(attrs: &[Attribute]) -> Result<Option<u32>, String> {
+    for attr in attrs {
+        if attr.value.name() != "id" {
+            continue;
+        }
+
+        let id = match attr.value {
+            MetaItem::NameValue(_, Lit::Int(value, _)) if value <= u64::from(u32::MAX) => {
+                Some(value as u32)
+            }
+
+            MetaItem::NameValue(_, Lit::Str(ref value, StrStyle::Cooked)) => {
+                value
+                    .strip_prefix("0x")
+                    .and_then(|value| u32::from_str_radix(value, 16).ok())
+            }
+
+            _ => None,
+        };
+
+        return match id {
+            Some(id) => verify_id(attrs, id).map(Some),
+            None => Err(format!(
+                "invalid constructor identifier `#[{}]`, expected e.g. `#[id = 0x1cb5c415]`",
+                {
+                    let value = &attr.value;
+                    quote! { #value }
                 }
+            )),
+        };
+    }
+
+    Ok(None)
+}
+
+/// Check `id` against the CRC32 of the combinator of `#[tl(combinator = "..")]`, if any,
+/// unless it is known not to match with `#[tl(unverified_id)]`.
+fn verify_id(attrs: &[Attribute], id: u32) -> Result<u32, String> {
+    let mut combinator = None;
+
+    for item in tl_items(attrs) {
+        match *item {
+            NestedMetaItem::MetaItem(MetaItem::Word(ref word)) if word.as_ref() == "unverified_id" => {
+                return Ok(id);
+            }
+
+            NestedMetaItem::MetaItem(MetaItem::NameValue(
+                ref name,
+                Lit::Str(ref value, StrStyle::Cooked),
+            )) if name.as_ref() == "combinator" =>
+            {
+                combinator = Some(value);
             }
+
+            _ => {}
         }
+    }
+
+    let combinator = match combinator {
+        Some(combinator) => combinator,
+        None => return Ok(id),
     };
 
-    quote! {
-        impl ::ser::Serialize for #item_name {
-            fn serialize_to(&self, buffer: &mut Vec<u8>) -> ::error::Result<()> {
-                #full_serialize_to_body
+    let expected = crc32(normalize_combinator(combinator).as_bytes());
+
+    if id == expected {
+        Ok(id)
+    } else {
+        Err(format!(
+            "constructor identifier 0x{:08x} differs from 0x{:08x}, the CRC32 of `{}`; \
+             use `#[tl(unverified_id)]` if the schema is known to disagree",
+            id,
+            expected,
+            combinator
+        ))
+    }
+}
+
+/// Normalize a combinator the way its identifier is computed from it: without the
+/// identifier itself, braces and angle brackets, with `bytes` spelled `string`, and
+/// without the `flags.N?true` parameters, which only exist as bits.
+fn normalize_combinator(combinator: &str) -> String {
+    let mut words = Vec::new();
+
+    for (index, word) in combinator.split_whitespace().enumerate() {
+        // `name#1cb5c415`
+        let word = match word.find('#') {
+            Some(hash) if index == 0 => &word[..hash],
+            _ => word,
+        };
+
+        let word = if word.ends_with(":bytes") || word.ends_with("?bytes") {
+            format!("{}string", &word[..word.len() - 5])
+        } else {
+            word.to_string()
+        };
+
+        let is_true_flag = word.ends_with("?true") && word
+            .split(':')
+            .nth(1)
+            .is_some_and(|kind| kind.starts_with("flags"));
+
+        if !is_true_flag {
+            words.push(word.replace('<', " ").replace(['>', '{', '}'], ""));
+        }
+    }
+
+    words.join(" ")
+}
+
+/// CRC32 (IEEE), as used for constructor identifiers.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Whether the type is `#[tl(observed)]`.
+fn observed(attrs: &[Attribute]) -> bool {
+    tl_items(attrs).into_iter().any(|item| match *item {
+        NestedMetaItem::MetaItem(MetaItem::Word(ref word)) => word.as_ref() == "observed",
+        _ => false,
+    })
+}
+
+/// The TL name of `#[tl(name = "..")]`.
+fn tl_name(attrs: &[Attribute]) -> Option<String> {
+    for item in tl_items(attrs) {
+        if let NestedMetaItem::MetaItem(MetaItem::NameValue(
+            ref name,
+            Lit::Str(ref value, StrStyle::Cooked),
+        )) = *item
+        {
+            if name.as_ref() == "name" {
+                return Some(value.clone());
             }
         }
     }
+
+    None
 }
 
-fn impl_serialize_to_body(
-    body_type: BodyType,
-    attrs: &[Attribute],
-    fields: Option<&[Field]>,
-) -> quote::Tokens {
-    let mut id = None;
+/// Read the `#[tl(..)]` attributes of `fields`, resolving the `#` parameter of each
+/// conditional field among the fields preceding it.
+fn tl_fields(fields: &[Field]) -> Result<Vec<TlField>, String> {
+    let mut tl_fields: Vec<TlField> = Vec::new();
+
+    for (index, field) in fields.iter().enumerate() {
+        // Conditional fields refer to `#` parameters by identifier, or by index in tuples
+        let field_name = match field.ident {
+            Some(ref ident) => ident.to_string(),
+            None => index.to_string(),
+        };
+
+        let mut kind = None;
+        let mut bare = false;
+
+        for item in tl_items(&field.attrs) {
+            let item_kind = match *item {
+                NestedMetaItem::MetaItem(MetaItem::Word(ref word)) if word.as_ref() == "flags" => {
+                    FieldKind::Flags
+                }
 
-    for attr in attrs {
-        match attr.value {
-            MetaItem::NameValue(ref name, ref value) => {
-                if name.as_ref() == "id" {
-                    if let Lit::Str(ref value, StrStyle::Cooked) = *value {
-                        // Found an identifier
-                        let value = u32::from_str_radix(&value[2..], 16).unwrap();
-                        id = Some(quote! {
-                            #value.serialize_to(buffer)?;
-                        });
+                NestedMetaItem::MetaItem(MetaItem::Word(ref word)) if word.as_ref() == "skip" => {
+                    FieldKind::Skip
+                }
 
-                        break;
-                    }
+                NestedMetaItem::MetaItem(MetaItem::Word(ref word)) if word.as_ref() == "bare" => {
+                    bare = true;
+                    continue;
+                }
+
+                NestedMetaItem::MetaItem(MetaItem::NameValue(ref name, Lit::Str(..)))
+                    if name.as_ref() == "name" =>
+                {
+                    continue;
+                }
+
+                NestedMetaItem::MetaItem(MetaItem::NameValue(
+                    ref name,
+                    Lit::Str(ref value, StrStyle::Cooked),
+                )) if name.as_ref() == "flag" =>
+                {
+                    flag_kind(fields, &tl_fields, value).map_err(|reason| {
+                        format!("invalid `#[tl(flag = {:?})]` on `{}`: {}", value, field_name, reason)
+                    })?
+                }
+
+                _ => {
+                    return Err(format!(
+                        "unsupported attribute `#[tl({})]` on `{}`",
+                        quote! { #item },
+                        field_name
+                    ));
                 }
+            };
+
+            if kind.is_some() {
+                return Err(format!(
+                    "`{}` can only have one of `#[tl(flags)]`, `#[tl(flag = \"..\")]` and `#[tl(skip)]`",
+                    field_name
+                ));
             }
 
-            _ => {
-                // Do nothing
+            kind = Some(item_kind);
+        }
+
+        let kind = kind.unwrap_or(FieldKind::Plain);
+
+        match kind {
+            FieldKind::Plain => {}
+            _ if bare => {
+                return Err(format!("`#[tl(bare)]` on `{}` only applies to plain fields", field_name));
             }
+            _ => {}
         }
+
+        tl_fields.push(TlField { kind, bare });
     }
 
+    Ok(tl_fields)
+}
+
+/// Parse `flags.N` into the index of the `#` parameter `flags` among `preceding` and `N`.
+fn flag_kind(fields: &[Field], preceding: &[TlField], value: &str) -> Result<FieldKind, String> {
+    let dot = value.find('.').ok_or("expected e.g. `flags.3`")?;
+    let (flags_name, bit) = (&value[..dot], &value[dot + 1..]);
+
+    let bit = match bit.parse::<u32>() {
+        Ok(bit) if bit < 32 => bit,
+        _ => return Err(format!("`{}` is not a bit between 0 and 31", bit)),
+    };
+
+    let index = preceding
+        .iter()
+        .enumerate()
+        .position(|(index, field)| {
+            let name = match fields[index].ident {
+                Some(ref ident) => ident.to_string(),
+                None => index.to_string(),
+            };
+
+            name == flags_name && matches!(field.kind, FieldKind::Flags)
+        })
+        .ok_or_else(|| format!("no preceding `#[tl(flags)]` field `{}`", flags_name))?;
+
+    Ok(FieldKind::Flag(index, bit))
+}
+
+/// The bodies serializing `fields`, whose values are the place expressions `accessors`.
+fn impl_serialize_fields(
+    krate: &quote::Tokens,
+    fields: &[TlField],
+    accessors: &[quote::Tokens],
+) -> SerializeBody {
+    let mut lengths = Vec::new();
     let mut properties = Vec::new();
 
-    if let Some(fields) = fields {
-        for field in fields {
-            if let Some(ref field_name) = field.ident {
-                let property = match body_type {
-                    BodyType::Struct => {
-                        quote! {
-                            self.#field_name.serialize_to(buffer)?;
+    for (index, (field, accessor)) in fields.iter().zip(accessors).enumerate() {
+        let property = match field.kind {
+            FieldKind::Plain if field.bare => {
+                lengths.push(quote! { #krate::ser::SerializeBare::serialized_bare_len(&#accessor) });
+
+                quote! {
+                    #krate::ser::SerializeBare::serialize_bare_to(&#accessor, buffer)?;
+                }
+            }
+
+            FieldKind::Plain => {
+                lengths.push(quote! { #krate::ser::Serialize::serialized_len(&#accessor) });
+
+                quote! {
+                    #krate::ser::Serialize::serialize_to(&#accessor, buffer)?;
+                }
+            }
+
+            FieldKind::Flags => {
+                // Set the bit of every conditional field referring to this one
+                let mut bits = Vec::new();
+
+                for (other, other_accessor) in fields.iter().zip(accessors) {
+                    if let FieldKind::Flag(flags, bit) = other.kind {
+                        if flags == index {
+                            bits.push(quote! {
+                                if #krate::ser::Flag::is_set(&#other_accessor) {
+                                    flags |= 1 << #bit;
+                                }
+                            });
                         }
                     }
+                }
 
-                    BodyType::Enum => {
-                        quote! {
-                            #field_name.serialize_to(buffer)?;
-                        }
+                lengths.push(quote! { 4 });
+
+                quote! {
+                    {
+                        let mut flags = 0u32;
+                        #(#bits)*
+                        #krate::ser::Serialize::serialize_to(&flags, buffer)?;
                     }
-                };
+                }
+            }
 
-                properties.push(property);
+            FieldKind::Flag(..) => {
+                lengths.push(quote! { #krate::ser::Flag::flagged_len(&#accessor) });
+
+                quote! {
+                    #krate::ser::Flag::serialize_flagged_to(&#accessor, buffer)?;
+                }
             }
-        }
-    }
 
-    quote! {
-        // Identifier
-        #id
+            FieldKind::Skip => continue,
+        };
+
+        properties.push(property);
+    }
 
-        // Properties
+    let serialize_to = quote! {
         #(#properties)*
 
         Ok(())
+    };
+
+    let serialized_len = quote! {
+        0 #(+ #lengths)*
+    };
+
+    SerializeBody {
+        serialize_to,
+        serialized_len,
+    }
+}
+
+fn impl_deserialize(ast: &syn::DeriveInput) -> Result<quote::Tokens, String> {
+    let item_name = &ast.ident;
+
+    let krate = crate_path(&ast.attrs)?;
+    let name = tl_name(&ast.attrs).unwrap_or_else(|| item_name.to_string());
+
+    // Deserialized values may borrow from the input for the lifetime of the item, if any
+    let mut generics = ast.generics.clone();
+    let lifetime = match generics.lifetimes.first() {
+        Some(def) => def.lifetime.clone(),
+        None => {
+            generics.lifetimes.insert(0, syn::LifetimeDef::new("'de"));
+            generics.lifetimes[0].lifetime.clone()
+        }
+    };
+
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+    let where_clause = where_clause(&ast.generics, &quote! { #krate::de::Deserialize<#lifetime> });
+
+    let read_id = quote! {
+        <u32 as #krate::de::Deserialize>::deserialize_from(reader)?
+    };
+
+    let unexpected = quote! {
+        id => Err(#krate::de::unexpected_constructor(#name, id)),
+    };
+
+    // Values of `#[tl(observed)]` types are passed on once read, bare ones excepted
+    let observe = |body: quote::Tokens| {
+        if observed(&ast.attrs) {
+            quote! {
+                let value = #body?;
+                #krate::de::Observed::observed(&value, reader);
+                Ok(value)
+            }
+        } else {
+            body
+        }
+    };
+
+    match ast.body {
+        Body::Struct(ref data) => {
+            let path = quote! { #item_name };
+            let bare_body = impl_deserialize_fields(&krate, &path, data)?;
+
+            let body = match constructor_id(&ast.attrs)? {
+                Some(id) => quote! {
+                    match #read_id {
+                        #id => #krate::de::DeserializeBare::deserialize_bare_from(reader),
+                        #unexpected
+                    }
+                },
+
+                None => quote! {
+                    #krate::de::DeserializeBare::deserialize_bare_from(reader)
+                },
+            };
+            let body = observe(body);
+
+            Ok(quote! {
+                impl #impl_generics #krate::de::Deserialize<#lifetime> for #item_name #ty_generics #where_clause {
+                    fn deserialize_from(reader: &mut #krate::de::Deserializer<#lifetime>) -> #krate::error::Result<Self> {
+                        #body
+                    }
+                }
+
+                impl #impl_generics #krate::de::DeserializeBare<#lifetime> for #item_name #ty_generics #where_clause {
+                    fn deserialize_bare_from(reader: &mut #krate::de::Deserializer<#lifetime>) -> #krate::error::Result<Self> {
+                        #bare_body
+                    }
+                }
+            })
+        }
+
+        Body::Enum(ref variants) => {
+            let mut arms = Vec::new();
+
+            for variant in variants {
+                let variant_name = &variant.ident;
+                let path = quote! { #item_name::#variant_name };
+
+                check_variant(variant)?;
+
+                match (constructor_id(&variant.attrs)?, &variant.data) {
+                    (Some(id), data) => {
+                        let body = impl_deserialize_fields(&krate, &path, data)?;
+                        arms.push(quote! { #id => { #body } });
+                    }
+
+                    (None, VariantData::Tuple(fields)) if fields.len() == 1 => {
+                        // The wrapped constructor holds the identifier
+                        let ty = &fields[0].ty;
+
+                        arms.push(quote! {
+                            id if id == <#ty as #krate::TlConstructor>::CONSTRUCTOR_ID => Ok(#path(
+                                #krate::de::DeserializeBare::deserialize_bare_from(reader)?
+                            )),
+                        });
+                    }
+
+                    _ => {
+                        return Err(format!(
+                            "variant `{}::{}` needs a constructor identifier `#[id = 0x..]`, \
+                             or to wrap a single constructor",
+                            item_name,
+                            variant_name
+                        ));
+                    }
+                }
+            }
+
+            let body = observe(quote! {
+                match #read_id {
+                    #(#arms)*
+                    #unexpected
+                }
+            });
+
+            Ok(quote! {
+                impl #impl_generics #krate::de::Deserialize<#lifetime> for #item_name #ty_generics #where_clause {
+                    fn deserialize_from(reader: &mut #krate::de::Deserializer<#lifetime>) -> #krate::error::Result<Self> {
+                        #body
+                    }
+                }
+            })
+        }
+    }
+}
+
+/// The body deserializing the fields of a struct or an enum variant named by `path`,
+/// following its identifier.
+fn impl_deserialize_fields(
+    krate: &quote::Tokens,
+    path: &quote::Tokens,
+    data: &VariantData,
+) -> Result<quote::Tokens, String> {
+    let fields = tl_fields(data.fields())?;
+
+    // Fields are read in order into `field0`, `field1`, ..., so each `#` parameter
+    // (kept in `flags0`, ...) is read before the fields depending on it
+    let mut reads = Vec::new();
+
+    for (index, field) in fields.iter().enumerate() {
+        let local = syn::Ident::new(format!("field{}", index));
+
+        let read = match field.kind {
+            FieldKind::Plain if field.bare => quote! {
+                let #local = #krate::de::DeserializeBare::deserialize_bare_from(reader)?;
+            },
+
+            FieldKind::Plain => quote! {
+                let #local = #krate::de::Deserialize::deserialize_from(reader)?;
+            },
+
+            FieldKind::Flags => {
+                let flags = syn::Ident::new(format!("flags{}", index));
+
+                quote! {
+                    let #flags = <u32 as #krate::de::Deserialize>::deserialize_from(reader)?;
+                    let #local = #krate::ser::Flags;
+                }
+            }
+
+            FieldKind::Flag(flags, bit) => {
+                let flags = syn::Ident::new(format!("flags{}", flags));
+
+                quote! {
+                    let #local = #krate::de::DeserializeFlag::deserialize_flagged_from(
+                        #flags & (1u32 << #bit) != 0,
+                        reader,
+                    )?;
+                }
+            }
+
+            FieldKind::Skip => quote! {
+                let #local = ::std::default::Default::default();
+            },
+        };
+
+        reads.push(read);
     }
+
+    let locals = (0..fields.len()).map(|index| syn::Ident::new(format!("field{}", index)));
+
+    let value = match *data {
+        VariantData::Struct(ref fields) => {
+            let idents = fields.iter().map(|field| &field.ident);
+
+            quote! { #path { #(#idents: #locals),* } }
+        }
+
+        VariantData::Tuple(_) => quote! { #path(#(#locals),*) },
+
+        VariantData::Unit => quote! { #path },
+    };
+
+    Ok(quote! {
+        #(#reads)*
+
+        Ok(#value)
+    })
 }